@@ -25,6 +25,8 @@ export type Scalars = {
 	 * The input/output is a string in RFC3339 format.
 	 */
 	DateTime: { input: any; output: any };
+	/** A scalar that can represent any JSON value. */
+	JSON: { input: any; output: any };
 	/** A scalar that can represent any JSON Object value. */
 	JSONObject: { input: any; output: any };
 	/**
@@ -40,6 +42,43 @@ export type Scalars = {
 	UUID: { input: string; output: string };
 };
 
+/** What to find the best mix for */
+export type BestMixInput = {
+	/** Altitude in meters, defaulting to sea level */
+	altitude?: InputMaybe<Scalars['Float']['input']>;
+	depth: Scalars['Float']['input'];
+	/** The deepest equivalent narcotic depth allowed, defaulting to 30m */
+	maxEnd?: InputMaybe<Scalars['Float']['input']>;
+	/** The highest ppO2 allowed at `depth`, defaulting to 1.4 bar */
+	maxPpo2?: InputMaybe<Scalars['Float']['input']>;
+	/** Whether oxygen counts as narcotic, defaulting to true */
+	oxygenNarcotic?: InputMaybe<Scalars['Boolean']['input']>;
+	/** Water density in kg/ℓ, defaulting to 10m being 1 bar */
+	salinity?: InputMaybe<Scalars['Float']['input']>;
+};
+
+export type BlendStep = {
+	__typename?: 'BlendStep';
+	/** The bar of this gas to add */
+	added: Scalars['Float']['output'];
+	gas: GasOutput;
+	/** The pressure the cylinder is filled to after this step */
+	pressure: Scalars['Float']['output'];
+};
+
+/** How far a camera's clock is out, as the seconds to add to it to get the real time */
+export type CameraOffset = {
+	__typename?: 'CameraOffset';
+	camera: Scalars['String']['output'];
+	clockOffset: Scalars['Int']['output'];
+};
+
+/** How far a camera's clock is out, as the seconds to add to it to get the real time */
+export type CameraOffsetInput = {
+	camera: Scalars['String']['input'];
+	clockOffset: Scalars['Int']['input'];
+};
+
 export type Category = {
 	__typename?: 'Category';
 	id: Scalars['UUID']['output'];
@@ -54,6 +93,15 @@ export type CategoryValue = {
 	value: Scalars['String']['output'];
 };
 
+/** A closed circuit rebreather held at a constant setpoint */
+export type ClosedCircuitInput = {
+	/** The ppO2 of the loop in bar during the final ascent, defaulting to the setpoint */
+	decoSetpoint?: InputMaybe<Scalars['Float']['input']>;
+	diluent: GasInput;
+	/** The ppO2 of the loop in bar */
+	setpoint: Scalars['Float']['input'];
+};
+
 export type CreateCategory = {
 	id?: InputMaybe<Scalars['UUID']['input']>;
 	name: Scalars['String']['input'];
@@ -126,12 +174,35 @@ export type CreateSealife = {
 	scientificName?: InputMaybe<Scalars['String']['input']>;
 };
 
+export enum DeviationType {
+	Deeper = 'DEEPER',
+	FastAscent = 'FAST_ASCENT',
+	LongerBottomTime = 'LONGER_BOTTOM_TIME',
+	MissedStop = 'MISSED_STOP',
+	ShortenedStop = 'SHORTENED_STOP'
+}
+
 export enum Difficulty {
 	Aow = 'AOW',
 	Ow = 'OW',
 	Tech = 'TECH'
 }
 
+/** The planned use of a cylinder, with pressures in bar and volumes in litres */
+export type CylinderUsage = {
+	__typename?: 'CylinderUsage';
+	belowReserve: Scalars['Boolean']['output'];
+	endPressure: Scalars['Float']['output'];
+	gas: GasOutput;
+	litresUsed: Scalars['Float']['output'];
+	/** The minimum gas needed to get two divers from the deepest point this gas is breathed to the surface */
+	rockBottom: Scalars['Float']['output'];
+	size: Scalars['Float']['output'];
+	startPressure: Scalars['Float']['output'];
+	/** The pressure to turn the dive at when following the rule of thirds */
+	turnPressure: Scalars['Float']['output'];
+};
+
 export type Dive = {
 	__typename?: 'Dive';
 	comments: Array<DiveComment>;
@@ -148,6 +219,8 @@ export type Dive = {
 	likes: Scalars['Int']['output'];
 	numComments: Scalars['Int']['output'];
 	number: Scalars['Int']['output'];
+	/** The saved plans this dive was executed as, which only the diver can see */
+	plans: Array<SavedDivePlan>;
 	published: Scalars['Boolean']['output'];
 	summary: Scalars['String']['output'];
 	user: PublicUserInfo;
@@ -165,22 +238,66 @@ export type DiveComment = {
 	userId: Scalars['UUID']['output'];
 };
 
+/** How a logged dive went compared to a plan for it */
+export type DivePlanComparison = {
+	__typename?: 'DivePlanComparison';
+	/** The logged profile, with the planned profile overlaid */
+	chart: Scalars['String']['output'];
+	deviations: Array<PlanDeviation>;
+	schedule: DiveSchedule;
+};
+
 export type DivePlanInput = {
+	/** Altitude of the dive site in meters, defaulting to sea level */
+	altitude?: InputMaybe<Scalars['Float']['input']>;
+	/** Ascent rate in m/min, defaulting to 10 m/min */
+	ascentRate?: InputMaybe<Scalars['Float']['input']>;
 	backGas: GasInput;
+	/** Plans the dive on a closed circuit rebreather.  The back gas & deco gasses are then the open circuit bailout */
+	closedCircuit?: InputMaybe<ClosedCircuitInput>;
 	decoGasses: Array<GasInput>;
+	/** The highest ppO2 allowed for deco gasses, defaulting to 1.6 bar */
+	decoMaxPpo2?: InputMaybe<Scalars['Float']['input']>;
 	depth: Scalars['Float']['input'];
+	/** Descent rate in m/min, defaulting to 18 m/min */
+	descentRate?: InputMaybe<Scalars['Float']['input']>;
 	gfHigh: Scalars['Int']['input'];
 	gfLow: Scalars['Int']['input'];
+	/** Depth of the last deco stop in meters, defaulting to 3m */
+	lastStop?: InputMaybe<Scalars['Float']['input']>;
+	/** The highest ppO2 allowed for the gasses breathed at each level, defaulting to 1.4 bar */
+	maxPpo2?: InputMaybe<Scalars['Float']['input']>;
+	/** Surface air consumption in litres/min, used to work out the gas needed for each stage */
+	sacRate?: InputMaybe<Scalars['Float']['input']>;
+	/** Water density in kg/ℓ, i.e, `1.0` for fresh & `1.03` for salt water.  Defaults to 10m being 1 bar */
+	salinity?: InputMaybe<Scalars['Float']['input']>;
+	segments?: InputMaybe<Array<PlanSegmentInput>>;
+	/** Minutes since the end of the diver's last logged dive.  When provided, the dives logged in the day before it are replayed first, so the plan accounts for residual inert gas */
+	surfaceInterval?: InputMaybe<Scalars['Int']['input']>;
 	time: Scalars['Int']['input'];
 };
 
 export type DiveSchedule = {
 	__typename?: 'DiveSchedule';
+	/** For closed circuit plans, the open circuit ascent from the point of the dive that takes longest to surface */
+	bailout?: Maybe<DiveSchedule>;
+	/** Whether any cylinder ends the dive below its rock bottom reserve */
+	belowReserve: Scalars['Boolean']['output'];
 	bigChart: Scalars['String']['output'];
+	/** Minutes from the start of the dive until the final ascent */
+	bottomTime: Scalars['Int']['output'];
+	/** The CNS oxygen toxicity percentage at the end of the dive */
+	cns: Scalars['Float']['output'];
+	/** The gas used from each cylinder, when a SAC rate is provided */
+	cylinders: Array<CylinderUsage>;
+	/** The oxygen tolerance units accumulated over the dive */
+	otu: Scalars['Float']['output'];
 	runtime: Scalars['Int']['output'];
 	smallChart: Scalars['String']['output'];
 	stages: Array<DiveStage>;
 	tts: Scalars['Int']['output'];
+	/** Problems with the gasses breathed, such as a deco gas beyond its MOD or a hypoxic mix used shallow */
+	warnings: Array<Scalars['String']['output']>;
 };
 
 export type DiveSite = {
@@ -208,8 +325,16 @@ export type DiveSite = {
 
 export type DiveStage = {
 	__typename?: 'DiveStage';
+	/** The CNS oxygen toxicity percentage at the end of this stage */
+	cns: Scalars['Float']['output'];
 	depth: Scalars['Float']['output'];
 	gas?: Maybe<GasOutput>;
+	/** Litres of gas used during this stage, when a SAC rate is provided */
+	gasUsed?: Maybe<Scalars['Float']['output']>;
+	/** The oxygen tolerance units accumulated by the end of this stage */
+	otu: Scalars['Float']['output'];
+	/** The loop setpoint in bar, when on a closed circuit rebreather */
+	setpoint?: Maybe<Scalars['Float']['output']>;
 	stageType: StageType;
 	time: Scalars['Int']['output'];
 };
@@ -223,10 +348,33 @@ export type Feedback = {
 	userId: Scalars['UUID']['output'];
 };
 
+/** How to blend a cylinder, treating the gasses as ideal */
+export type GasBlend = {
+	__typename?: 'GasBlend';
+	/** The pressure to drain the cylinder down to before blending.  This is the current pressure when nothing needs draining */
+	drainTo: Scalars['Float']['output'];
+	/** The gasses to add, in order */
+	steps: Array<BlendStep>;
+};
+
+/** A cylinder to partial pressure blend, with pressures in bar */
+export type GasBlendInput = {
+	/** What's in the cylinder now */
+	current: GasInput;
+	currentPressure: Scalars['Float']['input'];
+	target: GasInput;
+	targetPressure: Scalars['Float']['input'];
+	/** The gas the cylinder is topped up with after the helium & oxygen, defaulting to air */
+	topUp?: InputMaybe<GasInput>;
+};
+
 export type GasInput = {
 	he: Scalars['Float']['input'];
+	/** The size of the cylinder in litres */
 	litres: Scalars['Float']['input'];
 	o2: Scalars['Float']['input'];
+	/** The pressure the cylinder is filled to in bar, defaulting to 200 bar */
+	workingPressure?: InputMaybe<Scalars['Float']['input']>;
 };
 
 export type GasOutput = {
@@ -255,6 +403,8 @@ export type Mutation = {
 	addFeedback: Feedback;
 	changePassword: Scalars['Boolean']['output'];
 	checkReference: OgReference;
+	/** Plans a dive & compares it against a logged dive */
+	compareDivePlan: DivePlanComparison;
 	deleteUser: Scalars['Boolean']['output'];
 	fbLogin: LoginResponse;
 	fbRegisterUser: LoginResponse;
@@ -278,15 +428,28 @@ export type Mutation = {
 	removeCategoryValue: Scalars['Boolean']['output'];
 	removeComment: Scalars['Boolean']['output'];
 	removeDive: Scalars['Boolean']['output'];
+	removeDivePlan: Scalars['Boolean']['output'];
 	removeDiveSite: Scalars['Boolean']['output'];
 	removePhoto: Scalars['Boolean']['output'];
 	removeReference: Scalars['Boolean']['output'];
 	removeRegion: Scalars['Boolean']['output'];
 	removeSealife: Scalars['Boolean']['output'];
+	/**
+	 * Corrects the dates of the logged in user's photos by `clockOffset` seconds, or their camera's saved offset, and links them to the dives they were taken on.
+	 *
+	 * Matches the given photos or those from `camera`, or all of them when neither is given.  A `clockOffset` needs one of these, as it would otherwise apply to every camera.
+	 * Photos that aren't taken during a dive keep the dive they're linked to.  Returns how many photos are linked to a dive
+	 */
+	rematchPhotos: Scalars['Int']['output'];
 	requestResetToken: Scalars['Boolean']['output'];
 	resendVerification: Scalars['Boolean']['output'];
 	resetPassword: LoginResponse;
-	syncSubsurface: Scalars['Boolean']['output'];
+	saveDivePlan: SavedDivePlan;
+	/** Saves how far a camera's clock is out, used for photos uploaded from it afterwards */
+	setCameraOffset: CameraOffset;
+	syncSubsurface: SyncJob;
+	/** Tags the species in a photo, replacing any tagged before */
+	tagSealife: Array<SealifeTag>;
 	unlikeDive: Scalars['Boolean']['output'];
 	unlikePhoto: Scalars['Boolean']['output'];
 	updatePhoto: Photo;
@@ -307,6 +470,11 @@ export type MutationCheckReferenceArgs = {
 	url: Scalars['String']['input'];
 };
 
+export type MutationCompareDivePlanArgs = {
+	diveId: Scalars['UUID']['input'];
+	plan: DivePlanInput;
+};
+
 export type MutationDeleteUserArgs = {
 	password: Scalars['String']['input'];
 };
@@ -405,6 +573,10 @@ export type MutationRemoveDiveArgs = {
 	id: Scalars['UUID']['input'];
 };
 
+export type MutationRemoveDivePlanArgs = {
+	id: Scalars['UUID']['input'];
+};
+
 export type MutationRemoveDiveSiteArgs = {
 	id: Scalars['UUID']['input'];
 };
@@ -425,6 +597,12 @@ export type MutationRemoveSealifeArgs = {
 	id: Scalars['UUID']['input'];
 };
 
+export type MutationRematchPhotosArgs = {
+	camera?: InputMaybe<Scalars['String']['input']>;
+	clockOffset?: InputMaybe<Scalars['Int']['input']>;
+	photoIds?: InputMaybe<Array<Scalars['UUID']['input']>>;
+};
+
 export type MutationRequestResetTokenArgs = {
 	email: Scalars['String']['input'];
 };
@@ -435,11 +613,24 @@ export type MutationResetPasswordArgs = {
 	token: Scalars['UUID']['input'];
 };
 
+export type MutationSaveDivePlanArgs = {
+	plan: SaveDivePlan;
+};
+
+export type MutationSetCameraOffsetArgs = {
+	offset: CameraOffsetInput;
+};
+
 export type MutationSyncSubsurfaceArgs = {
 	email: Scalars['String']['input'];
 	password: Scalars['String']['input'];
 };
 
+export type MutationTagSealifeArgs = {
+	photoId: Scalars['UUID']['input'];
+	tags: Array<SealifeTagInput>;
+};
+
 export type MutationUnlikeDiveArgs = {
 	diveId: Scalars['UUID']['input'];
 };
@@ -484,21 +675,50 @@ export enum OverlayLocation {
 
 export type Photo = {
 	__typename?: 'Photo';
+	/** The camera the photo was taken with, which is only shown to the photographer as it can include a serial number */
+	camera?: Maybe<Scalars['String']['output']>;
+	/** The seconds added to the camera's clock to get the date */
+	cameraOffset: Scalars['Int']['output'];
 	date?: Maybe<Scalars['DateTime']['output']>;
 	dive?: Maybe<Dive>;
 	diveSite?: Maybe<DiveSite>;
 	filename: Scalars['String']['output'];
 	height: Scalars['Float']['output'];
 	id: Scalars['UUID']['output'];
+	/** The latitude the photo was taken at */
+	lat?: Maybe<Scalars['Float']['output']>;
 	liked: Scalars['Boolean']['output'];
 	likes: Scalars['Int']['output'];
+	/** The longitude the photo was taken at */
+	lon?: Maybe<Scalars['Float']['output']>;
 	sealife?: Maybe<Sealife>;
+	/** Every species tagged in the photo, with where they are in it */
+	sealifeTags: Array<SealifeTag>;
 	size: Scalars['Float']['output'];
 	user: PublicUserInfo;
 	userId: Scalars['UUID']['output'];
 	width: Scalars['Float']['output'];
 };
 
+/** Where a dive strayed from its plan.  `planned` & `actual` are in meters for depths, minutes for times & m/min for ascent rates */
+export type PlanDeviation = {
+	__typename?: 'PlanDeviation';
+	actual: Scalars['Float']['output'];
+	depth: Scalars['Float']['output'];
+	deviationType: DeviationType;
+	planned: Scalars['Float']['output'];
+	/** Seconds into the dive */
+	time: Scalars['Int']['output'];
+};
+
+/** A level of a dive plan, travelled to from the previous level */
+export type PlanSegmentInput = {
+	depth: Scalars['Float']['input'];
+	/** The gas breathed at this level, defaulting to the back gas */
+	gas?: InputMaybe<GasInput>;
+	time: Scalars['Int']['input'];
+};
+
 export type PublicUserInfo = {
 	__typename?: 'PublicUserInfo';
 	description: Scalars['String']['output'];
@@ -515,14 +735,28 @@ export type PublicUserInfo = {
 
 export type Query = {
 	__typename?: 'Query';
+	/** The mix with the most oxygen for a depth, with just enough helium to keep it within the narcotic depth */
+	bestMix: GasOutput;
+	/** Partial pressure blending steps to get from a cylinder's current fill to a target mix */
+	blendGas: GasBlend;
+	/** The cameras the logged in user has taken photos with, and how far their clocks are out */
+	cameraOffsets: Array<CameraOffset>;
 	categories: Array<Category>;
 	categoryValues: Array<CategoryValue>;
 	currentUser?: Maybe<LoginResponse>;
 	disableEmailLogin: Scalars['Boolean']['output'];
+	/** A saved dive plan, which doesn't need a login so plans can be shared */
+	divePlan?: Maybe<SavedDivePlan>;
+	/** The logged in user's saved dive plans */
+	divePlans: Array<SavedDivePlan>;
 	diveSites: Array<DiveSite>;
 	dives: Array<Dive>;
+	/** The depth on air that's as narcotic as breathing a gas at `depth`.  Oxygen is counted as narcotic unless `oxygenNarcotic` is false */
+	equivalentNarcoticDepth: Scalars['Float']['output'];
 	fbAppId: Scalars['String']['output'];
 	feedback: Array<Feedback>;
+	/** The deepest a gas can be breathed without going over `maxPpo2`, defaulting to 1.4 bar */
+	maxOperatingDepth: Scalars['Float']['output'];
 	openidIssuerName?: Maybe<Scalars['String']['output']>;
 	photos: Array<Photo>;
 	popularDiveSites: Array<DiveSite>;
@@ -534,6 +768,18 @@ export type Query = {
 	user: PublicUserInfo;
 };
 
+export type QueryBestMixArgs = {
+	mix: BestMixInput;
+};
+
+export type QueryBlendGasArgs = {
+	blend: GasBlendInput;
+};
+
+export type QueryDivePlanArgs = {
+	id: Scalars['UUID']['input'];
+};
+
 export type QueryDiveSitesArgs = {
 	id?: InputMaybe<Scalars['UUID']['input']>;
 	maxDepth?: InputMaybe<Scalars['Float']['input']>;
@@ -550,10 +796,25 @@ export type QueryDivesArgs = {
 	username?: InputMaybe<Scalars['String']['input']>;
 };
 
+export type QueryEquivalentNarcoticDepthArgs = {
+	altitude?: InputMaybe<Scalars['Float']['input']>;
+	depth: Scalars['Float']['input'];
+	gas: GasInput;
+	oxygenNarcotic?: InputMaybe<Scalars['Boolean']['input']>;
+	salinity?: InputMaybe<Scalars['Float']['input']>;
+};
+
 export type QueryFeedbackArgs = {
 	id?: InputMaybe<Scalars['UUID']['input']>;
 };
 
+export type QueryMaxOperatingDepthArgs = {
+	altitude?: InputMaybe<Scalars['Float']['input']>;
+	gas: GasInput;
+	maxPpo2?: InputMaybe<Scalars['Float']['input']>;
+	salinity?: InputMaybe<Scalars['Float']['input']>;
+};
+
 export type QueryPhotosArgs = {
 	dive?: InputMaybe<Scalars['UUID']['input']>;
 	diveSite?: InputMaybe<Scalars['UUID']['input']>;
@@ -593,6 +854,36 @@ export type Region = {
 	slug: Scalars['String']['output'];
 };
 
+export type SaveDivePlan = {
+	/** The dive this plan was executed as */
+	diveId?: InputMaybe<Scalars['UUID']['input']>;
+	/** The plan to update, or a new plan when not provided */
+	id?: InputMaybe<Scalars['UUID']['input']>;
+	name: Scalars['String']['input'];
+	notes: Scalars['String']['input'];
+	plan: DivePlanInput;
+};
+
+export type SavedDivePlan = {
+	__typename?: 'SavedDivePlan';
+	/** How the linked dive went compared to this plan */
+	comparison?: Maybe<DivePlanComparison>;
+	created: Scalars['DateTime']['output'];
+	/** The dive this plan was executed as */
+	dive?: Maybe<Dive>;
+	diveId?: Maybe<Scalars['UUID']['output']>;
+	id: Scalars['UUID']['output'];
+	name: Scalars['String']['output'];
+	notes: Scalars['String']['output'];
+	/** The `DivePlanInput` the plan was saved with */
+	plan: Scalars['JSON']['output'];
+	/** The schedule for the plan, replanned with the current planner */
+	schedule: DiveSchedule;
+	updated: Scalars['DateTime']['output'];
+	url: Scalars['String']['output'];
+	userId: Scalars['UUID']['output'];
+};
+
 export type Sealife = {
 	__typename?: 'Sealife';
 	categoryMap: Scalars['JSONObject']['output'];
@@ -610,6 +901,28 @@ export type Sealife = {
 	summary: Scalars['String']['output'];
 };
 
+/** A species tagged in a photo, boxed by fractions of the photo's width (`u`) & height (`v`) from the top left.  The box is all zeros when the species isn't boxed */
+export type SealifeTag = {
+	__typename?: 'SealifeTag';
+	/** The cropped thumbnail is at `/api/photos/crop/{photoId}/{sealifeId}/{cropVersion}` */
+	cropVersion: Scalars['String']['output'];
+	sealife: Sealife;
+	sealifeId: Scalars['UUID']['output'];
+	uMax: Scalars['Float']['output'];
+	uMin: Scalars['Float']['output'];
+	vMax: Scalars['Float']['output'];
+	vMin: Scalars['Float']['output'];
+};
+
+/** A species to tag in a photo, boxed by fractions of the photo's size from the top left.  Leaving out the box tags the whole photo */
+export type SealifeTagInput = {
+	sealifeId: Scalars['UUID']['input'];
+	uMax?: InputMaybe<Scalars['Float']['input']>;
+	uMin?: InputMaybe<Scalars['Float']['input']>;
+	vMax?: InputMaybe<Scalars['Float']['input']>;
+	vMin?: InputMaybe<Scalars['Float']['input']>;
+};
+
 export type SearchResult = {
 	__typename?: 'SearchResult';
 	id: Scalars['String']['output'];
@@ -639,6 +952,31 @@ export enum StageType {
 	Stay = 'STAY'
 }
 
+/** A background subsurface sync.  Credentials are never stored, only the progress of the job */
+export type SyncJob = {
+	__typename?: 'SyncJob';
+	created: Scalars['DateTime']['output'];
+	divesCreated: Scalars['Int']['output'];
+	divesSkipped: Scalars['Int']['output'];
+	divesUpdated: Scalars['Int']['output'];
+	error?: Maybe<Scalars['String']['output']>;
+	id: Scalars['UUID']['output'];
+	sitesCreated: Scalars['Int']['output'];
+	sitesSkipped: Scalars['Int']['output'];
+	state: SyncState;
+	updated: Scalars['DateTime']['output'];
+	userId: Scalars['UUID']['output'];
+};
+
+export enum SyncState {
+	Cloning = 'CLONING',
+	Done = 'DONE',
+	Failed = 'FAILED',
+	Importing = 'IMPORTING',
+	Parsing = 'PARSING',
+	Queued = 'QUEUED'
+}
+
 export type UserInfo = {
 	__typename?: 'UserInfo';
 	description: Scalars['String']['output'];
@@ -2166,7 +2504,10 @@ export type SyncSubsurfaceMutationVariables = Exact<{
 	password: Scalars['String']['input'];
 }>;
 
-export type SyncSubsurfaceMutation = { __typename?: 'Mutation'; syncSubsurface: boolean };
+export type SyncSubsurfaceMutation = {
+	__typename?: 'Mutation';
+	syncSubsurface: { __typename?: 'SyncJob'; id: string; state: SyncState };
+};
 
 export type UpdatePhotoMutationVariables = Exact<{
 	photo: CreatePhoto;
@@ -2278,6 +2619,35 @@ export type GetCurrentUserQuery = {
 	} | null;
 };
 
+export type DivePlanQueryVariables = Exact<{
+	id: Scalars['UUID']['input'];
+}>;
+
+export type DivePlanQuery = {
+	__typename?: 'Query';
+	divePlan?: {
+		__typename?: 'SavedDivePlan';
+		id: string;
+		name: string;
+		notes: string;
+		url: string;
+		schedule: {
+			__typename?: 'DiveSchedule';
+			runtime: number;
+			tts: number;
+			smallChart: string;
+			bigChart: string;
+			stages: Array<{
+				__typename?: 'DiveStage';
+				stageType: StageType;
+				time: number;
+				depth: number;
+				gas?: { __typename?: 'GasOutput'; o2: number; he: number } | null;
+			}>;
+		};
+	} | null;
+};
+
 export type GetFeedbackQueryVariables = Exact<{
 	id?: InputMaybe<Scalars['UUID']['input']>;
 }>;
@@ -3183,6 +3553,23 @@ export type SearchQuery = {
 	}>;
 };
 
+export type SyncJobsQueryVariables = Exact<{
+	id?: InputMaybe<Scalars['UUID']['input']>;
+}>;
+
+export type SyncJobsQuery = {
+	__typename?: 'Query';
+	syncJobs: Array<{
+		__typename?: 'SyncJob';
+		id: string;
+		state: SyncState;
+		sitesCreated: number;
+		divesCreated: number;
+		divesUpdated: number;
+		error?: string | null;
+	}>;
+};
+
 export const CategoryValueNodeFragmentDoc = gql`
 	fragment CategoryValueNode on CategoryValue {
 		id
@@ -3753,7 +4140,10 @@ export const ResendVerificationDocument = gql`
 `;
 export const SyncSubsurfaceDocument = gql`
 	mutation syncSubsurface($email: String!, $password: String!) {
-		syncSubsurface(email: $email, password: $password)
+		syncSubsurface(email: $email, password: $password) {
+			id
+			state
+		}
 	}
 `;
 export const UpdatePhotoDocument = gql`
@@ -3801,6 +4191,22 @@ export const GetCurrentUserDocument = gql`
 	}
 	${CurrentUserFragmentDoc}
 `;
+export const DivePlanDocument = gql`
+	query divePlan($id: UUID!) {
+		divePlan(id: $id) {
+			id
+			name
+			notes
+			url
+			schedule {
+				...DiveScheduleNode
+			}
+		}
+	}
+	${DiveScheduleNodeFragmentDoc}
+	${DiveStageNodeFragmentDoc}
+	${GasOutputNodeFragmentDoc}
+`;
 export const GetFeedbackDocument = gql`
 	query getFeedback($id: UUID) {
 		feedback(id: $id) {
@@ -3989,6 +4395,18 @@ export const SearchDocument = gql`
 	}
 	${SearchResultNodeFragmentDoc}
 `;
+export const SyncJobsDocument = gql`
+	query syncJobs($id: UUID) {
+		syncJobs(id: $id) {
+			id
+			state
+			sitesCreated
+			divesCreated
+			divesUpdated
+			error
+		}
+	}
+`;
 
 export type SdkFunctionWrapper = <T>(
 	action: (requestHeaders?: Record<string, string>) => Promise<T>,
@@ -4686,6 +5104,24 @@ export function getSdk(client: GraphQLClient, withWrapper: SdkFunctionWrapper =
 				variables
 			);
 		},
+		divePlan(
+			variables: DivePlanQueryVariables,
+			requestHeaders?: GraphQLClientRequestHeaders,
+			signal?: RequestInit['signal']
+		): Promise<DivePlanQuery> {
+			return withWrapper(
+				(wrappedRequestHeaders) =>
+					client.request<DivePlanQuery>({
+						document: DivePlanDocument,
+						variables,
+						requestHeaders: { ...requestHeaders, ...wrappedRequestHeaders },
+						signal
+					}),
+				'divePlan',
+				'query',
+				variables
+			);
+		},
 		getFeedback(
 			variables?: GetFeedbackQueryVariables,
 			requestHeaders?: GraphQLClientRequestHeaders,
@@ -4919,6 +5355,24 @@ export function getSdk(client: GraphQLClient, withWrapper: SdkFunctionWrapper =
 				'query',
 				variables
 			);
+		},
+		syncJobs(
+			variables?: SyncJobsQueryVariables,
+			requestHeaders?: GraphQLClientRequestHeaders,
+			signal?: RequestInit['signal']
+		): Promise<SyncJobsQuery> {
+			return withWrapper(
+				(wrappedRequestHeaders) =>
+					client.request<SyncJobsQuery>({
+						document: SyncJobsDocument,
+						variables,
+						requestHeaders: { ...requestHeaders, ...wrappedRequestHeaders },
+						signal
+					}),
+				'syncJobs',
+				'query',
+				variables
+			);
 		}
 	};
 }
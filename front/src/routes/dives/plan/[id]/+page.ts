@@ -0,0 +1,14 @@
+import { client } from '$lib/graphql/client';
+import type { PageLoad } from './$types';
+
+export const load: PageLoad = async ({ params }) => {
+	try {
+		let response = await client.divePlan({ id: params.id });
+
+		return {
+			plan: response.divePlan
+		};
+	} catch (error) {
+		return {};
+	}
+};
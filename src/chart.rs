@@ -3,13 +3,14 @@ use std::io::Cursor;
 use crate::{
     graphql::WebContext,
     photos::{get_font_width, FONT, WATERMARK},
-    schema::{DiveMetric, DiveSiteQuery},
+    sac::gas_consumption,
+    schema::{ConsumptionSegment, DiveMetric, DiveSiteQuery, GasConsumption},
 };
 use actix_web::{
     error::{ErrorInternalServerError, ErrorNotFound},
     web, Error as ActixError, HttpResponse,
 };
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use askama::Template;
 use dive_deco::{BuhlmannConfig, BuhlmannModel, DecoModel, Depth, Gas, Time};
 use image::{imageops::overlay, ImageOutputFormat, Rgba, RgbaImage};
@@ -32,6 +33,74 @@ pub struct ChartRequest {
     width: Option<f64>,
     #[serde(default)]
     height: Option<f64>,
+    /// Which dive computer to chart, defaulting to the primary one
+    #[serde(default)]
+    computer: Option<i32>,
+    /// Overlays the profiles from the other dive computers
+    #[serde(default)]
+    overlay: bool,
+    /// Shows the gas consumption over the dive, when there is pressure data
+    #[serde(default)]
+    consumption: bool,
+}
+
+/// Additional profiles & series drawn alongside the main one
+#[derive(Debug, Default)]
+pub struct ChartExtras {
+    pub overlays: Vec<Vec<DiveMetric>>,
+    pub consumption: Option<GasConsumption>,
+}
+
+async fn chart_metrics(
+    context: &WebContext,
+    dive_id: Uuid,
+    request: &ChartRequest,
+) -> Result<(Vec<DiveMetric>, ChartExtras), ActixError> {
+    let computer = request.computer.unwrap_or_default();
+
+    let metrics = context
+        .handle
+        .dive_computer_metrics(dive_id, computer)
+        .await
+        .map_err(|err| ErrorInternalServerError(err.to_string()))?;
+
+    if metrics.is_empty() {
+        return Err(ErrorNotFound("No metrics found for this dive computer"));
+    }
+
+    let mut extras = ChartExtras::default();
+
+    if request.overlay {
+        let computers = context
+            .handle
+            .dive_computers(dive_id)
+            .await
+            .map_err(|err| ErrorInternalServerError(err.to_string()))?;
+
+        for other in computers {
+            if other.computer != computer {
+                extras.overlays.push(
+                    context
+                        .handle
+                        .dive_computer_metrics(dive_id, other.computer)
+                        .await
+                        .map_err(|err| ErrorInternalServerError(err.to_string()))?,
+                );
+            }
+        }
+    }
+
+    if request.consumption {
+        let cylinders = context
+            .handle
+            .dive_cylinders(dive_id)
+            .await
+            .map_err(|err| ErrorInternalServerError(err.to_string()))?;
+
+        extras.consumption = gas_consumption(&metrics, cylinders.first());
+    }
+
+    Ok((metrics, extras))
 }
 
 static FONT_DB: Lazy<Database> = Lazy::new(|| {
@@ -41,15 +110,35 @@ static FONT_DB: Lazy<Database> = Lazy::new(|| {
     fontdb
 });
 
+/// Renders an SVG to an image `scale` times its size, on a solid `background`
+pub fn rasterize(svg: &str, scale: f32, background: Color) -> Result<RgbaImage, ActixError> {
+    let mut tree = resvg::usvg::Tree::from_data(svg.as_bytes(), &Default::default())
+        .map_err(ErrorInternalServerError)?;
+    tree.convert_text(&FONT_DB);
+
+    let rtree = resvg::Tree::from_usvg(&tree);
+
+    let pixmap_size = rtree.size.to_int_size().scale_by(scale);
+    let mut pixmap = pixmap_size
+        .and_then(|size| tiny_skia::Pixmap::new(size.width(), size.height()))
+        .ok_or_else(|| ErrorInternalServerError("SVG is too big to render"))?;
+
+    pixmap.fill(background);
+    rtree.render(
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    RgbaImage::from_raw(pixmap.width(), pixmap.height(), pixmap.take())
+        .ok_or_else(|| ErrorInternalServerError("Not enough pixels"))
+}
+
 pub async fn png_chart(
     context: web::Data<WebContext>,
+    query: web::Query<ChartRequest>,
     dive_id: web::Path<Uuid>,
 ) -> Result<HttpResponse, ActixError> {
-    let dive_metrics = context
-        .handle
-        .dive_metrics(*dive_id)
-        .await
-        .map_err(|err| ErrorInternalServerError(err.to_string()))?;
+    let (dive_metrics, extras) = chart_metrics(&context, *dive_id, &query).await?;
 
     let dive = context
         .handle
@@ -100,22 +189,10 @@ pub async fn png_chart(
         .ok();
     }
 
-    let svg = render_dive(dive_metrics, deco_model, width, height)
+    let svg = render_dive(dive_metrics, deco_model, width, height, extras)
         .map_err(|err| ErrorInternalServerError(err.to_string()))?;
 
-    let mut tree = resvg::usvg::Tree::from_data(svg.as_bytes(), &Default::default()).unwrap();
-    tree.convert_text(&FONT_DB);
-
-    let rtree = resvg::Tree::from_usvg(&tree);
-
-    let pixmap_size = rtree.size.to_int_size();
-    let mut pixmap = tiny_skia::Pixmap::new(pixmap_size.width(), pixmap_size.height()).unwrap();
-
-    pixmap.fill(Color::from_rgba8(48, 55, 66, 255));
-    rtree.render(tiny_skia::Transform::default(), &mut pixmap.as_mut());
-
-    let img = RgbaImage::from_raw(pixmap.width(), pixmap.height(), pixmap.take())
-        .ok_or_else(|| ErrorInternalServerError("Not enough pixels"))?;
+    let img = rasterize(&svg, 1., Color::from_rgba8(48, 55, 66, 255))?;
 
     static EXTRA_HEIGHT: u32 = 40;
 
@@ -171,11 +248,7 @@ pub async fn svg_chart(
     query: web::Query<ChartRequest>,
     dive_id: web::Path<Uuid>,
 ) -> Result<HttpResponse, ActixError> {
-    let dive_metrics = context
-        .handle
-        .dive_metrics(*dive_id)
-        .await
-        .map_err(|err| ErrorInternalServerError(err.to_string()))?;
+    let (dive_metrics, extras) = chart_metrics(&context, *dive_id, &query).await?;
 
     let dive = context
         .handle
@@ -183,13 +256,12 @@ pub async fn svg_chart(
         .await
         .map_err(|err| ErrorInternalServerError(err.to_string()))?;
 
-    let ChartRequest { width, height } = query.into_inner();
-
     let svg = render_dive(
         dive_metrics,
         dive.deco_model.as_deref().and_then(parse_deco_model),
-        width,
-        height,
+        query.width,
+        query.height,
+        extras,
     )
     .map_err(|err| ErrorInternalServerError(err.to_string()))?;
 
@@ -363,7 +435,7 @@ fn get_ceiling(mut model: BuhlmannModel, metrics: &[DiveMetric]) -> Vec<Point> {
     ceiling
 }
 
-fn parse_deco_model(model: &str) -> Option<BuhlmannModel> {
+pub fn parse_deco_model(model: &str) -> Option<BuhlmannModel> {
     let suffix = model.strip_prefix("GF ")?;
 
     let mut split = suffix.split("/");
@@ -384,6 +456,7 @@ pub fn render_dive(
     deco_model: Option<BuhlmannModel>,
     width: Option<f64>,
     height: Option<f64>,
+    extras: ChartExtras,
 ) -> Result<String, Error> {
     let mut points = metrics
         .iter()
@@ -401,9 +474,23 @@ pub fn render_dive(
     let max_y = points.iter().map(|val| val.y).fold(f64::NAN, f64::max);
 
     #[allow(clippy::float_cmp)]
-    let max_point = points.iter().find(|val| val.y == max_y).unwrap();
+    let max_point = points
+        .iter()
+        .find(|val| val.y == max_y)
+        .ok_or_else(|| anyhow!("No metrics to chart"))?;
     let max_depth = max_point.y;
 
+    // Overlays share the same axes, so need to fit in too
+    let overlay_metrics = extras.overlays.iter().flatten();
+
+    let max_x = overlay_metrics
+        .clone()
+        .map(|val| val.time as f64)
+        .fold(max_x, f64::max);
+    let max_y = overlay_metrics
+        .map(|val| val.depth as f64)
+        .fold(max_y, f64::max);
+
     let divider_depth = 5.0;
 
     let max_y = ((max_y + divider_depth) / divider_depth).round() * divider_depth;
@@ -432,6 +519,23 @@ pub fn render_dive(
         .collect::<Vec<String>>()
         .join("");
 
+    let overlays = extras
+        .overlays
+        .iter()
+        .filter(|overlay| !overlay.is_empty())
+        .map(|overlay| {
+            overlay
+                .iter()
+                .map(|val| Point {
+                    x: (val.time as f64 / max_x * width) + offset,
+                    y: (val.depth as f64 / max_y * height) + offset,
+                })
+                .map(|val| val.as_svg())
+                .collect::<Vec<String>>()
+                .join("")
+        })
+        .collect();
+
     let ceiling_points = deco_model
         .map(|model| get_ceiling(model, &metrics))
         .unwrap_or_default();
@@ -470,11 +574,87 @@ pub fn render_dive(
         None
     };
 
+    // ppO2 is drawn against its own axis on the right, from the bottom of the chart
+    let max_ppo2 = metrics
+        .iter()
+        .flat_map(|val| [val.ppo2, val.setpoint])
+        .flatten()
+        .fold(2.0_f64, |max, val| max.max(val as f64));
+
+    let ppo2_divider = 0.5;
+    let max_ppo2 = (max_ppo2 / ppo2_divider).ceil() * ppo2_divider;
+
+    let ppo2_series = |value: fn(&DiveMetric) -> Option<f32>| {
+        let path = metrics
+            .iter()
+            .filter_map(|val| {
+                value(val).map(|ppo2| Point {
+                    x: (val.time as f64 / max_x * width) + offset,
+                    y: height + offset - (ppo2 as f64 / max_ppo2 * height),
+                })
+            })
+            .enumerate()
+            .map(|(i, val)| {
+                if i == 0 {
+                    format!("M {} {}", val.x, val.y)
+                } else {
+                    val.as_svg()
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("");
+
+        (!path.is_empty()).then_some(path)
+    };
+
+    let ppo2 = ppo2_series(|val| val.ppo2);
+    let setpoint = ppo2_series(|val| val.setpoint);
+
+    // Consumption is drawn as steps for each segment, labelled with the RMV when the cylinder size is known
+    let mut consumption = None;
+    let mut consumption_labels = Vec::new();
+
+    if let Some(ref gas) = extras.consumption {
+        let rate = |segment: &ConsumptionSegment| segment.rmv.unwrap_or(segment.sac) as f64;
+
+        let max_rate = gas.segments.iter().map(rate).fold(f64::NAN, f64::max);
+        let max_rate = (max_rate / 10.).ceil().max(1.) * 10.;
+
+        let mut path = String::new();
+
+        for segment in &gas.segments {
+            let y = height + offset - (rate(segment) / max_rate * height);
+            let start = (segment.start as f64 / max_x * width) + offset;
+            let end = (segment.end as f64 / max_x * width) + offset;
+
+            write!(&mut path, "M {start} {y} L {end} {y}").ok();
+
+            consumption_labels.push(SeriesLabel {
+                point: Point {
+                    x: (start + end) / 2.,
+                    y,
+                },
+                text: match segment.rmv {
+                    Some(rmv) => format!("{rmv:.0} L/min"),
+                    None => format!("{:.1} bar/min", segment.sac),
+                },
+            });
+        }
+
+        consumption = (!path.is_empty()).then_some(path);
+    }
+
     let chart = ChartTemplate {
         width,
         height,
         path,
+        overlays,
         ceiling,
+        ppo2,
+        setpoint,
+        max_ppo2,
+        consumption,
+        consumption_labels,
         offset,
         lines: 5,
         max_x,
@@ -508,7 +688,13 @@ struct ChartTemplate {
     width: f64,
     height: f64,
     path: String,
+    overlays: Vec<String>,
     ceiling: Option<String>,
+    ppo2: Option<String>,
+    setpoint: Option<String>,
+    max_ppo2: f64,
+    consumption: Option<String>,
+    consumption_labels: Vec<SeriesLabel>,
     offset: f64,
     lines: usize,
     max_x: f64,
@@ -523,6 +709,11 @@ struct GasChange {
     gas: String,
 }
 
+struct SeriesLabel {
+    point: Point,
+    text: String,
+}
+
 impl ChartTemplate {
     fn lidx(&self, idx: &usize) -> f64 {
         *idx as f64 / self.lines as f64
@@ -0,0 +1,299 @@
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+use crate::{
+    chart::{parse_deco_model, render_dive, ChartExtras},
+    db::DbHandle,
+    plan::{plan_dive, DEFAULT_ASCENT_RATE},
+    schema::{
+        DeviationType, DiveMetric, DivePlanComparison, DivePlanInput, DiveQuery, DiveSchedule,
+        PlanDeviation,
+    },
+};
+
+/// How much deeper than planned a dive can go before it counts, in meters
+const DEPTH_TOLERANCE: f32 = 1.;
+
+/// How much longer than planned the bottom time can be before it counts, in seconds
+const BOTTOM_TIME_TOLERANCE: i32 = 60;
+
+/// The bottom phase is over once the diver is this far above the last planned level, in meters
+const BOTTOM_DEPTH_TOLERANCE: f32 = 3.;
+
+/// Time at a stop is counted within half the distance between stops, in meters
+const STOP_BAND: f32 = 1.5;
+
+/// How much shorter than planned a stop can be before it counts, in seconds
+const STOP_TOLERANCE: i32 = 30;
+
+/// Ascent rates are averaged over this many seconds, so a single noisy sample isn't a fast ascent
+const RATE_WINDOW: i32 = 30;
+
+/// How much faster than planned an ascent can be before it counts
+const ASCENT_TOLERANCE: f32 = 1.1;
+
+/// Compares a plan against a logged dive the viewer can see.  Repetitive plans replay the dives from the day before it that the viewer can also see
+pub async fn compare_dive(
+    handle: &DbHandle,
+    viewer: Option<Uuid>,
+    dive_id: Uuid,
+    plan: DivePlanInput,
+) -> Result<DivePlanComparison> {
+    let dive = handle
+        .dives(viewer, &DiveQuery::id(dive_id))
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow!("No dive found"))?;
+
+    let metrics = handle.dive_metrics(dive.id).await?;
+
+    if metrics.is_empty() {
+        return Err(anyhow!("This dive has no profile to compare against"));
+    }
+
+    let ascent_rate = plan.ascent_rate.unwrap_or(DEFAULT_ASCENT_RATE);
+
+    let previous_dives = if plan.surface_interval.is_some() {
+        let dives = handle
+            .day_dives_before(dive.user_id, viewer, dive.date)
+            .await?;
+
+        handle.dive_profiles(dives).await?
+    } else {
+        Vec::new()
+    };
+
+    let schedule = plan_dive(plan, &previous_dives)?;
+
+    let deviations = plan_deviations(&schedule, &metrics, ascent_rate);
+
+    let chart = render_dive(
+        metrics,
+        dive.deco_model.as_deref().and_then(parse_deco_model),
+        Some(958.),
+        Some(400.),
+        ChartExtras {
+            overlays: vec![schedule.metrics.clone()],
+            ..Default::default()
+        },
+    )?;
+
+    Ok(DivePlanComparison {
+        schedule,
+        deviations,
+        chart,
+    })
+}
+
+/// Finds where the logged `metrics` went deeper, stayed longer, cut deco short or ascended faster than the plan
+pub fn plan_deviations(
+    schedule: &DiveSchedule,
+    metrics: &[DiveMetric],
+    ascent_rate: f32,
+) -> Vec<PlanDeviation> {
+    let mut deviations = Vec::new();
+
+    let Some(deepest) = metrics.iter().max_by(|a, b| a.depth.total_cmp(&b.depth)) else {
+        return deviations;
+    };
+
+    let planned_depth = schedule
+        .metrics
+        .iter()
+        .map(|val| val.depth)
+        .fold(0., f32::max);
+
+    if deepest.depth > planned_depth + DEPTH_TOLERANCE {
+        deviations.push(PlanDeviation {
+            deviation_type: DeviationType::DEEPER,
+            time: deepest.time,
+            depth: deepest.depth,
+            planned: planned_depth,
+            actual: deepest.depth,
+        });
+    }
+
+    let planned_end = schedule.bottom_time * 60;
+
+    // The depth of the last level, just before the final ascent
+    let end_depth = schedule
+        .metrics
+        .iter()
+        .rev()
+        .find(|val| val.time <= planned_end)
+        .map(|val| val.depth)
+        .unwrap_or(planned_depth);
+
+    let bottom_end = metrics
+        .iter()
+        .rev()
+        .find(|val| val.depth >= end_depth - BOTTOM_DEPTH_TOLERANCE)
+        .unwrap_or(deepest);
+
+    if bottom_end.time > planned_end + BOTTOM_TIME_TOLERANCE {
+        deviations.push(PlanDeviation {
+            deviation_type: DeviationType::LONGER_BOTTOM_TIME,
+            time: bottom_end.time,
+            depth: bottom_end.depth,
+            planned: planned_end as f32 / 60.,
+            actual: bottom_end.time as f32 / 60.,
+        });
+    }
+
+    let ascent = metrics
+        .iter()
+        .filter(|val| val.time >= bottom_end.time)
+        .collect::<Vec<_>>();
+
+    for (depth, planned) in planned_stops(&schedule.metrics, planned_end) {
+        let in_band = |metric: &DiveMetric| (metric.depth - depth).abs() <= STOP_BAND;
+
+        let actual = ascent
+            .windows(2)
+            .filter(|val| in_band(val[0]) && in_band(val[1]))
+            .map(|val| val[1].time - val[0].time)
+            .sum::<i32>();
+
+        if actual + STOP_TOLERANCE >= planned {
+            continue;
+        }
+
+        // When the stop was reached, or where it was passed when it was missed
+        let time = ascent
+            .iter()
+            .find(|val| val.depth <= depth + STOP_BAND)
+            .map(|val| val.time)
+            .unwrap_or(bottom_end.time);
+
+        deviations.push(PlanDeviation {
+            deviation_type: if actual == 0 {
+                DeviationType::MISSED_STOP
+            } else {
+                DeviationType::SHORTENED_STOP
+            },
+            time,
+            depth,
+            planned: planned as f32 / 60.,
+            actual: actual as f32 / 60.,
+        });
+    }
+
+    if let Some((metric, rate)) = fastest_ascent(metrics) {
+        if rate > ascent_rate * ASCENT_TOLERANCE {
+            deviations.push(PlanDeviation {
+                deviation_type: DeviationType::FAST_ASCENT,
+                time: metric.time,
+                depth: metric.depth,
+                planned: ascent_rate,
+                actual: rate,
+            });
+        }
+    }
+
+    deviations.sort_by_key(|val| val.time);
+
+    deviations
+}
+
+/// The planned stops after the bottom phase, as the depth & seconds spent there
+fn planned_stops(metrics: &[DiveMetric], bottom_end: i32) -> Vec<(f32, i32)> {
+    let mut stops: Vec<(f32, i32)> = Vec::new();
+
+    for pair in metrics.windows(2) {
+        let (start, end) = (&pair[0], &pair[1]);
+
+        #[allow(clippy::float_cmp)]
+        if start.time < bottom_end || start.depth != end.depth || start.depth <= 0. {
+            continue;
+        }
+
+        let duration = end.time - start.time;
+
+        // Gas switches & stops at the same depth are one stop
+        match stops.last_mut() {
+            #[allow(clippy::float_cmp)]
+            Some((depth, time)) if *depth == start.depth => *time += duration,
+            _ => stops.push((start.depth, duration)),
+        }
+    }
+
+    stops.retain(|(_, time)| *time > 0);
+
+    stops
+}
+
+/// The fastest ascent rate in m/min over any `RATE_WINDOW`, with the sample it started from
+fn fastest_ascent(metrics: &[DiveMetric]) -> Option<(&DiveMetric, f32)> {
+    let mut fastest: Option<(&DiveMetric, f32)> = None;
+
+    for (idx, start) in metrics.iter().enumerate() {
+        let Some(end) = metrics[idx..]
+            .iter()
+            .find(|val| val.time >= start.time + RATE_WINDOW)
+        else {
+            break;
+        };
+
+        let rate = (start.depth - end.depth) / (end.time - start.time) as f32 * 60.;
+
+        if fastest.is_none_or(|(_, val)| rate > val) {
+            fastest = Some((start, rate));
+        }
+    }
+
+    fastest.filter(|(_, rate)| *rate > 0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::GasInput;
+
+    fn profile(points: &[(i32, f32)]) -> Vec<DiveMetric> {
+        points
+            .iter()
+            .map(|(time, depth)| DiveMetric {
+                time: *time,
+                depth: *depth,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_deviations() {
+        let schedule = plan_dive(
+            DivePlanInput {
+                time: 30,
+                depth: 40.,
+                gf_low: 30,
+                gf_high: 70,
+                back_gas: GasInput {
+                    o2: 21.,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            &[],
+        )
+        .expect("Plans dive");
+
+        // Goes deeper, stays longer, then heads straight for the surface
+        let metrics = profile(&[(0, 0.), (150, 43.), (2100, 43.), (2220, 0.)]);
+
+        let types = plan_deviations(&schedule, &metrics, DEFAULT_ASCENT_RATE)
+            .into_iter()
+            .map(|val| val.deviation_type)
+            .collect::<Vec<_>>();
+
+        assert!(types.contains(&DeviationType::DEEPER), "{types:?}");
+        assert!(types.contains(&DeviationType::LONGER_BOTTOM_TIME));
+        assert!(types.contains(&DeviationType::MISSED_STOP));
+        assert!(types.contains(&DeviationType::FAST_ASCENT));
+
+        // Following the plan is fine
+        let deviations = plan_deviations(&schedule, &schedule.metrics, DEFAULT_ASCENT_RATE);
+
+        assert_eq!(deviations, Vec::new());
+    }
+}
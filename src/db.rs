@@ -17,6 +17,7 @@ mod migrations;
 mod categories;
 mod comment;
 mod dive;
+mod dive_plan;
 mod dive_site;
 mod email_verification;
 mod feedback;
@@ -25,6 +26,8 @@ mod password_reset;
 mod photo;
 mod region;
 mod sealife;
+mod stats;
+mod subsurface;
 mod user;
 
 #[derive(Clone)]
@@ -170,3 +173,56 @@ impl<'a> StatementBuilder<'a> {
         self.statement.push(' ');
     }
 }
+
+#[cfg(test)]
+impl DbHandle {
+    /// Connects to the database at `TEST_CONNECT_URL`, returning a new user to run a test as
+    pub async fn test_handle() -> (DbHandle, Uuid) {
+        let url = std::env::var("TEST_CONNECT_URL").expect("TEST_CONNECT_URL is set");
+
+        let mgr_config = ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        };
+
+        let mgr = Manager::from_config(
+            tokio_postgres::Config::from_str(&url).expect("Valid TEST_CONNECT_URL"),
+            NoTls,
+            mgr_config,
+        );
+
+        let pool = Pool::builder(mgr).max_size(4).build().unwrap();
+
+        Migrator::new(pool.clone())
+            .apply_migrations()
+            .await
+            .expect("Applies migrations");
+
+        let user_id = Uuid::new_v4();
+
+        pool.get()
+            .await
+            .unwrap()
+            .execute(
+                "insert into users (id, username, level, public_key) values ($1, $2, 'User', '')",
+                &[&user_id, &user_id.to_string()],
+            )
+            .await
+            .unwrap();
+
+        (DbHandle::from_pool(&pool), user_id)
+    }
+
+    /// Removes a user from [`DbHandle::test_handle`], along with everything they logged
+    pub async fn remove_test_user(&self, user_id: Uuid) {
+        let client = self.pool.get().await.unwrap();
+
+        for query in [
+            "delete from photos where user_id = $1",
+            "delete from dives where user_id = $1",
+            "delete from dive_sites where user_id = $1",
+            "delete from users where id = $1",
+        ] {
+            client.execute(query, &[&user_id]).await.unwrap();
+        }
+    }
+}
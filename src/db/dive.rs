@@ -36,7 +36,7 @@ impl DbHandle {
         &self,
         user_id: Uuid,
         request: &CreateDive,
-        dive_metrics: Option<Vec<DiveMetric>>,
+        dive_profile: Option<DiveProfile>,
     ) -> Result<Dive, Error> {
         let uuid = request.id.unwrap_or_else(Uuid::new_v4);
 
@@ -45,8 +45,10 @@ impl DbHandle {
 
             let mut conn = client.transaction().await?;
 
-            let query = "insert into dives (id, user_id, date, duration, depth, dive_site_id, description, published, deco_model)
-            values ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            // The extra details are optional, and left alone when not provided
+            let query = "insert into dives (id, user_id, date, duration, depth, dive_site_id, description, published, deco_model, buddy, divemaster, tags, visibility, rating)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9,
+                coalesce($10::text, ''), coalesce($11::text, ''), coalesce($12::text[], '{}'), coalesce($13::integer, 0), coalesce($14::integer, 0))
 
             on conflict(id) do update
                 set date = excluded.date,
@@ -55,7 +57,12 @@ impl DbHandle {
                     depth = excluded.depth,
                     description = excluded.description,
                     published = excluded.published,
-                    deco_model = excluded.deco_model
+                    deco_model = excluded.deco_model,
+                    buddy = coalesce($10::text, dives.buddy),
+                    divemaster = coalesce($11::text, dives.divemaster),
+                    tags = coalesce($12::text[], dives.tags),
+                    visibility = coalesce($13::integer, dives.visibility),
+                    rating = coalesce($14::integer, dives.rating)
 
             returning *";
 
@@ -74,6 +81,11 @@ impl DbHandle {
                         &request.description,
                         &request.published,
                         &request.deco_model,
+                        &request.buddy,
+                        &request.divemaster,
+                        &request.tags,
+                        &request.visibility,
+                        &request.rating,
                     ],
                 )
                 .await?;
@@ -87,12 +99,24 @@ impl DbHandle {
             )
             .await?;
 
-            if let Some(metrics) = dive_metrics {
+            if let Some(profile) = dive_profile {
                 debug!("Adding metrics for:{}", uuid);
-                self.add_metrics(uuid, &metrics, &mut conn).await?;
+                self.add_metrics(uuid, &profile.metrics, &mut conn).await?;
+                self.add_computers(uuid, &profile.computers, &mut conn)
+                    .await?;
+                self.add_sensor_pressures(uuid, &profile.pressures, &mut conn)
+                    .await?;
                 debug!("Finished metrics for:{}", uuid);
             }
 
+            if let Some(ref cylinders) = request.cylinders {
+                self.add_cylinders(uuid, cylinders, &mut conn).await?;
+            }
+
+            if let Some(ref weights) = request.weights {
+                self.add_weights(uuid, weights, &mut conn).await?;
+            }
+
             conn.commit().await?;
 
             result
@@ -197,13 +221,97 @@ impl DbHandle {
             .and_then(|row| Dive::from_row(row).ok()))
     }
 
+    /// The dives a user has logged starting at any of the given times
+    pub async fn dives_at(
+        &self,
+        user_id: Uuid,
+        dates: &[DateTime<Local>],
+    ) -> Result<Vec<Dive>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select * from dives where user_id = $1 and date = any($2)";
+
+        Dive::from_rows(client.query(query, &[&user_id, &dates]).await?)
+    }
+
+    /// The dives a user logged in the day leading up to their latest dive, oldest first
+    pub async fn latest_day_dives(&self, user_id: Uuid) -> Result<Vec<Dive>, Error> {
+        let client = self.pool.get().await?;
+        let query = "
+            select
+                *
+            from
+                dives
+            where
+                user_id = $1
+                and date > (select max(date) from dives where user_id = $1) - interval '1 day'
+            order by date asc";
+
+        Dive::from_rows(client.query(query, &[&user_id]).await?)
+    }
+
+    /// The dives a user logged in the day before `date` that `viewer` can see, oldest first
+    pub async fn day_dives_before(
+        &self,
+        user_id: Uuid,
+        viewer: Option<Uuid>,
+        date: DateTime<Local>,
+    ) -> Result<Vec<Dive>, Error> {
+        let client = self.pool.get().await?;
+        let query = "
+            select
+                *
+            from
+                dives
+            where
+                user_id = $1
+                and ( user_id = $2 or published = true )
+                and date < $3
+                and date > $3 - interval '1 day'
+            order by date asc";
+
+        Dive::from_rows(client.query(query, &[&user_id, &viewer, &date]).await?)
+    }
+
+    /// A user's dives with the given ids, oldest first
+    pub async fn dives_by_id(&self, user_id: Uuid, ids: &[Uuid]) -> Result<Vec<Dive>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select * from dives where user_id = $1 and id = any($2) order by date asc";
+
+        Dive::from_rows(client.query(query, &[&user_id, &ids]).await?)
+    }
+
+    /// The profiles of the dives from [`DbHandle::latest_day_dives`], for replaying on a deco model
+    pub async fn latest_day_dive_profiles(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<(Dive, Vec<DiveMetric>)>, Error> {
+        self.dive_profiles(self.latest_day_dives(user_id).await?)
+            .await
+    }
+
+    /// Loads the metrics of each dive, for replaying on a deco model
+    pub async fn dive_profiles(
+        &self,
+        dives: Vec<Dive>,
+    ) -> Result<Vec<(Dive, Vec<DiveMetric>)>, Error> {
+        let mut profiles = Vec::new();
+
+        for dive in dives {
+            let metrics = self.dive_metrics(dive.id).await?;
+
+            profiles.push((dive, metrics));
+        }
+
+        Ok(profiles)
+    }
+
     pub async fn add_metrics<'a>(
         &self,
         dive_id: Uuid,
         request: &[DiveMetric],
         conn: &mut Transaction<'a>,
     ) -> Result<(), Error> {
-        let query = "insert into dive_metrics (dive_id, time, depth, pressure, temperature, o2, he) values ($1, $2, $3, $4, $5, $6, $7)";
+        let query = "insert into dive_metrics (dive_id, time, depth, pressure, temperature, o2, he, computer, ppo2, setpoint, cns, ndl, tts, heart_rate) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)";
 
         conn.query("delete from dive_metrics where dive_id = $1", &[&dive_id])
             .await?;
@@ -221,6 +329,13 @@ impl DbHandle {
                     &metric.temperature,
                     &metric.o2,
                     &metric.he,
+                    &metric.computer,
+                    &metric.ppo2,
+                    &metric.setpoint,
+                    &metric.cns,
+                    &metric.ndl,
+                    &metric.tts,
+                    &metric.heart_rate,
                 ],
             )
             .await?;
@@ -229,6 +344,145 @@ impl DbHandle {
         Ok(())
     }
 
+    pub async fn add_cylinders<'a>(
+        &self,
+        dive_id: Uuid,
+        request: &[DiveCylinder],
+        conn: &mut Transaction<'a>,
+    ) -> Result<(), Error> {
+        let query = "insert into dive_cylinders (dive_id, idx, description, size, working_pressure, start_pressure, end_pressure, o2, he) values ($1, $2, $3, $4, $5, $6, $7, $8, $9)";
+
+        conn.query("delete from dive_cylinders where dive_id = $1", &[&dive_id])
+            .await?;
+
+        let statement = conn.prepare(query).await?;
+
+        for (idx, cylinder) in request.iter().enumerate() {
+            conn.execute(
+                &statement,
+                &[
+                    &dive_id,
+                    &(idx as i32),
+                    &cylinder.description,
+                    &cylinder.size,
+                    &cylinder.working_pressure,
+                    &cylinder.start_pressure,
+                    &cylinder.end_pressure,
+                    &cylinder.o2,
+                    &cylinder.he,
+                ],
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn add_weights<'a>(
+        &self,
+        dive_id: Uuid,
+        request: &[DiveWeight],
+        conn: &mut Transaction<'a>,
+    ) -> Result<(), Error> {
+        let query =
+            "insert into dive_weights (dive_id, idx, description, weight) values ($1, $2, $3, $4)";
+
+        conn.query("delete from dive_weights where dive_id = $1", &[&dive_id])
+            .await?;
+
+        let statement = conn.prepare(query).await?;
+
+        for (idx, weight) in request.iter().enumerate() {
+            conn.execute(
+                &statement,
+                &[&dive_id, &(idx as i32), &weight.description, &weight.weight],
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn add_computers<'a>(
+        &self,
+        dive_id: Uuid,
+        request: &[DiveComputer],
+        conn: &mut Transaction<'a>,
+    ) -> Result<(), Error> {
+        let query =
+            "insert into dive_computers (dive_id, computer, model, serial) values ($1, $2, $3, $4)";
+
+        conn.query("delete from dive_computers where dive_id = $1", &[&dive_id])
+            .await?;
+
+        let statement = conn.prepare(query).await?;
+
+        for computer in request {
+            conn.execute(
+                &statement,
+                &[
+                    &dive_id,
+                    &computer.computer,
+                    &computer.model,
+                    &computer.serial,
+                ],
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn add_sensor_pressures<'a>(
+        &self,
+        dive_id: Uuid,
+        request: &[SensorPressure],
+        conn: &mut Transaction<'a>,
+    ) -> Result<(), Error> {
+        let query = "insert into dive_sensor_pressures (dive_id, computer, time, sensor, pressure) values ($1, $2, $3, $4, $5)";
+
+        conn.query(
+            "delete from dive_sensor_pressures where dive_id = $1",
+            &[&dive_id],
+        )
+        .await?;
+
+        let statement = conn.prepare(query).await?;
+
+        for pressure in request {
+            conn.execute(
+                &statement,
+                &[
+                    &dive_id,
+                    &pressure.computer,
+                    &pressure.time,
+                    &pressure.sensor,
+                    &pressure.pressure,
+                ],
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn dive_cylinders(&self, dive_id: Uuid) -> Result<Vec<DiveCylinder>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select description, size, working_pressure, start_pressure, end_pressure, o2, he from dive_cylinders where dive_id = $1 order by idx asc";
+        let result = client.query(query, &[&dive_id]).await?;
+
+        DiveCylinder::from_rows(result)
+    }
+
+    pub async fn dive_weights(&self, dive_id: Uuid) -> Result<Vec<DiveWeight>, Error> {
+        let client = self.pool.get().await?;
+        let query =
+            "select description, weight from dive_weights where dive_id = $1 order by idx asc";
+        let result = client.query(query, &[&dive_id]).await?;
+
+        DiveWeight::from_rows(result)
+    }
+
     pub async fn dive_number(&self, dive_id: Uuid, user_id: Uuid) -> Result<i64, Error> {
         let client = self.pool.get().await?;
         let query =
@@ -250,15 +504,60 @@ impl DbHandle {
         Ok(count)
     }
 
+    /// Metrics from the primary dive computer
     pub async fn dive_metrics(&self, dive_id: Uuid) -> Result<Vec<DiveMetric>, Error> {
+        self.dive_computer_metrics(dive_id, 0).await
+    }
+
+    pub async fn dive_computer_metrics(
+        &self,
+        dive_id: Uuid,
+        computer: i32,
+    ) -> Result<Vec<DiveMetric>, Error> {
         let client = self.pool.get().await?;
         let query =
-            "select time, depth, pressure, temperature, o2, he from dive_metrics where dive_id = $1 order by time asc";
-        let result = client.query(query, &[&dive_id]).await?;
+            "select time, depth, pressure, temperature, o2, he, computer, ppo2, setpoint, cns, ndl, tts, heart_rate from dive_metrics where dive_id = $1 and computer = $2 order by time asc";
+        let result = client.query(query, &[&dive_id, &computer]).await?;
 
         DiveMetric::from_rows(result)
     }
 
+    /// The dive computers used on a dive.  Dives logged before computers were tracked only have metrics for computer `0`
+    pub async fn dive_computers(&self, dive_id: Uuid) -> Result<Vec<DiveComputer>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select computer, model, serial from dive_computers where dive_id = $1
+            union
+            select distinct computer, null, null from dive_metrics
+                where dive_id = $1
+                and computer not in (select computer from dive_computers where dive_id = $1)
+            order by computer asc";
+        let result = client.query(query, &[&dive_id]).await?;
+
+        DiveComputer::from_rows(result)
+    }
+
+    pub async fn dive_sensor_pressures(
+        &self,
+        dive_id: Uuid,
+        computer: i32,
+    ) -> Result<Vec<SensorPressure>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select computer, time, sensor, pressure from dive_sensor_pressures where dive_id = $1 and computer = $2 order by sensor asc, time asc";
+        let result = client.query(query, &[&dive_id, &computer]).await?;
+
+        SensorPressure::from_rows(result)
+    }
+
+    pub async fn dive_gas_mixes(&self, user_id: Uuid) -> Result<Vec<(f32, f32)>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select distinct coalesce(o2, 21), coalesce(he, 0) from dive_metrics
+            where dive_id in (select id from dives where user_id = $1)
+            and (o2 is not null or he is not null)";
+        let result = client.query(query, &[&user_id]).await?;
+
+        Ok(result.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
     pub async fn has_metrics(&self, dive_id: Uuid) -> Result<bool, Error> {
         let client = self.pool.get().await?;
         let query = "select count(*) from dive_metrics where dive_id = $1";
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Error};
+use divedb_core::FromRow;
+use postgres_types::Json;
+use uuid::Uuid;
+
+use crate::schema::*;
+
+use super::DbHandle;
+
+impl DbHandle {
+    pub async fn save_dive_plan(
+        &self,
+        user_id: Uuid,
+        request: &SaveDivePlan,
+        previous_dive_ids: &[Uuid],
+    ) -> Result<SavedDivePlan, Error> {
+        let client = self.pool.get().await?;
+        let plan = Json(&request.plan);
+
+        if let Some(id) = request.id {
+            let query = "update dive_plans set name = $3, notes = $4, plan = $5, dive_id = $6, previous_dive_ids = $7, updated = now() where id = $1 and user_id = $2 returning *";
+
+            let result = client
+                .query_opt(
+                    query,
+                    &[
+                        &id,
+                        &user_id,
+                        &request.name,
+                        &request.notes,
+                        &plan,
+                        &request.dive_id,
+                        &previous_dive_ids,
+                    ],
+                )
+                .await?
+                .ok_or_else(|| anyhow!("No dive plan found"))?;
+
+            SavedDivePlan::from_row(result)
+        } else {
+            let query = "insert into dive_plans (id, user_id, name, notes, plan, dive_id, previous_dive_ids) values ($1, $2, $3, $4, $5, $6, $7) returning *";
+
+            let result = client
+                .query_one(
+                    query,
+                    &[
+                        &Uuid::new_v4(),
+                        &user_id,
+                        &request.name,
+                        &request.notes,
+                        &plan,
+                        &request.dive_id,
+                        &previous_dive_ids,
+                    ],
+                )
+                .await?;
+
+            SavedDivePlan::from_row(result)
+        }
+    }
+
+    pub async fn dive_plan(&self, id: Uuid) -> Result<Option<SavedDivePlan>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select * from dive_plans where id = $1";
+
+        client
+            .query_opt(query, &[&id])
+            .await?
+            .map(SavedDivePlan::from_row)
+            .transpose()
+    }
+
+    /// A user's plans, optionally only those linked to `dive_id`
+    pub async fn dive_plans(
+        &self,
+        user_id: Uuid,
+        dive_id: Option<Uuid>,
+    ) -> Result<Vec<SavedDivePlan>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select * from dive_plans where user_id = $1 and ($2::uuid is null or dive_id = $2) order by updated desc";
+
+        SavedDivePlan::from_rows(client.query(query, &[&user_id, &dive_id]).await?)
+    }
+
+    /// The dives to replay before a saved plan.  Plans linked to a dive replay the ones `viewer` can see from the day before it, otherwise the dives from when it was saved are used
+    pub async fn dive_plan_previous_dives(
+        &self,
+        saved: &SavedDivePlan,
+        viewer: Option<Uuid>,
+    ) -> Result<Vec<(Dive, Vec<DiveMetric>)>, Error> {
+        if saved.plan.0.surface_interval.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let dives = match saved.dive_id {
+            Some(dive_id) => {
+                let dive = self.dive(dive_id).await?;
+
+                self.day_dives_before(saved.user_id, viewer, dive.date)
+                    .await?
+            }
+            None => {
+                self.dives_by_id(saved.user_id, &saved.previous_dive_ids)
+                    .await?
+            }
+        };
+
+        self.dive_profiles(dives).await
+    }
+
+    /// Removes one of a user's plans, returning how many were removed
+    pub async fn remove_dive_plan(&self, user_id: Uuid, id: Uuid) -> Result<u64, Error> {
+        let client = self.pool.get().await?;
+        let query = "delete from dive_plans where id = $1 and user_id = $2";
+
+        Ok(client.execute(query, &[&id, &user_id]).await?)
+    }
+}
@@ -174,6 +174,43 @@ impl DbHandle {
         Ok(result)
     }
 
+    /// The closest dive site to a point that the user can see, if it's within `radius` meters
+    pub async fn nearest_dive_site(
+        &self,
+        user_id: Uuid,
+        lat: f64,
+        lon: f64,
+        radius: f64,
+    ) -> Result<Option<Uuid>, Error> {
+        let client = self.pool.get().await?;
+
+        // The haversine distance in meters
+        let query = "
+            select
+                id
+            from
+                (
+                select
+                    id,
+                    2 * 6371000 * asin(sqrt(
+                        power(sin(radians(lat - $2) / 2), 2)
+                        + cos(radians($2)) * cos(radians(lat)) * power(sin(radians(lon - $3) / 2), 2)
+                    )) as distance
+                from
+                    dive_sites
+                where
+                    published = true or user_id = $1
+                ) as distances
+            where distance <= $4
+            order by distance
+            limit 1";
+
+        Ok(client
+            .query_opt(query, &[&user_id, &lat, &lon, &radius])
+            .await?
+            .map(|row| row.get(0)))
+    }
+
     pub async fn dive_sites(
         &self,
         user_id: Option<Uuid>,
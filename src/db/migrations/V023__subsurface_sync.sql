@@ -0,0 +1,7 @@
+create table if not exists subsurface_sync (
+    user_id uuid primary key REFERENCES users(id) ON DELETE CASCADE,
+    remote_url text not null,
+    branch text not null,
+    commit_id text not null,
+    "date" timestamp with time zone not null default now()
+);
@@ -0,0 +1,28 @@
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'SyncState') THEN
+        CREATE TYPE "SyncState" as enum ('Queued', 'Cloning', 'Parsing', 'Importing', 'Done', 'Failed');
+    END IF;
+END$$;
+
+create table if not exists sync_jobs (
+    id uuid primary key,
+    user_id uuid not null REFERENCES users(id) ON DELETE CASCADE,
+    state "SyncState" not null default 'Queued',
+    sites_created integer not null default 0,
+    sites_skipped integer not null default 0,
+    dives_created integer not null default 0,
+    dives_updated integer not null default 0,
+    dives_skipped integer not null default 0,
+    error text,
+    -- The process that queued the job keeps it fresh, so other replicas can tell when it has been abandoned
+    worker uuid,
+    created timestamp with time zone not null default now(),
+    updated timestamp with time zone not null default now()
+);
+
+create index if not exists sync_jobs_user_id on sync_jobs (user_id);
+
+-- Only one job per user can be unfinished
+create unique index if not exists sync_jobs_unfinished_user_id on sync_jobs (user_id)
+where state not in ('Done', 'Failed');
@@ -0,0 +1,26 @@
+alter table dives add column buddy text not null default '';
+alter table dives add column divemaster text not null default '';
+alter table dives add column tags text[] not null default '{}';
+alter table dives add column visibility integer not null default 0;
+alter table dives add column rating integer not null default 0;
+
+create table if not exists dive_cylinders (
+    dive_id uuid not null REFERENCES dives(id) ON DELETE CASCADE,
+    idx integer not null,
+    description text,
+    size real,
+    working_pressure real,
+    start_pressure real,
+    end_pressure real,
+    o2 real,
+    he real,
+    primary key (dive_id, idx)
+);
+
+create table if not exists dive_weights (
+    dive_id uuid not null REFERENCES dives(id) ON DELETE CASCADE,
+    idx integer not null,
+    description text,
+    weight real not null,
+    primary key (dive_id, idx)
+);
@@ -0,0 +1,19 @@
+alter table dive_metrics add column computer integer not null default 0;
+
+create table if not exists dive_computers (
+    dive_id uuid not null REFERENCES dives(id) ON DELETE CASCADE,
+    computer integer not null,
+    model text,
+    serial text,
+    primary key (dive_id, computer)
+);
+
+create table if not exists dive_sensor_pressures (
+    dive_id uuid not null REFERENCES dives(id) ON DELETE CASCADE,
+    computer integer not null,
+    time integer not null,
+    sensor integer not null,
+    pressure real not null
+);
+
+create index if not exists dive_sensor_pressures_dive_id on dive_sensor_pressures (dive_id);
@@ -0,0 +1,6 @@
+alter table dive_metrics add column ppo2 real;
+alter table dive_metrics add column setpoint real;
+alter table dive_metrics add column cns real;
+alter table dive_metrics add column ndl integer;
+alter table dive_metrics add column tts integer;
+alter table dive_metrics add column heart_rate integer;
@@ -0,0 +1,14 @@
+create table if not exists dive_plans (
+    id uuid primary key,
+    user_id uuid not null REFERENCES users(id) ON DELETE CASCADE,
+    name text not null,
+    notes text not null default '',
+    plan jsonb not null,
+    -- The dive this plan was executed as
+    dive_id uuid REFERENCES dives(id) ON DELETE SET NULL,
+    created timestamp with time zone not null default now(),
+    updated timestamp with time zone not null default now()
+);
+
+create index if not exists dive_plans_user_id on dive_plans (user_id);
+create index if not exists dive_plans_dive_id on dive_plans (dive_id);
@@ -0,0 +1,3 @@
+-- Where a photo was taken, from its GPS metadata
+alter table photos add column if not exists lat double precision;
+alter table photos add column if not exists lon double precision;
@@ -0,0 +1,11 @@
+-- The camera a photo was taken with, and the seconds added to its clock to get `date`
+alter table photos add column if not exists camera text;
+alter table photos add column if not exists camera_offset integer not null default 0;
+
+-- How far each of a user's camera clocks are out, in seconds to add to them
+create table if not exists camera_offsets (
+    user_id uuid not null REFERENCES users(id) ON DELETE CASCADE,
+    camera text not null,
+    clock_offset integer not null,
+    primary key (user_id, camera)
+);
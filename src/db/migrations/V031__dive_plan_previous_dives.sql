@@ -0,0 +1,2 @@
+-- The dives replayed before a repetitive plan when it was saved, so it stays the same as more dives are logged
+alter table dive_plans add column if not exists previous_dive_ids uuid[] not null default '{}';
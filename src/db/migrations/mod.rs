@@ -55,6 +55,15 @@ impl Migrator {
                 Box::new(external!("V020__external_users.sql")),
                 Box::new(external!("V021__email_verification.sql")),
                 Box::new(external!("V022__deco_model.sql")),
+                Box::new(external!("V023__subsurface_sync.sql")),
+                Box::new(external!("V024__sync_jobs.sql")),
+                Box::new(external!("V025__dive_details.sql")),
+                Box::new(external!("V026__dive_computers.sql")),
+                Box::new(external!("V027__dive_metric_details.sql")),
+                Box::new(external!("V028__dive_plans.sql")),
+                Box::new(external!("V029__photo_location.sql")),
+                Box::new(external!("V030__camera_offsets.sql")),
+                Box::new(external!("V031__dive_plan_previous_dives.sql")),
             ],
         }
     }
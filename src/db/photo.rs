@@ -20,8 +20,9 @@ impl DbHandle {
         let client = self.pool.get().await?;
         let uuid = create_photo.id.unwrap_or_else(Uuid::new_v4);
 
-        let query = "insert into photos (id, user_id, filename, date, dive_id, size, dive_site_id, internal)
-            values ($1, $2, $3, $4, $5, $6, $7, $8)
+        // The location & camera only come from the photo itself, so isn't changed when a photo is edited
+        let query = "insert into photos (id, user_id, filename, date, dive_id, size, dive_site_id, internal, lat, lon, camera, camera_offset)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
             
             on conflict(id) do update
                 set filename = excluded.filename,
@@ -45,7 +46,11 @@ impl DbHandle {
                 height,
                 description,
                 copyright,
-                internal";
+                internal,
+                lat,
+                lon,
+                camera,
+                camera_offset";
 
         let result = client
             .query_one(
@@ -59,6 +64,10 @@ impl DbHandle {
                     &create_photo.size,
                     &create_photo.dive_site_id,
                     &create_photo.internal.unwrap_or_default(),
+                    &create_photo.lat,
+                    &create_photo.lon,
+                    &create_photo.camera,
+                    &create_photo.camera_offset,
                 ],
             )
             .await?;
@@ -67,8 +76,12 @@ impl DbHandle {
 
         let photo = Photo::from_row(result)?;
 
+        // The sealife here is the species tagged in the whole photo, so boxed tags are left for `tag_sealife` to manage
         client
-            .execute("delete from sealife_tags where photo_id = $1", &[&photo.id])
+            .execute(
+                "delete from sealife_tags where photo_id = $1 and sealife_id is distinct from $2 and u_max <= u_min",
+                &[&photo.id, &create_photo.sealife_id],
+            )
             .await?;
 
         if let Some(sealife_id) = create_photo.sealife_id {
@@ -77,6 +90,7 @@ impl DbHandle {
                     "insert into sealife_tags
                 (sealife_id, photo_id, u_min, v_min, u_max, v_max)
                 values ($1, $2, 0, 0, 0, 0)
+                on conflict do nothing
             ",
                     &[&sealife_id, &photo.id],
                 )
@@ -118,12 +132,16 @@ impl DbHandle {
                 height,
                 description,
                 copyright,
-                internal
+                internal,
+                lat,
+                lon,
+                camera,
+                camera_offset
             from 
                 photos as p
             left join lateral 
                 (select 
-                    hide_location
+                    bool_or(hide_location) as hide_location
                  from sealife_tags as st
                  inner join sealife s on s.id = st.sealife_id
                  where p.id = st.photo_id
@@ -265,4 +283,271 @@ impl DbHandle {
         client.execute(query, &[&photo_id, &user_id]).await?;
         Ok(())
     }
+
+    /// The cameras a user has taken photos with or set an offset for, with how far their clocks are out
+    pub async fn camera_offsets(&self, user_id: Uuid) -> Result<Vec<CameraOffset>, Error> {
+        let client = self.pool.get().await?;
+        let query = "
+            select
+                camera,
+                coalesce(max(clock_offset), 0) as clock_offset
+            from
+                (
+                select camera, clock_offset from camera_offsets where user_id = $1
+                union all
+                select distinct camera, null::integer from photos where user_id = $1 and camera is not null
+                ) as cameras
+            group by camera
+            order by camera";
+
+        CameraOffset::from_rows(client.query(query, &[&user_id]).await?)
+    }
+
+    pub async fn camera_offset(&self, user_id: Uuid, camera: &str) -> Result<Option<i32>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select clock_offset from camera_offsets where user_id = $1 and camera = $2";
+
+        Ok(client
+            .query_opt(query, &[&user_id, &camera])
+            .await?
+            .map(|row| row.get(0)))
+    }
+
+    pub async fn set_camera_offset(
+        &self,
+        user_id: Uuid,
+        offset: &CameraOffset,
+    ) -> Result<(), Error> {
+        let client = self.pool.get().await?;
+        let query = "insert into camera_offsets (user_id, camera, clock_offset) values ($1, $2, $3)
+            on conflict (user_id, camera) do update set clock_offset = excluded.clock_offset";
+
+        client
+            .execute(query, &[&user_id, &offset.camera, &offset.clock_offset])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Corrects the dates of a user's photos by `clock_offset` seconds, or their camera's offset when there isn't one, then links each to the dive it was taken on.
+    ///
+    /// Only the given photos, or those from the given camera, are matched.  An offset applies to every camera, so needs one of these to narrow it down.
+    /// Photos that don't fall within a dive keep the one they're linked to.  Returns how many are linked to a dive
+    pub async fn rematch_photos(
+        &self,
+        user_id: Uuid,
+        photo_ids: Option<&[Uuid]>,
+        camera: Option<&str>,
+        clock_offset: Option<i32>,
+    ) -> Result<i64, Error> {
+        if clock_offset.is_some() && photo_ids.is_none() && camera.is_none() {
+            return Err(anyhow!(
+                "A clock offset needs either the photos or camera it applies to"
+            ));
+        }
+
+        let client = self.pool.get().await?;
+
+        // Uses the same window as `nearest_dive_by_time`.  A site that came from the previous dive follows the new one
+        let query = "
+            with corrected as (
+                select
+                    p.id,
+                    corrected.camera_offset,
+                    p.date + make_interval(secs => corrected.camera_offset - p.camera_offset) as date
+                from
+                    photos as p
+                left join camera_offsets as co on co.user_id = p.user_id and co.camera = p.camera
+                cross join lateral
+                    (select coalesce($3, co.clock_offset, 0) as camera_offset) corrected
+                where
+                    p.user_id = $1
+                    and not p.internal
+                    and p.date is not null
+                    and ($2::uuid[] is null or p.id = any($2))
+                    and ($4::text is null or p.camera = $4)
+            ), matched as (
+                select
+                    corrected.*,
+                    dive.id as dive_id,
+                    dive.dive_site_id
+                from
+                    corrected
+                left join lateral
+                    (select
+                        id,
+                        dive_site_id
+                     from dives as d
+                     where d.user_id = $1
+                        and ABS(extract(epoch from (d.date - corrected.date))) < (d.duration + 7200)
+                     order by ABS(extract(epoch from (d.date - corrected.date)))
+                     limit 1
+                    ) dive on true
+            )
+            update photos
+                set date = matched.date,
+                    camera_offset = matched.camera_offset,
+                    dive_id = coalesce(matched.dive_id, photos.dive_id),
+                    dive_site_id = case
+                        when photos.dive_site_id is null
+                            or photos.dive_site_id = (select dive_site_id from dives where id = photos.dive_id)
+                        then coalesce(matched.dive_site_id, photos.dive_site_id)
+                        else photos.dive_site_id
+                    end
+            from matched
+            where photos.id = matched.id
+            returning photos.dive_id";
+
+        let rows = client
+            .query(query, &[&user_id, &photo_ids, &clock_offset, &camera])
+            .await?;
+
+        self.clear_cache().await;
+
+        Ok(rows
+            .iter()
+            .filter(|row| row.get::<_, Option<Uuid>>(0).is_some())
+            .count() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{prelude::*, Duration};
+
+    use super::*;
+
+    async fn photo_date(handle: &DbHandle, id: Uuid) -> (DateTime<Local>, Option<Uuid>, i32) {
+        let photo = handle
+            .photos(None, &PhotoQuery::id(id))
+            .await
+            .expect("Queries photos")
+            .pop()
+            .expect("Photo exists");
+
+        (photo.date.unwrap(), photo.dive_id, photo.camera_offset)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres database at TEST_CONNECT_URL"]
+    async fn test_rematch_photos() {
+        let (handle, user_id) = DbHandle::test_handle().await;
+
+        let dive_date = Local.with_ymd_and_hms(2023, 1, 14, 10, 0, 0).unwrap();
+
+        let dive = handle
+            .create_dive(
+                user_id,
+                &CreateDive {
+                    id: None,
+                    date: dive_date.into(),
+                    duration: 3600,
+                    depth: 12.,
+                    dive_site_id: None,
+                    description: String::new(),
+                    published: false,
+                    deco_model: None,
+                    buddy: None,
+                    divemaster: None,
+                    tags: None,
+                    visibility: None,
+                    rating: None,
+                    cylinders: None,
+                    weights: None,
+                },
+                None,
+            )
+            .await
+            .expect("Creates dive");
+
+        let add_photo = |camera: &str, date: DateTime<Local>, dive_id: Option<Uuid>| {
+            let photo = CreatePhoto {
+                id: None,
+                user_id,
+                filename: "test.jpg".into(),
+                date,
+                dive_id,
+                size: 0,
+                internal: None,
+                dive_site_id: None,
+                sealife_id: None,
+                lat: None,
+                lon: None,
+                camera: Some(camera.into()),
+                camera_offset: 0,
+            };
+
+            let handle = &handle;
+
+            async move { handle.add_photo(&photo).await.expect("Adds photo").id }
+        };
+
+        // The first camera's clock is 4 hours behind, so its photo misses the dive
+        let behind = add_photo("First", dive_date - Duration::hours(4), None).await;
+        let linked = add_photo("Second", dive_date, Some(dive.id)).await;
+
+        // An offset on its own would move every camera's photos
+        assert!(handle
+            .rematch_photos(user_id, None, None, Some(3600))
+            .await
+            .is_err());
+
+        let linked_count = handle
+            .rematch_photos(user_id, None, Some("First"), Some(4 * 3600))
+            .await
+            .expect("Rematches photos");
+
+        assert_eq!(linked_count, 1);
+        assert_eq!(
+            photo_date(&handle, behind).await,
+            (dive_date, Some(dive.id), 4 * 3600)
+        );
+        assert_eq!(
+            photo_date(&handle, linked).await,
+            (dive_date, Some(dive.id), 0)
+        );
+
+        // Moving a photo away from every dive keeps the dive it's linked to
+        handle
+            .rematch_photos(user_id, Some(&[linked]), None, Some(-10 * 3600))
+            .await
+            .expect("Rematches photos");
+
+        assert_eq!(
+            photo_date(&handle, linked).await,
+            (dive_date - Duration::hours(10), Some(dive.id), -10 * 3600)
+        );
+
+        // Offsets replace the previous one rather than adding to it
+        handle
+            .rematch_photos(user_id, Some(&[behind]), None, Some(0))
+            .await
+            .expect("Rematches photos");
+
+        assert_eq!(
+            photo_date(&handle, behind).await.0,
+            dive_date - Duration::hours(4)
+        );
+
+        // Without an offset, the camera's saved one is used
+        handle
+            .set_camera_offset(
+                user_id,
+                &CameraOffset {
+                    camera: "First".into(),
+                    clock_offset: 4 * 3600,
+                },
+            )
+            .await
+            .expect("Sets offset");
+
+        handle
+            .rematch_photos(user_id, None, None, None)
+            .await
+            .expect("Rematches photos");
+
+        assert_eq!(photo_date(&handle, behind).await.0, dive_date);
+
+        handle.remove_test_user(user_id).await;
+    }
 }
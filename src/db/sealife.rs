@@ -88,6 +88,68 @@ impl DbHandle {
         Sealife::from_rows(result)
     }
 
+    pub async fn sealife_tags(&self, photo_id: Uuid) -> Result<Vec<SealifeTag>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select sealife_id, u_min, v_min, u_max, v_max from sealife_tags where photo_id = $1 order by u_min, v_min";
+
+        SealifeTag::from_rows(client.query(query, &[&photo_id]).await?)
+    }
+
+    /// The tags of each of the photos, along with the photo they're in
+    pub async fn sealife_tags_batch(
+        &self,
+        photo_ids: &[Uuid],
+    ) -> Result<Vec<(Uuid, SealifeTag)>, Error> {
+        let client = self.pool.get().await?;
+        let query = "select sealife_id, u_min, v_min, u_max, v_max, photo_id from sealife_tags where photo_id = ANY($1) order by u_min, v_min";
+
+        client
+            .query(query, &[&photo_ids])
+            .await?
+            .into_iter()
+            .map(|row| Ok((row.try_get(5)?, SealifeTag::from_row(row)?)))
+            .collect()
+    }
+
+    /// Replaces the species tagged in a photo
+    pub async fn tag_sealife(&self, photo_id: Uuid, tags: &[SealifeTag]) -> Result<(), Error> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        transaction
+            .execute("delete from sealife_tags where photo_id = $1", &[&photo_id])
+            .await?;
+
+        let statement = transaction
+            .prepare(
+                "insert into sealife_tags (sealife_id, photo_id, u_min, v_min, u_max, v_max)
+                values ($1, $2, $3, $4, $5, $6)",
+            )
+            .await?;
+
+        for tag in tags {
+            transaction
+                .execute(
+                    &statement,
+                    &[
+                        &tag.sealife_id,
+                        &photo_id,
+                        &tag.u_min,
+                        &tag.v_min,
+                        &tag.u_max,
+                        &tag.v_max,
+                    ],
+                )
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        self.clear_cache().await;
+
+        Ok(())
+    }
+
     pub async fn sealife(&self, query: &SealifeQuery) -> Result<Vec<Sealife>, Error> {
         let mut sql = StatementBuilder::new("select * from sealife");
 
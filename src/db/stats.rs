@@ -0,0 +1,220 @@
+use anyhow::Error;
+use chrono::Utc;
+use divedb_core::FromRow;
+use postgres_types::ToSql;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{sac::gas_consumption, schema::*};
+
+use super::DbHandle;
+
+impl DbHandle {
+    /// Statistics across a user's dives.  Unpublished dives are only included when `viewer` is the same user
+    pub async fn user_stats(
+        &self,
+        viewer: Option<Uuid>,
+        user_id: Uuid,
+    ) -> Result<DiveStats, Error> {
+        let client = self.pool.get().await?;
+
+        let dives =
+            "select * from dives where user_id = $1 and (published = true or user_id = $2::uuid)";
+        let dive_ids = format!("select id from ({dives}) as visible");
+
+        let params: [&(dyn ToSql + Sync); 2] = [&user_id, &viewer];
+
+        let totals = client
+            .query_one(
+                &format!("select count(*), coalesce(sum(duration), 0)::bigint, avg(depth)::float8 from ({dives}) as visible"),
+                &params,
+            )
+            .await?;
+
+        let deepest_dive = client
+            .query_opt(&format!("{dives} order by depth desc limit 1"), &params)
+            .await?
+            .map(Dive::from_row)
+            .transpose()?;
+
+        let longest_dive = client
+            .query_opt(&format!("{dives} order by duration desc limit 1"), &params)
+            .await?
+            .map(Dive::from_row)
+            .transpose()?;
+
+        let temperatures = client
+            .query_one(
+                &format!("select min(temperature), max(temperature) from dive_metrics where dive_id in ({dive_ids})"),
+                &params,
+            )
+            .await?;
+
+        let dives_per_year = client
+            .query(
+                &format!("select extract(year from date)::integer, count(*) from ({dives}) as visible group by 1 order by 1"),
+                &params,
+            )
+            .await?
+            .iter()
+            .map(|row| YearCount {
+                year: row.get(0),
+                dive_count: row.get(1),
+            })
+            .collect();
+
+        let dives_per_month = client
+            .query(
+                &format!("select extract(year from date)::integer, extract(month from date)::integer, count(*) from ({dives}) as visible group by 1, 2 order by 1, 2"),
+                &params,
+            )
+            .await?
+            .iter()
+            .map(|row| MonthCount {
+                year: row.get(0),
+                month: row.get(1),
+                dive_count: row.get(2),
+            })
+            .collect();
+
+        let depth_histogram = client
+            .query(
+                &format!("select (floor(depth / {DEPTH_BUCKET}) * {DEPTH_BUCKET})::integer, count(*) from ({dives}) as visible group by 1 order by 1"),
+                &params,
+            )
+            .await?
+            .iter()
+            .map(|row| {
+                let min_depth: i32 = row.get(0);
+
+                DepthBucket {
+                    min_depth,
+                    max_depth: min_depth + DEPTH_BUCKET,
+                    dive_count: row.get(1),
+                }
+            })
+            .collect();
+
+        // Mixes come from both the gas changes during the dive and the cylinders that were carried
+        let gas_mixes = client
+            .query(
+                &format!(
+                    "select o2, he, count(distinct dive_id) from (
+                        select dive_id, coalesce(o2, 21) as o2, coalesce(he, 0) as he from dive_metrics
+                            where dive_id in ({dive_ids}) and (o2 is not null or he is not null)
+                        union
+                        select dive_id, coalesce(o2, 21), coalesce(he, 0) from dive_cylinders
+                            where dive_id in ({dive_ids})
+                    ) as mixes
+                    group by 1, 2
+                    order by 3 desc, 1, 2"
+                ),
+                &params,
+            )
+            .await?
+            .iter()
+            .map(|row| GasMixCount {
+                o2: row.get(0),
+                he: row.get(1),
+                dive_count: row.get(2),
+            })
+            .collect();
+
+        let top_sites = client
+            .query(
+                &format!("select dive_site_id, count(*) from ({dives}) as visible where dive_site_id is not null group by 1 order by 2 desc, 1 limit 5"),
+                &params,
+            )
+            .await?
+            .iter()
+            .map(|row| SiteCount {
+                dive_site_id: row.get(0),
+                dive_count: row.get(1),
+            })
+            .collect();
+
+        Ok(DiveStats {
+            dive_count: totals.get(0),
+            total_bottom_time: totals.get(1),
+            average_depth: totals.get(2),
+            deepest_dive,
+            longest_dive,
+            min_temperature: temperatures.get(0),
+            max_temperature: temperatures.get(1),
+            dives_per_year,
+            dives_per_month,
+            depth_histogram,
+            gas_mixes,
+            top_sites,
+        })
+    }
+
+    /// Gas consumption of the latest dives that have pressure data, oldest first
+    pub async fn user_sac_trend(
+        &self,
+        viewer: Option<Uuid>,
+        user_id: Uuid,
+    ) -> Result<Vec<SacTrendPoint>, Error> {
+        let client = self.pool.get().await?;
+
+        let query = "select * from dives where user_id = $1 and (published = true or user_id = $2::uuid)
+            and (
+                id in (select dive_id from dive_metrics where computer = 0 and pressure is not null)
+                or id in (select dive_id from dive_cylinders where idx = 0 and start_pressure is not null and end_pressure is not null)
+            )
+            order by date desc
+            limit 50";
+
+        let dives = Dive::from_rows(client.query(query, &[&user_id, &viewer]).await?)?;
+
+        let dive_ids = dives.iter().map(|dive| dive.id).collect::<Vec<_>>();
+
+        let mut metrics: HashMap<Uuid, Vec<DiveMetric>> = HashMap::new();
+
+        for row in client
+            .query(
+                "select dive_id, time, depth, pressure from dive_metrics where computer = 0 and dive_id = any($1) order by time asc",
+                &[&dive_ids],
+            )
+            .await?
+        {
+            metrics.entry(row.get(0)).or_default().push(DiveMetric {
+                time: row.get(1),
+                depth: row.get(2),
+                pressure: row.get(3),
+                ..Default::default()
+            });
+        }
+
+        let mut cylinders = HashMap::new();
+
+        for row in client
+            .query(
+                "select description, size, working_pressure, start_pressure, end_pressure, o2, he, dive_id from dive_cylinders where idx = 0 and dive_id = any($1)",
+                &[&dive_ids],
+            )
+            .await?
+        {
+            let dive_id: Uuid = row.get(7);
+            cylinders.insert(dive_id, DiveCylinder::from_row(row)?);
+        }
+
+        Ok(dives
+            .iter()
+            .rev()
+            .filter_map(|dive| {
+                let consumption = gas_consumption(
+                    metrics.get(&dive.id).map(Vec::as_slice).unwrap_or_default(),
+                    cylinders.get(&dive.id),
+                )?;
+
+                Some(SacTrendPoint {
+                    dive_id: dive.id,
+                    date: dive.date.with_timezone(&Utc),
+                    sac: consumption.sac,
+                    rmv: consumption.rmv,
+                })
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::Error;
+use divedb_core::FromRow;
+use uuid::Uuid;
+
+use crate::schema::*;
+
+use super::{DbHandle, StatementBuilder};
+
+impl DbHandle {
+    pub async fn subsurface_sync(&self, user_id: Uuid) -> Result<Option<SubsurfaceSync>, Error> {
+        let mut sql = StatementBuilder::new("select * from subsurface_sync");
+
+        sql.add_param("user_id = ${}", &user_id);
+
+        Ok(SubsurfaceSync::from_rows(self.query(sql).await?)?
+            .into_iter()
+            .next())
+    }
+
+    pub async fn update_subsurface_sync(&self, sync: &SubsurfaceSync) -> Result<(), Error> {
+        let client = self.pool.get().await?;
+        let query = "insert into subsurface_sync (user_id, remote_url, branch, commit_id)
+            values ($1, $2, $3, $4)
+            on conflict(user_id) do update
+                set remote_url = excluded.remote_url,
+                    branch = excluded.branch,
+                    commit_id = excluded.commit_id,
+                    date = now()";
+
+        client
+            .execute(
+                query,
+                &[
+                    &sync.user_id,
+                    &sync.remote_url,
+                    &sync.branch,
+                    &sync.commit_id,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Creates a job run by `worker`, or returns `None` if the user already has an unfinished one
+    pub async fn create_sync_job(
+        &self,
+        user_id: Uuid,
+        worker: Uuid,
+    ) -> Result<Option<SyncJob>, Error> {
+        let id = Uuid::new_v4();
+        let client = self.pool.get().await?;
+        let query = "insert into sync_jobs (id, user_id, worker) values ($1, $2, $3)
+            on conflict (user_id) where state not in ('Done', 'Failed') do nothing
+            returning *";
+        let result = client.query_opt(query, &[&id, &user_id, &worker]).await?;
+
+        result.map(SyncJob::from_row).transpose()
+    }
+
+    pub async fn update_sync_job(
+        &self,
+        id: Uuid,
+        state: SyncState,
+        error: Option<&str>,
+    ) -> Result<SyncJob, Error> {
+        let client = self.pool.get().await?;
+        let query = "update sync_jobs set state = $2, error = $3, updated = now()
+            where id = $1 returning *";
+        let result = client.query_one(query, &[&id, &state, &error]).await?;
+
+        SyncJob::from_row(result)
+    }
+
+    pub async fn finish_sync_job(
+        &self,
+        id: Uuid,
+        summary: &ImportSummary,
+    ) -> Result<SyncJob, Error> {
+        let client = self.pool.get().await?;
+        let query = "update sync_jobs set state = $2,
+                sites_created = $3,
+                sites_skipped = $4,
+                dives_created = $5,
+                dives_updated = $6,
+                dives_skipped = $7,
+                updated = now()
+            where id = $1 returning *";
+        let result = client
+            .query_one(
+                query,
+                &[
+                    &id,
+                    &SyncState::Done,
+                    &summary.sites_created,
+                    &summary.sites_skipped,
+                    &summary.dives_created,
+                    &summary.dives_updated,
+                    &summary.dives_skipped,
+                ],
+            )
+            .await?;
+
+        SyncJob::from_row(result)
+    }
+
+    pub async fn sync_jobs(&self, user_id: Uuid, id: Option<Uuid>) -> Result<Vec<SyncJob>, Error> {
+        let mut sql = StatementBuilder::new("select * from sync_jobs");
+
+        sql.add_param("user_id = ${}", &user_id);
+
+        if let Some(ref id) = id {
+            sql.add_param("id = ${}", id);
+        }
+
+        sql.add_sql("order by created desc limit 20");
+
+        SyncJob::from_rows(self.query(sql).await?)
+    }
+
+    /// Marks the unfinished jobs of `worker` as still alive
+    pub async fn touch_sync_jobs(&self, worker: Uuid) -> Result<u64, Error> {
+        let client = self.pool.get().await?;
+        let query = "update sync_jobs set updated = now()
+            where worker = $1 and state not in ('Done', 'Failed')";
+
+        Ok(client.execute(query, &[&worker]).await?)
+    }
+
+    // Credentials only live in memory, so jobs whose worker has stopped touching them can't be resumed.
+    // Other replicas keep their own jobs fresh, so only abandoned jobs go stale
+    pub async fn fail_stale_sync_jobs(&self) -> Result<u64, Error> {
+        let client = self.pool.get().await?;
+        let query = "update sync_jobs set state = 'Failed', error = 'Interrupted by a restart', updated = now()
+            where state not in ('Done', 'Failed') and updated < now() - interval '5 minutes'";
+
+        Ok(client.execute(query, &[]).await?)
+    }
+}
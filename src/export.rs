@@ -0,0 +1,739 @@
+use std::fmt::Write;
+
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, Error as ActixError, HttpResponse,
+};
+use anyhow::Error;
+use bytes::Bytes;
+use chrono::prelude::*;
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    escape::escape,
+    graphql::WebContext,
+    log_error,
+    schema::{Dive, DiveMetric, DiveQuery, DiveSite},
+    token::Token,
+};
+
+#[derive(Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Uddf,
+    Subsurface,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Uddf => "uddf",
+            ExportFormat::Subsurface => "xml",
+        }
+    }
+
+    /// Closes off everything opened by the header
+    fn footer(&self) -> &'static str {
+        match self {
+            ExportFormat::Uddf => "</repetitiongroup>\n</profiledata>\n</uddf>\n",
+            ExportFormat::Subsurface => "</dives>\n</divelog>\n",
+        }
+    }
+}
+
+/// Streams all of the logged in user's dives, sites & profiles in the requested format
+pub async fn export_dives(
+    format: web::Path<ExportFormat>,
+    context: web::Data<WebContext>,
+    token: Token,
+) -> Result<HttpResponse, ActixError> {
+    let format = format.into_inner();
+
+    let user = match token.user_id {
+        Some(id) => context.handle.user_details(id).await.map_err(log_error)?,
+        None => return Ok(HttpResponse::Unauthorized().body("Unauthorized")),
+    };
+
+    let mut dives = context
+        .handle
+        .dives(
+            Some(user.id),
+            &DiveQuery {
+                user_id: Some(user.id),
+                limit: Some(i64::MAX as usize),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(log_error)?;
+
+    // Export in logged order, oldest first
+    dives.reverse();
+
+    let mut site_ids = dives
+        .iter()
+        .filter_map(|dive| dive.dive_site_id)
+        .collect::<Vec<_>>();
+
+    site_ids.sort();
+    site_ids.dedup();
+
+    let sites = context
+        .handle
+        .dive_sites_batch(&site_ids)
+        .await
+        .map_err(log_error)?;
+
+    let header = match format {
+        ExportFormat::Uddf => {
+            let mixes = context
+                .handle
+                .dive_gas_mixes(user.id)
+                .await
+                .map_err(log_error)?;
+
+            uddf_header(&sites, &mixes)
+        }
+        ExportFormat::Subsurface => subsurface_header(&sites),
+    };
+
+    let footer = format.footer();
+
+    let dive_stream = stream::iter(dives).then(move |dive| {
+        let context = context.clone();
+        async move {
+            let metrics = context.handle.dive_metrics(dive.id).await?;
+
+            let output = match format {
+                ExportFormat::Uddf => uddf_dive(&dive, &metrics),
+                ExportFormat::Subsurface => subsurface_dive(&dive, &metrics),
+            };
+
+            Ok::<_, Error>(Bytes::from(output))
+        }
+    });
+
+    let body = stream::once(async move { Ok::<_, Error>(Bytes::from(header)) })
+        .chain(dive_stream)
+        .chain(stream::once(async move { Ok(Bytes::from(footer)) }));
+
+    let filename = format!("divedb-{}.{}", user.username, format.extension());
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/xml")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(body))
+}
+
+fn mix_id(o2: f32, he: f32) -> String {
+    format!("mix_{:.0}_{:.0}", o2, he)
+}
+
+// Normalises the gas recorded against a metric, if any
+fn metric_gas(metric: &DiveMetric) -> Option<(f32, f32)> {
+    if metric.o2.is_none() && metric.he.is_none() {
+        return None;
+    }
+
+    Some((metric.o2.unwrap_or(21.), metric.he.unwrap_or(0.)))
+}
+
+fn uddf_header(sites: &[DiveSite], mixes: &[(f32, f32)]) -> String {
+    let mut output = String::new();
+
+    writeln!(output, r#"<?xml version="1.0" encoding="UTF-8"?>"#).ok();
+    writeln!(
+        output,
+        r#"<uddf xmlns="http://www.streit.cc/uddf/3.2/" version="3.2.0">"#
+    )
+    .ok();
+    writeln!(
+        output,
+        "<generator><name>DiveDB</name><type>logbook</type><datetime>{}</datetime></generator>",
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    )
+    .ok();
+
+    writeln!(output, "<gasdefinitions>").ok();
+
+    for (o2, he) in mixes {
+        writeln!(
+            output,
+            "<mix id=\"{}\"><name>{}</name><o2>{:.3}</o2><he>{:.3}</he></mix>",
+            mix_id(*o2, *he),
+            escape(&gas_name(*o2, *he)),
+            o2 / 100.,
+            he / 100.
+        )
+        .ok();
+    }
+
+    writeln!(output, "</gasdefinitions>").ok();
+
+    writeln!(output, "<divesite>").ok();
+
+    for site in sites {
+        writeln!(output, "<site id=\"site_{}\">", site.id).ok();
+        writeln!(output, "<name>{}</name>", escape(&site.name)).ok();
+        writeln!(
+            output,
+            "<geography><latitude>{}</latitude><longitude>{}</longitude></geography>",
+            site.lat, site.lon
+        )
+        .ok();
+
+        if !site.description.is_empty() {
+            writeln!(
+                output,
+                "<notes><para>{}</para></notes>",
+                escape(&site.description)
+            )
+            .ok();
+        }
+
+        writeln!(output, "</site>").ok();
+    }
+
+    writeln!(output, "</divesite>").ok();
+    writeln!(output, "<profiledata>").ok();
+    writeln!(output, "<repetitiongroup id=\"divedb\">").ok();
+
+    output
+}
+
+fn uddf_dive(dive: &Dive, metrics: &[DiveMetric]) -> String {
+    let mut output = String::new();
+
+    writeln!(output, "<dive id=\"dive_{}\">", dive.id).ok();
+    writeln!(output, "<informationbeforedive>").ok();
+
+    if let Some(site_id) = dive.dive_site_id {
+        writeln!(output, "<link ref=\"site_{site_id}\"/>").ok();
+    }
+
+    writeln!(output, "<divenumber>{}</divenumber>", dive.dive_number).ok();
+    writeln!(
+        output,
+        "<datetime>{}</datetime>",
+        dive.date.format("%Y-%m-%dT%H:%M:%S")
+    )
+    .ok();
+    writeln!(output, "</informationbeforedive>").ok();
+
+    writeln!(output, "<samples>").ok();
+
+    for metric in metrics {
+        write!(output, "<waypoint>").ok();
+        write!(output, "<depth>{:.2}</depth>", metric.depth).ok();
+        write!(output, "<divetime>{}</divetime>", metric.time).ok();
+
+        if let Some((o2, he)) = metric_gas(metric) {
+            write!(output, "<switchmix ref=\"{}\"/>", mix_id(o2, he)).ok();
+        }
+
+        if let Some(pressure) = metric.pressure {
+            write!(
+                output,
+                "<tankpressure>{:.0}</tankpressure>",
+                pressure * 100000.
+            )
+            .ok();
+        }
+
+        if let Some(temperature) = metric.temperature {
+            write!(
+                output,
+                "<temperature>{:.2}</temperature>",
+                temperature + 273.15
+            )
+            .ok();
+        }
+
+        writeln!(output, "</waypoint>").ok();
+    }
+
+    writeln!(output, "</samples>").ok();
+
+    writeln!(output, "<informationafterdive>").ok();
+    writeln!(output, "<greatestdepth>{:.2}</greatestdepth>", dive.depth).ok();
+    writeln!(output, "<diveduration>{}</diveduration>", dive.duration).ok();
+
+    if !dive.description.is_empty() {
+        writeln!(
+            output,
+            "<notes><para>{}</para></notes>",
+            escape(&dive.description)
+        )
+        .ok();
+    }
+
+    writeln!(output, "</informationafterdive>").ok();
+    writeln!(output, "</dive>").ok();
+
+    output
+}
+
+// Subsurface identifies sites by a 32 bit hex id, which is the first field of our site uuids
+fn subsurface_site_id(id: &Uuid) -> String {
+    format!("{:08x}", id.as_fields().0)
+}
+
+fn subsurface_header(sites: &[DiveSite]) -> String {
+    let mut output = String::new();
+
+    writeln!(output, "<divelog program='subsurface' version='3'>").ok();
+    writeln!(output, "<divesites>").ok();
+
+    for site in sites {
+        write!(
+            output,
+            "<site uuid='{}' name='{}' gps='{:.6} {:.6}'",
+            subsurface_site_id(&site.id),
+            escape(&site.name),
+            site.lat,
+            site.lon
+        )
+        .ok();
+
+        if !site.description.is_empty() {
+            write!(output, " description='{}'", escape(&site.description)).ok();
+        }
+
+        writeln!(output, "/>").ok();
+    }
+
+    writeln!(output, "</divesites>").ok();
+    writeln!(output, "<dives>").ok();
+
+    output
+}
+
+fn subsurface_dive(dive: &Dive, metrics: &[DiveMetric]) -> String {
+    let mut output = String::new();
+
+    write!(
+        output,
+        "<dive number='{}' date='{}' time='{}' duration='{} min'",
+        dive.dive_number,
+        dive.date.format("%Y-%m-%d"),
+        dive.date.format("%H:%M:%S"),
+        subsurface_time(dive.duration)
+    )
+    .ok();
+
+    if let Some(site_id) = dive.dive_site_id {
+        write!(output, " divesiteid='{}'", subsurface_site_id(&site_id)).ok();
+    }
+
+    writeln!(output, ">").ok();
+
+    if !dive.description.is_empty() {
+        writeln!(output, "<notes>{}</notes>", escape(&dive.description)).ok();
+    }
+
+    writeln!(output, "<divecomputer model='DiveDB'>").ok();
+    writeln!(output, "<depth max='{:.2} m' />", dive.depth).ok();
+
+    if let Some(ref deco_model) = dive.deco_model {
+        writeln!(
+            output,
+            "<extradata key='Deco model' value='{}' />",
+            escape(deco_model)
+        )
+        .ok();
+    }
+
+    for metric in metrics {
+        if let Some((o2, he)) = metric_gas(metric) {
+            // Subsurface packs the helium percentage into the upper 16 bits of the gas change value
+            let value = o2.round() as u32 | ((he.round() as u32) << 16);
+
+            writeln!(
+                output,
+                "<event time='{} min' type='25' value='{}' name='gaschange' />",
+                subsurface_time(metric.time),
+                value
+            )
+            .ok();
+        }
+    }
+
+    for metric in metrics {
+        write!(
+            output,
+            "<sample time='{} min' depth='{:.2} m'",
+            subsurface_time(metric.time),
+            metric.depth
+        )
+        .ok();
+
+        if let Some(temperature) = metric.temperature {
+            write!(output, " temp='{:.1} C'", temperature).ok();
+        }
+
+        if let Some(pressure) = metric.pressure {
+            write!(output, " pressure='{:.1} bar'", pressure).ok();
+        }
+
+        writeln!(output, " />").ok();
+    }
+
+    writeln!(output, "</divecomputer>").ok();
+    writeln!(output, "</dive>").ok();
+
+    output
+}
+
+// Subsurface times are `mm:ss`, with minutes not wrapping over to hours
+fn subsurface_time(seconds: i32) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn gas_name(o2: f32, he: f32) -> String {
+    if he > 0. {
+        format!("{:.0}/{:.0}", o2, he)
+    } else if o2 == 21. {
+        "Air".into()
+    } else {
+        format!("EAN{:.0}", o2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::DbHandle,
+        schema::Difficulty,
+        schema::{CreateDive, DiveComputer, DiveProfile, SensorPressure},
+        subsurface::DIVE_NAMESPACE,
+        uddf::{parse_uddf, skip_existing_dives},
+    };
+
+    #[test]
+    fn test_uddf_round_trip() {
+        let user_id = Uuid::new_v4();
+
+        let site = DiveSite {
+            id: Uuid::new_v4(),
+            user_id: Some(user_id),
+            name: "Rapid Bay <Jetty>".into(),
+            description: "Leafy sea dragons & more".into(),
+            access: String::new(),
+            difficulty: Difficulty::OW,
+            depth: 8.,
+            lat: -35.521,
+            lon: 138.186,
+            published: true,
+            photo_id: None,
+            date: Local::now(),
+            slug: None,
+        };
+
+        let dive = Dive {
+            id: Uuid::new_v4(),
+            user_id,
+            date: Local.with_ymd_and_hms(2023, 1, 14, 9, 30, 0).unwrap(),
+            duration: 120,
+            depth: 12.5,
+            dive_site_id: Some(site.id),
+            dive_number: 7,
+            description: "Saw a dragon".into(),
+            published: true,
+            deco_model: None,
+            buddy: String::new(),
+            divemaster: String::new(),
+            tags: Vec::new(),
+            visibility: 0,
+            rating: 0,
+        };
+
+        let metrics = vec![
+            DiveMetric {
+                time: 0,
+                depth: 0.,
+                pressure: Some(200.),
+                temperature: Some(18.5),
+                o2: Some(32.),
+                he: None,
+                ..Default::default()
+            },
+            DiveMetric {
+                time: 60,
+                depth: 12.5,
+                pressure: Some(180.),
+                temperature: None,
+                o2: None,
+                he: None,
+                ..Default::default()
+            },
+            DiveMetric {
+                time: 120,
+                depth: 0.,
+                pressure: None,
+                temperature: None,
+                o2: None,
+                he: None,
+                ..Default::default()
+            },
+        ];
+
+        let header = uddf_header(std::slice::from_ref(&site), &[(32., 0.)]);
+
+        // The gas definitions come before the sites
+        assert!(header.find("<gasdefinitions>") < header.find("<divesite>"));
+
+        let output = format!(
+            "{header}{}{}",
+            uddf_dive(&dive, &metrics),
+            ExportFormat::Uddf.footer()
+        );
+
+        let repo = parse_uddf(user_id, "test@example.com", &output).expect("Parses the export");
+
+        assert_eq!(repo.sites.len(), 1);
+        let parsed_site = &repo.sites[0];
+        assert_eq!(parsed_site.name, site.name);
+        assert_eq!(parsed_site.description, site.description);
+        assert!((parsed_site.lat - site.lat).abs() < 1e-6);
+        assert!((parsed_site.lon - site.lon).abs() < 1e-6);
+
+        assert_eq!(repo.dives.len(), 1);
+        let parsed = &repo.dives[0];
+        assert_eq!(parsed.date, dive.date);
+        assert_eq!(parsed.duration, dive.duration);
+        assert_eq!(parsed.depth, dive.depth);
+        assert_eq!(parsed.description, dive.description);
+        assert_eq!(parsed.dive_site_id, Some(parsed_site.id));
+
+        let parsed_metrics = &repo.dive_metrics[&parsed.id];
+        assert_eq!(parsed_metrics.len(), metrics.len());
+
+        for (parsed, metric) in parsed_metrics.iter().zip(metrics.iter()) {
+            assert_eq!(parsed.time, metric.time);
+            assert_eq!(parsed.depth, metric.depth);
+            assert_eq!(parsed.pressure, metric.pressure);
+            assert_eq!(parsed.o2, metric.o2);
+            assert_eq!(
+                parsed.temperature.map(|val| (val * 100.).round()),
+                metric.temperature.map(|val| (val * 100.).round())
+            );
+        }
+    }
+
+    #[test]
+    fn test_uddf_reimport_synced_dive() {
+        let user_id = Uuid::new_v4();
+
+        // Synced dives get their id from the subsurface dive number
+        let dive_ns = Uuid::new_v5(&DIVE_NAMESPACE, b"test@example.com");
+        let (_, d2, d3, d4) = dive_ns.as_fields();
+
+        let dive = Dive {
+            id: Uuid::from_fields(42, d2, d3, d4),
+            user_id,
+            date: Local.with_ymd_and_hms(2023, 1, 14, 9, 30, 0).unwrap(),
+            duration: 120,
+            depth: 12.5,
+            dive_site_id: None,
+            dive_number: 42,
+            description: String::new(),
+            published: false,
+            deco_model: None,
+            buddy: String::new(),
+            divemaster: String::new(),
+            tags: Vec::new(),
+            visibility: 0,
+            rating: 0,
+        };
+
+        let metrics = vec![DiveMetric {
+            time: 60,
+            depth: 12.5,
+            ..Default::default()
+        }];
+
+        let output = format!(
+            "{}{}{}",
+            uddf_header(&[], &[]),
+            uddf_dive(&dive, &metrics),
+            ExportFormat::Uddf.footer()
+        );
+
+        let mut repo = parse_uddf(user_id, "test@example.com", &output).expect("Parses the export");
+
+        assert_ne!(repo.dives[0].id, dive.id);
+
+        // Updating the synced dive from the export would lose what UDDF doesn't carry
+        assert_eq!(
+            skip_existing_dives(&mut repo, std::slice::from_ref(&dive)),
+            1
+        );
+        assert!(repo.dives.is_empty());
+        assert!(repo.dive_metrics.is_empty());
+
+        // Whereas a dive from importing the same file again is updated
+        let mut repo = parse_uddf(user_id, "test@example.com", &output).expect("Parses the export");
+        let imported = repo.dives.clone();
+
+        assert_eq!(skip_existing_dives(&mut repo, &imported), 0);
+        assert_eq!(repo.dives.len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a postgres database at TEST_CONNECT_URL"]
+    async fn test_uddf_round_trip_keeps_profile() {
+        let (handle, user_id) = DbHandle::test_handle().await;
+
+        let dive_ns = Uuid::new_v5(&DIVE_NAMESPACE, user_id.to_string().as_bytes());
+        let (_, d2, d3, d4) = dive_ns.as_fields();
+
+        let metric = |computer, time, depth| DiveMetric {
+            time,
+            depth,
+            computer,
+            ppo2: Some(1.2),
+            setpoint: Some(1.3),
+            cns: Some(4.),
+            ndl: Some(600),
+            tts: Some(60),
+            ..Default::default()
+        };
+
+        let profile = DiveProfile {
+            metrics: vec![
+                metric(0, 0, 0.),
+                metric(0, 60, 12.345),
+                metric(0, 120, 0.),
+                metric(1, 0, 0.),
+                metric(1, 60, 12.4),
+            ],
+            computers: vec![
+                DiveComputer {
+                    computer: 0,
+                    model: Some("Perdix".into()),
+                    serial: Some("1234".into()),
+                },
+                DiveComputer {
+                    computer: 1,
+                    model: Some("Teric".into()),
+                    serial: None,
+                },
+            ],
+            pressures: vec![SensorPressure {
+                computer: 0,
+                time: 60,
+                sensor: 1,
+                pressure: 150.,
+            }],
+        };
+
+        // As a subsurface sync would store it
+        let dive = handle
+            .create_dive(
+                user_id,
+                &CreateDive {
+                    id: Some(Uuid::from_fields(42, d2, d3, d4)),
+                    date: Local
+                        .with_ymd_and_hms(2023, 1, 14, 9, 30, 0)
+                        .unwrap()
+                        .into(),
+                    duration: 120,
+                    depth: 12.345,
+                    dive_site_id: None,
+                    description: String::new(),
+                    published: false,
+                    deco_model: Some("GF 30/70".into()),
+                    buddy: None,
+                    divemaster: None,
+                    tags: None,
+                    visibility: None,
+                    rating: None,
+                    cylinders: None,
+                    weights: None,
+                },
+                Some(profile.clone()),
+            )
+            .await
+            .expect("Creates dive");
+
+        let metrics = handle.dive_metrics(dive.id).await.unwrap();
+
+        let output = format!(
+            "{}{}{}",
+            uddf_header(&[], &[]),
+            uddf_dive(&dive, &metrics),
+            ExportFormat::Uddf.footer()
+        );
+
+        let repo = parse_uddf(user_id, &user_id.to_string(), &output).expect("Parses the export");
+
+        let summary = crate::uddf::import_repository(user_id, repo, handle.clone())
+            .await
+            .expect("Imports the export");
+
+        assert_eq!(summary.dives_created, 0);
+        assert_eq!(summary.dives_skipped, 1);
+
+        let stored = handle.dive(dive.id).await.unwrap();
+
+        assert_eq!(stored.deco_model.as_deref(), Some("GF 30/70"));
+        assert_eq!(stored.depth, dive.depth);
+        let details = |metrics: Vec<DiveMetric>| {
+            metrics
+                .into_iter()
+                .map(|val| {
+                    (
+                        val.time,
+                        val.depth,
+                        val.ppo2,
+                        val.setpoint,
+                        val.cns,
+                        val.ndl,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            details(handle.dive_metrics(dive.id).await.unwrap()),
+            details(metrics)
+        );
+        assert_eq!(
+            handle
+                .dive_computer_metrics(dive.id, 1)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            handle.dive_computers(dive.id).await.unwrap(),
+            profile.computers
+        );
+        assert_eq!(
+            handle.dive_sensor_pressures(dive.id, 0).await.unwrap(),
+            profile.pressures
+        );
+
+        let dives = handle
+            .dives(
+                Some(user_id),
+                &DiveQuery {
+                    user_id: Some(user_id),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(dives.len(), 1);
+
+        handle.remove_test_user(user_id).await;
+    }
+}
@@ -0,0 +1,321 @@
+use anyhow::{anyhow, Result};
+use dive_deco::Gas;
+
+use crate::{
+    plan::{surface_pressure, Water, DEFAULT_MAX_PPO2},
+    schema::{BestMixInput, BlendStep, GasBlend, GasBlendInput, GasInput, GasOutput},
+};
+
+/// The equivalent narcotic depth in meters best mixes are kept to, when there's no limit given
+pub const DEFAULT_MAX_END: f32 = 30.;
+
+/// The fraction of air that's nitrogen, for narcotic depths that don't count oxygen as narcotic
+const AIR_NITROGEN: f32 = 0.79;
+
+/// Blending steps smaller than this many bar are left out
+const MIN_STEP: f32 = 0.05;
+
+/// Converts a `GasInput`, checking it's a mix a `Gas` can be made from without panicking
+pub fn checked_gas(input: GasInput) -> Result<Gas> {
+    // Checked the same way `Gas::new` does, so rounding can't sneak a mix past this
+    let (o2, he) = ((input.o2 / 100.) as f64, (input.he / 100.) as f64);
+
+    if !(0. ..=1.).contains(&o2) || !(0. ..=1.).contains(&he) || o2 + he > 1. {
+        return Err(anyhow!(
+            "Gas percentages can't be negative, and the oxygen & helium can't add up to more than 100"
+        ));
+    }
+
+    Ok(Gas::from(input))
+}
+
+fn water(salinity: Option<f32>, altitude: Option<f32>) -> Result<Water> {
+    let surface_pressure = surface_pressure(altitude.unwrap_or_default());
+
+    if !(500..=1500).contains(&surface_pressure) {
+        return Err(anyhow!("Altitude is too high to calculate for"));
+    }
+
+    if salinity.is_some_and(|val| val <= 0.) {
+        return Err(anyhow!("Salinity must be above 0"));
+    }
+
+    Ok(Water::new(salinity, surface_pressure))
+}
+
+fn check_depth(depth: f32) -> Result<()> {
+    if depth < 0. {
+        return Err(anyhow!("Depth can't be negative"));
+    }
+
+    Ok(())
+}
+
+/// The deepest `gas` can be breathed without its ppO2 going over `max_ppo2`, in whole meters
+pub fn max_operating_depth(
+    gas: GasInput,
+    max_ppo2: Option<f32>,
+    salinity: Option<f32>,
+    altitude: Option<f32>,
+) -> Result<f32> {
+    let gas = checked_gas(gas)?;
+    let max_ppo2 = max_ppo2.unwrap_or(DEFAULT_MAX_PPO2);
+
+    if GasOutput::from(gas).o2 <= 0. {
+        return Err(anyhow!(
+            "A gas without oxygen has no maximum operating depth"
+        ));
+    }
+
+    if max_ppo2 <= 0. {
+        return Err(anyhow!("The max ppO2 must be above 0"));
+    }
+
+    Ok(water(salinity, altitude)?.mod_depth(gas, max_ppo2))
+}
+
+/// The depth breathing air that's as narcotic as breathing `gas` at `depth`.  Oxygen is counted as narcotic unless `oxygen_narcotic` is false
+pub fn equivalent_narcotic_depth(
+    gas: GasInput,
+    depth: f32,
+    oxygen_narcotic: bool,
+    salinity: Option<f32>,
+    altitude: Option<f32>,
+) -> Result<f32> {
+    check_depth(depth)?;
+
+    let gas = GasOutput::from(checked_gas(gas)?);
+    let water = water(salinity, altitude)?;
+
+    let narcotic = if oxygen_narcotic {
+        1. - gas.he / 100.
+    } else {
+        (1. - (gas.o2 + gas.he) / 100.) / AIR_NITROGEN
+    };
+
+    Ok(water
+        .pressure_depth(water.pressure(depth) * narcotic)
+        .max(0.))
+}
+
+/// The mix with the most oxygen that stays within `max_ppo2` at `depth`, with just enough helium to keep the narcotic depth within `max_end`.
+///
+/// The oxygen is rounded down & the helium up to whole percentages.  When the narcotic depth can't be reached, this is the mix that gets closest
+pub fn best_mix(input: BestMixInput) -> Result<GasOutput> {
+    let depth = input.depth;
+    check_depth(depth)?;
+
+    let max_ppo2 = input.max_ppo2.unwrap_or(DEFAULT_MAX_PPO2);
+    let max_end = input.max_end.unwrap_or(DEFAULT_MAX_END);
+
+    if max_ppo2 <= 0. {
+        return Err(anyhow!("The max ppO2 must be above 0"));
+    }
+
+    check_depth(max_end)?;
+
+    let water = water(input.salinity, input.altitude)?;
+    let ambient = water.pressure(depth);
+
+    let o2 = (max_ppo2 / ambient * 100.).min(100.).floor();
+
+    // The most of the mix that can be narcotic
+    let narcotic = (water.pressure(max_end) / ambient).min(1.);
+
+    let he = if input.oxygen_narcotic.unwrap_or(true) {
+        (1. - narcotic) * 100.
+    } else {
+        100. - o2 - narcotic * AIR_NITROGEN * 100.
+    };
+
+    Ok(GasOutput {
+        o2,
+        he: he.ceil().clamp(0., 100. - o2),
+    })
+}
+
+/// The fractions of oxygen, helium & nitrogen in a gas
+fn fractions(gas: Gas) -> (f32, f32, f32) {
+    let pp = gas.gas_pressures_compound(1.);
+
+    (pp.o2 as f32, pp.he as f32, pp.n2 as f32)
+}
+
+/// How to partial pressure blend from the current fill to the target, adding helium, then oxygen, then topping up.
+///
+/// When the current fill has too much of a gas in it, the cylinder is drained by as little as possible first
+pub fn blend(input: GasBlendInput) -> Result<GasBlend> {
+    if input.current_pressure < 0. || input.target_pressure <= 0. {
+        return Err(anyhow!(
+            "The current pressure can't be negative & the target pressure must be above 0"
+        ));
+    }
+
+    let target = checked_gas(input.target)?;
+    let top_up = input
+        .top_up
+        .map(checked_gas)
+        .transpose()?
+        .unwrap_or(Gas::air());
+
+    let (current_o2, current_he, current_n2) = fractions(checked_gas(input.current)?);
+    let (target_o2, target_he, target_n2) = fractions(target);
+    let (top_up_o2, top_up_he, top_up_n2) = fractions(top_up);
+
+    if top_up_n2 <= 0. {
+        return Err(anyhow!("The top up gas needs nitrogen in it"));
+    }
+
+    // With the cylinder drained to `k` of its current pressure, each gas added is `a - b * k` bar
+    let top_up_bar = |k: f32| {
+        (target_n2 * input.target_pressure - k * current_n2 * input.current_pressure) / top_up_n2
+    };
+
+    let helium_bar = |k: f32| {
+        target_he * input.target_pressure
+            - k * current_he * input.current_pressure
+            - top_up_bar(k) * top_up_he
+    };
+
+    let oxygen_bar = |k: f32| {
+        target_o2 * input.target_pressure
+            - k * current_o2 * input.current_pressure
+            - top_up_bar(k) * top_up_o2
+    };
+
+    let mut k: f32 = 1.;
+
+    for added in [&top_up_bar as &dyn Fn(f32) -> f32, &helium_bar, &oxygen_bar] {
+        let (a, b) = (added(0.), added(0.) - added(1.));
+
+        // Even an empty cylinder would end up with too much of this gas
+        if a < -MIN_STEP {
+            return Err(anyhow!(
+                "{} can't be blended by topping up with {}",
+                GasOutput::from(target),
+                GasOutput::from(top_up)
+            ));
+        }
+
+        if b > 0. {
+            k = k.min(a / b);
+        }
+    }
+
+    let k = k.max(0.);
+    let drain_to = k * input.current_pressure;
+
+    let mut pressure = drain_to;
+    let mut steps = Vec::new();
+
+    for (gas, added) in [
+        (Gas::new(0., 1.), helium_bar(k)),
+        (Gas::new(1., 0.), oxygen_bar(k)),
+        (top_up, top_up_bar(k)),
+    ] {
+        if added < MIN_STEP {
+            continue;
+        }
+
+        pressure += added;
+
+        steps.push(BlendStep {
+            gas: gas.into(),
+            added,
+            pressure,
+        });
+    }
+
+    Ok(GasBlend { drain_to, steps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gas(o2: f32, he: f32) -> GasInput {
+        GasInput {
+            o2,
+            he,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gas_calculators() {
+        assert_eq!(
+            max_operating_depth(gas(32., 0.), None, None, None).unwrap(),
+            33.
+        );
+
+        let end = equivalent_narcotic_depth(gas(21., 35.), 50., true, None, None).unwrap();
+
+        assert!((end - 28.95).abs() < 0.01, "{end}");
+
+        let mix = best_mix(BestMixInput {
+            depth: 60.,
+            max_ppo2: None,
+            max_end: None,
+            oxygen_narcotic: None,
+            salinity: None,
+            altitude: None,
+        })
+        .unwrap();
+
+        assert_eq!((mix.o2, mix.he), (19., 43.));
+
+        assert!(checked_gas(gas(80., 30.)).is_err());
+    }
+
+    #[test]
+    fn test_blend() {
+        let fill = blend(GasBlendInput {
+            current: gas(21., 0.),
+            current_pressure: 0.,
+            target: gas(21., 35.),
+            target_pressure: 200.,
+            top_up: None,
+        })
+        .unwrap();
+
+        let steps = fill
+            .steps
+            .iter()
+            .map(|step| (step.gas.to_string(), step.added.round()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            steps,
+            vec![
+                ("0/100".to_string(), 70.),
+                ("EAN100".to_string(), 19.),
+                ("Air".to_string(), 111.)
+            ]
+        );
+
+        assert!((fill.steps[2].pressure - 200.).abs() < 0.01);
+
+        // A full cylinder of air has too much nitrogen for EAN32, so has to be drained first
+        let fill = blend(GasBlendInput {
+            current: gas(21., 0.),
+            current_pressure: 200.,
+            target: gas(32., 0.),
+            target_pressure: 200.,
+            top_up: None,
+        })
+        .unwrap();
+
+        assert!((fill.drain_to - 172.2).abs() < 0.1, "{}", fill.drain_to);
+        assert_eq!(fill.steps.len(), 1);
+        assert_eq!(fill.steps[0].gas.to_string(), "EAN100");
+
+        assert!(blend(GasBlendInput {
+            current: gas(21., 0.),
+            current_pressure: 0.,
+            target: gas(10., 20.),
+            target_pressure: 200.,
+            top_up: None,
+        })
+        .is_err());
+    }
+}
@@ -2,12 +2,15 @@ use crate::activitypub::activities::CreatePost;
 use crate::email::Emailer;
 use crate::openid::OpenIDClient;
 use crate::search::{SearchResult, Searcher};
+use crate::storage::Storage;
+use crate::sync::SyncQueue;
 use crate::{db::DbHandle, facebook::FacebookOauth, schema::*, subsurface, token::TokenEncryptor};
 use crate::{SiteContext, SITE_URL};
 use aes_gcm::Aes256Gcm;
 use anyhow::{anyhow, Context as AnyhowContext};
 use async_graphql::*;
 use foyer::HybridCache;
+use futures::Stream;
 use governor::clock::{Clock, DefaultClock};
 use governor::state::keyed::DefaultKeyedStateStore;
 use governor::{Quota, RateLimiter};
@@ -15,6 +18,7 @@ use reqwest::Client;
 use scrypt::*;
 use std::net::IpAddr;
 use std::num::NonZeroU32;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::*;
@@ -42,6 +46,7 @@ pub struct WebContext {
     pub site_context: SiteContext,
     pub dive_batch: DiveSiteLoader,
     pub sealife_batch: SealifeLoader,
+    pub sealife_tag_batch: SealifeTagLoader,
     pub facebook: FacebookOauth,
     pub frontend_cache: HybridCache<String, crate::frontend::CacheEntry>,
     pub frontend_url: String,
@@ -49,6 +54,11 @@ pub struct WebContext {
     pub client: Client,
     pub openid_client: Option<OpenIDClient>,
     pub disable_email_login: bool,
+    pub allow_local_subsurface_remotes: bool,
+    pub subsurface_dir: PathBuf,
+    pub sync_queue: SyncQueue,
+    pub storage: Arc<dyn Storage>,
+    pub photo_site_radius: f64,
 }
 
 impl SchemaContext {
@@ -328,6 +338,100 @@ impl Query {
             Err(anyhow!("Admin user level required").into())
         }
     }
+
+    async fn sync_jobs(
+        &self,
+        context: &Context<'_>,
+        id: Option<Uuid>,
+    ) -> FieldResult<Vec<SyncJob>> {
+        let context = context.data::<SchemaContext>()?;
+        let user_id = context
+            .con
+            .user
+            .as_ref()
+            .map(|val| val.id)
+            .ok_or_else(|| anyhow!("Login Required"))?;
+
+        Ok(context.web.handle.sync_jobs(user_id, id).await?)
+    }
+
+    /// The logged in user's saved dive plans
+    async fn dive_plans(&self, context: &Context<'_>) -> FieldResult<Vec<SavedDivePlan>> {
+        let context = context.data::<SchemaContext>()?;
+        let user_id = context
+            .con
+            .user
+            .as_ref()
+            .map(|val| val.id)
+            .ok_or_else(|| anyhow!("Login Required"))?;
+
+        Ok(context.web.handle.dive_plans(user_id, None).await?)
+    }
+
+    /// The cameras the logged in user has taken photos with, and how far their clocks are out
+    async fn camera_offsets(&self, context: &Context<'_>) -> FieldResult<Vec<CameraOffset>> {
+        let context = context.data::<SchemaContext>()?;
+        let user_id = context
+            .con
+            .user
+            .as_ref()
+            .map(|val| val.id)
+            .ok_or_else(|| anyhow!("Login Required"))?;
+
+        Ok(context.web.handle.camera_offsets(user_id).await?)
+    }
+
+    /// A saved dive plan, which doesn't need a login so plans can be shared
+    async fn dive_plan(
+        &self,
+        context: &Context<'_>,
+        id: Uuid,
+    ) -> FieldResult<Option<SavedDivePlan>> {
+        let context = context.data::<SchemaContext>()?;
+
+        Ok(context.web.handle.dive_plan(id).await?)
+    }
+
+    /// The deepest a gas can be breathed without going over `maxPpo2`, defaulting to 1.4 bar
+    async fn max_operating_depth(
+        &self,
+        gas: GasInput,
+        max_ppo2: Option<f32>,
+        salinity: Option<f32>,
+        altitude: Option<f32>,
+    ) -> FieldResult<f32> {
+        Ok(crate::gas::max_operating_depth(
+            gas, max_ppo2, salinity, altitude,
+        )?)
+    }
+
+    /// The depth on air that's as narcotic as breathing a gas at `depth`.  Oxygen is counted as narcotic unless `oxygenNarcotic` is false
+    async fn equivalent_narcotic_depth(
+        &self,
+        gas: GasInput,
+        depth: f32,
+        oxygen_narcotic: Option<bool>,
+        salinity: Option<f32>,
+        altitude: Option<f32>,
+    ) -> FieldResult<f32> {
+        Ok(crate::gas::equivalent_narcotic_depth(
+            gas,
+            depth,
+            oxygen_narcotic.unwrap_or(true),
+            salinity,
+            altitude,
+        )?)
+    }
+
+    /// The mix with the most oxygen for a depth, with just enough helium to keep it within the narcotic depth
+    async fn best_mix(&self, mix: BestMixInput) -> FieldResult<GasOutput> {
+        Ok(crate::gas::best_mix(mix)?)
+    }
+
+    /// Partial pressure blending steps to get from a cylinder's current fill to a target mix
+    async fn blend_gas(&self, blend: GasBlendInput) -> FieldResult<GasBlend> {
+        Ok(crate::gas::blend(blend)?)
+    }
 }
 
 pub struct Mutation;
@@ -716,7 +820,8 @@ impl Mutation {
         context: &Context<'_>,
         email: String,
         password: String,
-    ) -> FieldResult<bool> {
+        remote: Option<SubsurfaceRemote>,
+    ) -> FieldResult<SyncJob> {
         let context = context.data::<SchemaContext>()?;
         let user_id = context
             .con
@@ -725,16 +830,15 @@ impl Mutation {
             .map(|val| val.id)
             .ok_or_else(|| anyhow!("Login Required"))?;
 
-        let mut repo = tokio::task::spawn_blocking(move || {
-            subsurface::sync_repository(user_id, &email, &password)
-        })
-        .await??;
-
-        subsurface::resolve_existing_sites(user_id, &mut repo, context.web.handle.clone()).await?;
+        let remote = remote.unwrap_or_default();
 
-        subsurface::import_repository(user_id, repo, context.web.handle.clone()).await?;
+        subsurface::validate_remote(&remote, context.web.allow_local_subsurface_remotes)?;
 
-        Ok(true)
+        Ok(context
+            .web
+            .sync_queue
+            .enqueue(&context.web.handle, user_id, email, password, remote)
+            .await?)
     }
 
     async fn add_feedback(&self, context: &Context<'_>, feedback: String) -> FieldResult<Feedback> {
@@ -1155,14 +1259,133 @@ impl Mutation {
             .ok_or_else(|| anyhow!("Photo not found"))?;
 
         if user.is_editor() || photo.user_id == user.id {
+            let tags = context.web.handle.sealife_tags(id).await?;
+
             context.web.handle.remove_photo(id).await?;
 
+            let crops = tags.iter().map(|tag| photo.crop_location(tag));
+
+            // The photo is already gone, so a file that can't be removed is only logged
+            for location in photo.locations().into_iter().chain(crops) {
+                if let Err(err) = context.web.storage.delete(&location).await {
+                    error!("Error removing {location}: {err}");
+                }
+            }
+
             return Ok(true);
         }
 
         Ok(false)
     }
 
+    /// Tags the species in a photo, replacing any tagged before
+    async fn tag_sealife(
+        &self,
+        context: &Context<'_>,
+        photo_id: Uuid,
+        tags: Vec<SealifeTagInput>,
+    ) -> FieldResult<Vec<SealifeTag>> {
+        let context = context.data::<SchemaContext>()?;
+        let user = context
+            .con
+            .user
+            .as_ref()
+            .ok_or_else(|| anyhow!("Login Required"))?;
+
+        let photo = context
+            .web
+            .handle
+            .photos(Some(user), &PhotoQuery::id(photo_id))
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow!("Photo not found"))?;
+
+        if !(user.is_editor() || photo.user_id == user.id) {
+            return Err(anyhow!("No permissions to tag this photo").into());
+        }
+
+        let tags = tags
+            .into_iter()
+            .map(SealifeTag::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut sealife_ids = tags.iter().map(|tag| tag.sealife_id).collect::<Vec<_>>();
+        sealife_ids.sort();
+        sealife_ids.dedup();
+
+        if sealife_ids.len() != tags.len() {
+            return Err(anyhow!("Each species can only be tagged once in a photo").into());
+        }
+
+        let previous = context.web.handle.sealife_tags(photo_id).await?;
+
+        context.web.handle.tag_sealife(photo_id, &tags).await?;
+
+        // Crops are remade from the new boxes when they're next asked for, so a stale one
+        // that can't be removed is only logged
+        for tag in previous.iter().filter(|tag| !tags.contains(tag)) {
+            let location = photo.crop_location(tag);
+
+            if let Err(err) = context.web.storage.delete(&location).await {
+                error!("Error removing {location}: {err}");
+            }
+        }
+
+        Ok(context.web.handle.sealife_tags(photo_id).await?)
+    }
+
+    /// Saves how far a camera's clock is out, used for photos uploaded from it afterwards
+    async fn set_camera_offset(
+        &self,
+        context: &Context<'_>,
+        offset: CameraOffset,
+    ) -> FieldResult<CameraOffset> {
+        let context = context.data::<SchemaContext>()?;
+        let user = context
+            .con
+            .user
+            .as_ref()
+            .ok_or_else(|| anyhow!("Login Required"))?;
+
+        context
+            .web
+            .handle
+            .set_camera_offset(user.id, &offset)
+            .await?;
+
+        Ok(offset)
+    }
+
+    /// Corrects the dates of the logged in user's photos by `clockOffset` seconds, or their camera's saved offset, and links them to the dives they were taken on.
+    ///
+    /// Matches the given photos or those from `camera`, or all of them when neither is given.  A `clockOffset` needs one of these, as it would otherwise apply to every camera.
+    /// Photos that aren't taken during a dive keep the dive they're linked to.  Returns how many photos are linked to a dive
+    async fn rematch_photos(
+        &self,
+        context: &Context<'_>,
+        photo_ids: Option<Vec<Uuid>>,
+        camera: Option<String>,
+        clock_offset: Option<i32>,
+    ) -> FieldResult<i64> {
+        let context = context.data::<SchemaContext>()?;
+        let user = context
+            .con
+            .user
+            .as_ref()
+            .ok_or_else(|| anyhow!("Login Required"))?;
+
+        Ok(context
+            .web
+            .handle
+            .rematch_photos(
+                user.id,
+                photo_ids.as_deref(),
+                camera.as_deref(),
+                clock_offset,
+            )
+            .await?)
+    }
+
     async fn like_photo(&self, context: &Context<'_>, photo_id: Uuid) -> FieldResult<bool> {
         let context = context.data::<SchemaContext>()?;
 
@@ -1410,17 +1633,156 @@ impl Mutation {
         Ok(true)
     }
 
-    async fn plan_dive(&self, plan: DivePlanInput) -> FieldResult<DiveSchedule> {
-        let schedule = crate::plan::plan_dive(plan)?;
+    async fn plan_dive(
+        &self,
+        context: &Context<'_>,
+        plan: DivePlanInput,
+    ) -> FieldResult<DiveSchedule> {
+        let mut previous_dives = Vec::new();
+
+        if plan.surface_interval.is_some() {
+            let context = context.data::<SchemaContext>()?;
+
+            let user = context
+                .con
+                .user
+                .as_ref()
+                .ok_or_else(|| anyhow!("You must be logged in to plan a repetitive dive"))?;
+
+            previous_dives = context.web.handle.latest_day_dive_profiles(user.id).await?;
+        }
+
+        let schedule = crate::plan::plan_dive(plan, &previous_dives)?;
 
         Ok(schedule)
     }
+
+    /// Plans a dive & compares it against a logged dive
+    async fn compare_dive_plan(
+        &self,
+        context: &Context<'_>,
+        dive_id: Uuid,
+        plan: DivePlanInput,
+    ) -> FieldResult<DivePlanComparison> {
+        let context = context.data::<SchemaContext>()?;
+
+        Ok(crate::comparison::compare_dive(
+            &context.web.handle,
+            context.con.user.as_ref().map(|val| val.id),
+            dive_id,
+            plan,
+        )
+        .await?)
+    }
+
+    async fn save_dive_plan(
+        &self,
+        context: &Context<'_>,
+        plan: SaveDivePlan,
+    ) -> FieldResult<SavedDivePlan> {
+        let context = context.data::<SchemaContext>()?;
+        let user = context
+            .con
+            .user
+            .as_ref()
+            .ok_or_else(|| anyhow!("Login Required"))?;
+
+        if plan.name.trim().is_empty() {
+            return Err(anyhow!("A dive plan needs a name").into());
+        }
+
+        let mut linked_dive = None;
+
+        if let Some(dive_id) = plan.dive_id {
+            linked_dive = context
+                .web
+                .handle
+                .dives(
+                    Some(user.id),
+                    &DiveQuery {
+                        id: Some(dive_id),
+                        user_id: Some(user.id),
+                        ..Default::default()
+                    },
+                )
+                .await?
+                .pop();
+
+            if linked_dive.is_none() {
+                return Err(anyhow!("Dive plans can only be linked to your own dives").into());
+            }
+        }
+
+        // The dives before a repetitive plan are kept with it, so it doesn't change as more dives are logged
+        let previous_dives = match (plan.plan.surface_interval, linked_dive) {
+            (None, _) => Vec::new(),
+            (Some(_), Some(dive)) => {
+                context
+                    .web
+                    .handle
+                    .day_dives_before(user.id, Some(user.id), dive.date)
+                    .await?
+            }
+            (Some(_), None) => context.web.handle.latest_day_dives(user.id).await?,
+        };
+
+        let previous_dive_ids = previous_dives.iter().map(|val| val.id).collect::<Vec<_>>();
+
+        // Only save plans that can be planned
+        let previous_dives = context.web.handle.dive_profiles(previous_dives).await?;
+
+        crate::plan::plan_dive(plan.plan.clone(), &previous_dives)?;
+
+        Ok(context
+            .web
+            .handle
+            .save_dive_plan(user.id, &plan, &previous_dive_ids)
+            .await?)
+    }
+
+    async fn remove_dive_plan(&self, context: &Context<'_>, id: Uuid) -> FieldResult<bool> {
+        let context = context.data::<SchemaContext>()?;
+        let user = context
+            .con
+            .user
+            .as_ref()
+            .ok_or_else(|| anyhow!("Login Required"))?;
+
+        let removed = context.web.handle.remove_dive_plan(user.id, id).await?;
+
+        Ok(removed > 0)
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    async fn sync_job(
+        &self,
+        context: &Context<'_>,
+        id: Uuid,
+    ) -> FieldResult<impl Stream<Item = SyncJob>> {
+        let context = context.data::<SchemaContext>()?;
+        let user_id = context
+            .con
+            .user
+            .as_ref()
+            .map(|val| val.id)
+            .ok_or_else(|| anyhow!("Login Required"))?;
+
+        Ok(context
+            .web
+            .sync_queue
+            .subscribe(&context.web.handle, user_id, id)
+            .await?)
+    }
 }
 
-pub type Schema = async_graphql::Schema<Query, Mutation, EmptySubscription>;
+pub type Schema = async_graphql::Schema<Query, Mutation, Subscription>;
 
 pub fn schema() -> Schema {
-    Schema::build(Query, Mutation, EmptySubscription)
+    Schema::build(Query, Mutation, Subscription)
         .extension(async_graphql::extensions::Tracing)
         .finish()
 }
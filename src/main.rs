@@ -16,7 +16,7 @@ use clap::Parser;
 use facebook::FacebookOauth;
 use foyer::{DirectFsDeviceOptions, Engine, HybridCacheBuilder, LargeEngineOptions};
 use once_cell::sync::Lazy;
-use photos::{open_photo, save_files};
+use photos::{open_photo, save_files, sealife_crop};
 use rand::{thread_rng, Rng};
 use std::{
     cmp, env,
@@ -32,19 +32,28 @@ mod activitypub;
 pub mod db;
 // mod photos;
 pub mod chart;
+pub mod comparison;
 pub mod email;
 pub mod escape;
+pub mod export;
 pub mod facebook;
 pub mod frontend;
+pub mod gas;
 pub mod graphql;
 pub mod openid;
+pub mod photo_format;
 mod photos;
 pub mod plan;
+pub mod sac;
 pub mod schema;
 pub mod search;
 mod seo;
+pub mod slate;
+pub mod storage;
 pub mod subsurface;
+pub mod sync;
 pub mod token;
+pub mod uddf;
 
 use db::DbHandle;
 use graphql::*;
@@ -52,16 +61,18 @@ use schema::User;
 
 use aes_gcm::Aes256Gcm;
 use aes_gcm::KeyInit;
-use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
 
 use crate::{
     chart::{png_chart, svg_chart},
     email::Emailer,
     openid::{OpenIDClient, OpenIDSettings},
     photos::{image_dims, resize_image},
-    schema::{DiveSiteBatcher, Photo, PhotoQuery, SealifeBatcher},
+    schema::{DiveSiteBatcher, PhotoQuery, SealifeBatcher, SealifeTagBatcher},
     search::Searcher,
     seo::{robots, sitemap_handler},
+    storage::StorageSettings,
+    sync::SyncQueue,
 };
 
 // Sets up the postgres connection as given by `-c`
@@ -89,6 +100,9 @@ pub struct ConfigContext {
     #[arg(long, default_value = "cache", env)]
     cache_dir: String,
 
+    #[arg(long, default_value = "subsurface", env)]
+    subsurface_dir: String,
+
     #[arg(long, default_value = "http://localhost:3000", env)]
     frontend_url: String,
 
@@ -98,6 +112,13 @@ pub struct ConfigContext {
     #[arg(long, env)]
     disable_email_login: bool,
 
+    #[arg(long, env)]
+    allow_local_subsurface_remotes: bool,
+
+    /// How far in meters a photo can be taken from a dive site to be linked to it
+    #[arg(long, default_value = "500", env)]
+    photo_site_radius: f64,
+
     #[command(flatten)]
     facebook: FacebookOauth,
 
@@ -109,6 +130,9 @@ pub struct ConfigContext {
 
     #[command(flatten)]
     open_id_settings: Option<OpenIDSettings>,
+
+    #[command(flatten)]
+    storage_settings: StorageSettings,
 }
 
 #[derive(Parser, Clone, Debug, PartialEq)]
@@ -228,6 +252,9 @@ async fn main() -> Result<(), Error> {
 
     // Starts up the db.  This can take time to timeout if there are issues connecting
     let handle = DbHandle::new(&config.connect_url, frontend_cache.clone()).await?;
+
+    let storage = config.storage_settings.storage()?;
+
     if config.refresh_photos {
         let limit = Some(10);
         let mut offset = 0;
@@ -250,23 +277,21 @@ async fn main() -> Result<(), Error> {
 
             offset += photos.len();
 
-            let mut handles = Vec::new();
             for mut photo in photos {
                 let user = handle.user_details(photo.user_id).await?;
-                handles.push(tokio::task::spawn_blocking(move || {
-                    debug!("Resizing:{}", photo.id);
-                    resize_image(&photo, &user, true)?;
-                    let (width, height) = image_dims(photo.orig_location())?;
 
-                    photo.width = width;
-                    photo.height = height;
+                debug!("Resizing:{}", photo.id);
+                resize_image(&*storage, &photo, &user, true).await?;
 
-                    Ok(photo) as Result<Photo, Error>
-                }));
-            }
+                let original = storage
+                    .read(&photo.orig_location())
+                    .await?
+                    .ok_or_else(|| anyhow!("No original found for photo {}", photo.id))?;
+
+                let filename = photo.filename.clone();
 
-            for join_handle in handles {
-                let photo = join_handle.await??;
+                (photo.width, photo.height) =
+                    tokio::task::spawn_blocking(move || image_dims(&original, &filename)).await??;
 
                 handle
                     .update_dims(photo.id, photo.width, photo.height)
@@ -277,6 +302,7 @@ async fn main() -> Result<(), Error> {
 
     let dive_batch = DiveSiteBatcher::new(&handle);
     let sealife_batch = SealifeBatcher::new(&handle);
+    let sealife_tag_batch = SealifeTagBatcher::new(&handle);
 
     debug!("Warming Searcher");
 
@@ -308,6 +334,8 @@ async fn main() -> Result<(), Error> {
         None
     };
 
+    let (sync_queue, sync_receiver) = SyncQueue::new();
+
     let web_context = Arc::new(WebContext {
         handle,
         rate_limiter,
@@ -317,6 +345,7 @@ async fn main() -> Result<(), Error> {
         site_context,
         dive_batch,
         sealife_batch,
+        sealife_tag_batch,
         facebook: config.facebook,
         frontend_cache,
         frontend_url: config.frontend_url,
@@ -324,8 +353,16 @@ async fn main() -> Result<(), Error> {
         admin_email: config.admin_email,
         openid_client,
         disable_email_login: config.disable_email_login,
+        allow_local_subsurface_remotes: config.allow_local_subsurface_remotes,
+        subsurface_dir: config.subsurface_dir.into(),
+        sync_queue,
+        storage,
+        photo_site_radius: config.photo_site_radius,
     });
 
+    tokio::spawn(sync::run_heartbeat(web_context.clone()));
+    tokio::spawn(sync::run_worker(sync_receiver, web_context.clone()));
+
     let domain = site_url
         .domain()
         .ok_or_else(|| anyhow!("No domain from url: {site_url}"))?;
@@ -353,6 +390,7 @@ async fn main() -> Result<(), Error> {
                     .route(web::post().to(graphql))
                     .route(web::get().to(graphql)),
             )
+            .service(web::resource("/api/graphql/ws").route(web::get().to(graphql_ws)))
             .service(web::resource("/api/playground").route(web::get().to(playground_handler)))
             .service(
                 web::resource("/api/chart/{dive_id}")
@@ -364,12 +402,28 @@ async fn main() -> Result<(), Error> {
                     .wrap(cache_header(604800))
                     .route(web::get().to(png_chart)),
             )
+            .service(
+                web::resource("/api/photos/crop/{photo_id}/{sealife_id}/{version}")
+                    .wrap(cache_header(604800))
+                    .route(web::get().to(sealife_crop)),
+            )
             .service(
                 web::resource(["/api/photos/{kind}/{id}"])
                     .wrap(cache_header(604800))
                     .route(web::get().to(open_photo)),
             )
             .service(web::resource("/api/photos").route(web::post().to(save_files)))
+            .service(
+                web::resource("/api/plan/slate/{format}").route(web::post().to(slate::plan_slate)),
+            )
+            .service(
+                web::resource("/api/plan/{plan_id}/slate/{format}")
+                    .route(web::get().to(slate::saved_plan_slate)),
+            )
+            .service(web::resource("/api/import/uddf").route(web::post().to(uddf::import_uddf)))
+            .service(
+                web::resource("/api/export/{format}").route(web::get().to(export::export_dives)),
+            )
             .service(activitypub::configure_users())
             .service(activitypub::configure_dives())
             .service(
@@ -399,9 +453,9 @@ async fn main() -> Result<(), Error> {
 pub async fn playground_handler() -> HttpResponse {
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(playground_source(GraphQLPlaygroundConfig::new(
-            "/api/graphql",
-        )))
+        .body(playground_source(
+            GraphQLPlaygroundConfig::new("/api/graphql").subscription_endpoint("/api/graphql/ws"),
+        ))
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -449,3 +503,44 @@ async fn graphql(
 
     response.into()
 }
+
+async fn graphql_ws(
+    http_request: HttpRequest,
+    payload: web::Payload,
+    conn: ConnectionInfo,
+    web: web::Data<WebContext>,
+    schema: web::Data<Schema>,
+    fed_data: activitypub_federation::config::Data<Arc<WebContext>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let remote_ip = conn
+        .realip_remote_addr()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or_else(|| IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)));
+
+    let web = web.into_inner();
+
+    GraphQLSubscription::new(Schema::clone(&schema))
+        .on_connection_init(move |value| async move {
+            // Browsers can't set headers on websockets, so the token is sent when the connection is initialised
+            let token = value
+                .get("divedb-token")
+                .and_then(|val| val.as_str())
+                .map(|val| Token::decrypt(&web, val));
+
+            let user = match token.and_then(|token| token.user_id) {
+                Some(user_id) => web.handle.user_details(user_id).await.ok(),
+                None => None,
+            };
+
+            let mut data = async_graphql::Data::default();
+
+            data.insert(SchemaContext {
+                con: ConnectionContext { user, remote_ip },
+                web,
+                fed: fed_data,
+            });
+
+            Ok(data)
+        })
+        .start(&http_request, payload)
+}
@@ -0,0 +1,475 @@
+use std::io::Cursor;
+
+use anyhow::{anyhow, Error};
+use chrono::prelude::*;
+use exif::{Exif, In, Tag, Value};
+use image::{codecs::jpeg::JpegDecoder, ColorType, DynamicImage, ImageDecoder, ImageFormat};
+use tracing::*;
+use twoway::find_bytes;
+
+/// Extensions of camera RAW files that start out looking like a TIFF
+const RAW_EXTENSIONS: &[&str] = &[
+    "3fr", "arw", "cr2", "cr3", "crw", "dng", "erf", "iiq", "kdc", "mef", "mos", "nef", "nrw",
+    "orf", "pef", "raf", "rw2", "rwl", "sr2", "srf", "srw", "x3f",
+];
+
+/// ISO media brands used by HEIC & HEIF photos
+const HEIF_BRANDS: &[&[u8]] = &[
+    b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1",
+];
+
+/// XMP properties holding when a photo was taken, most specific first
+const XMP_DATES: &[&str] = &[
+    "exif:DateTimeOriginal",
+    "photoshop:DateCreated",
+    "xmp:CreateDate",
+];
+
+/// What's known about a photo from its metadata
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PhotoMetadata {
+    pub taken_at: Option<DateTime<Local>>,
+    /// The latitude & longitude the photo was taken at
+    pub location: Option<(f64, f64)>,
+    /// The make, model & serial number of the camera, to tell which clock took the photo
+    pub camera: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhotoFormat {
+    Jpeg,
+    Png,
+    WebP,
+    Tiff,
+    Heif,
+    /// A camera RAW file, which is shown using the JPEG preview the camera embeds in it
+    Raw,
+}
+
+impl PhotoFormat {
+    /// Works out the format from the start of the file, using the extension to tell RAW files apart from plain TIFFs
+    pub fn detect(data: &[u8], filename: &str) -> Option<Self> {
+        let extension = filename
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase())
+            .unwrap_or_default();
+
+        let header =
+            |start: usize, bytes: &[u8]| data.get(start..start + bytes.len()) == Some(bytes);
+
+        if header(0, &[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if header(0, b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if header(0, b"RIFF") && header(8, b"WEBP") {
+            Some(Self::WebP)
+        } else if header(0, b"FUJIFILMCCD-RAW") || header(4, b"ftypcrx ") {
+            Some(Self::Raw)
+        } else if header(4, b"ftyp") && HEIF_BRANDS.iter().any(|brand| header(8, brand)) {
+            Some(Self::Heif)
+        } else if is_tiff_like(data) {
+            // Olympus & Panasonic RAW files have their own magic numbers, everything else has to go by extension
+            if !(header(0, b"II*\0") || header(0, b"MM\0*"))
+                || RAW_EXTENSIONS.contains(&extension.as_str())
+            {
+                Some(Self::Raw)
+            } else {
+                Some(Self::Tiff)
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Whether photos of this format can be shown on this server
+    pub fn is_supported(self) -> bool {
+        self != Self::Heif || cfg!(feature = "heif")
+    }
+
+    fn image_format(self) -> Option<ImageFormat> {
+        match self {
+            Self::Jpeg => Some(ImageFormat::Jpeg),
+            Self::Png => Some(ImageFormat::Png),
+            Self::WebP => Some(ImageFormat::WebP),
+            Self::Tiff => Some(ImageFormat::Tiff),
+            Self::Heif | Self::Raw => None,
+        }
+    }
+
+    pub fn decode(self, data: &[u8]) -> Result<DynamicImage, Error> {
+        if let Some(format) = self.image_format() {
+            return Ok(image::load_from_memory_with_format(data, format)?);
+        }
+
+        match self {
+            Self::Raw => Ok(image::load_from_memory_with_format(
+                raw_preview(data).ok_or_else(|| anyhow!("No preview found in RAW file"))?,
+                ImageFormat::Jpeg,
+            )?),
+            _ => decode_heif(data),
+        }
+    }
+
+    /// The width & height of the photo as it's shown, without decoding it where possible
+    pub fn dimensions(self, data: &[u8]) -> Result<(u32, u32), Error> {
+        if let Some(format) = self.image_format() {
+            return Ok(image::io::Reader::with_format(Cursor::new(data), format).into_dimensions()?);
+        }
+
+        match self {
+            Self::Raw => Ok(image::io::Reader::with_format(
+                Cursor::new(
+                    raw_preview(data).ok_or_else(|| anyhow!("No preview found in RAW file"))?,
+                ),
+                ImageFormat::Jpeg,
+            )
+            .into_dimensions()?),
+            _ => {
+                let im = decode_heif(data)?;
+                Ok((im.width(), im.height()))
+            }
+        }
+    }
+
+    /// When & where the photo was taken, from its EXIF or XMP metadata
+    pub fn metadata(self, data: &[u8]) -> PhotoMetadata {
+        let mut exif = read_exif(data);
+
+        // Some RAW files only have their EXIF in the embedded preview
+        if exif.is_none() && self == Self::Raw {
+            exif = raw_preview(data).and_then(read_exif);
+        }
+
+        PhotoMetadata {
+            taken_at: exif.as_ref().and_then(exif_date).or_else(|| xmp_date(data)),
+            location: exif.as_ref().and_then(exif_location),
+            camera: exif.as_ref().and_then(exif_camera),
+        }
+    }
+}
+
+fn is_tiff_like(data: &[u8]) -> bool {
+    [&b"II*\0"[..], b"MM\0*", b"IIRO", b"IIRS", b"IIU\0"]
+        .iter()
+        .any(|magic| data.starts_with(magic))
+}
+
+fn read_exif(data: &[u8]) -> Option<Exif> {
+    let reader = exif::Reader::new();
+
+    let result = if is_tiff_like(data) {
+        // Olympus & Panasonic RAW files are TIFFs with a different magic number, which the reader won't take
+        let mut data = data.to_vec();
+        let magic = if data[0] == b'I' { b"*\0" } else { b"\0*" };
+        data[2..4].copy_from_slice(magic);
+
+        reader.read_raw(data)
+    } else {
+        reader.read_from_container(&mut Cursor::new(data))
+    };
+
+    match result {
+        Ok(exif) => Some(exif),
+        Err(err) => {
+            debug!("Could not read exif data: {}", err);
+            None
+        }
+    }
+}
+
+fn exif_date(exif: &Exif) -> Option<DateTime<Local>> {
+    let date_time = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)?;
+    let date_string = date_time.display_value().to_string();
+
+    debug!(
+        "Field Value:{:?}, Date String:{}",
+        date_time.value, date_string
+    );
+
+    NaiveDateTime::parse_from_str(&date_string, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .and_then(|date| Local.from_local_datetime(&date).single())
+}
+
+/// Degrees from the degrees, minutes & seconds EXIF stores coordinates as, negative when south or west
+fn exif_coordinate(exif: &Exif, tag: Tag, reference: Tag, negative: u8) -> Option<f64> {
+    let Value::Rational(ref parts) = exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+
+    let degrees = parts
+        .iter()
+        .zip([1., 60., 3600.])
+        .map(|(part, divisor)| part.to_f64() / divisor)
+        .sum::<f64>();
+
+    let is_negative = match exif.get_field(reference, In::PRIMARY)?.value {
+        Value::Ascii(ref values) => values.first()?.first() == Some(&negative),
+        _ => return None,
+    };
+
+    Some(if is_negative { -degrees } else { degrees })
+}
+
+fn exif_location(exif: &Exif) -> Option<(f64, f64)> {
+    let lat = exif_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?;
+    let lon = exif_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?;
+
+    // Some cameras write zeros when they don't have a fix
+    if !lat.is_finite() || !lon.is_finite() || lat.abs() > 90. || lon.abs() > 180. {
+        return None;
+    }
+
+    (lat, lon).ne(&(0., 0.)).then_some((lat, lon))
+}
+
+fn exif_text(exif: &Exif, tag: Tag) -> Option<String> {
+    let Value::Ascii(ref values) = exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+
+    let text = String::from_utf8_lossy(values.first()?).trim().to_string();
+
+    (!text.is_empty()).then_some(text)
+}
+
+fn exif_camera(exif: &Exif) -> Option<String> {
+    let make = exif_text(exif, Tag::Make);
+    let model = exif_text(exif, Tag::Model)?;
+
+    // Most models already start with the make, i.e, `Canon EOS R5`
+    let camera = match make {
+        Some(make) if !model.starts_with(&make) => format!("{make} {model}"),
+        _ => model,
+    };
+
+    Some(match exif_text(exif, Tag::BodySerialNumber) {
+        Some(serial) => format!("{camera} ({serial})"),
+        None => camera,
+    })
+}
+
+/// Finds the date in an XMP packet, as either an attribute or an element
+fn xmp_date(data: &[u8]) -> Option<DateTime<Local>> {
+    let start = find_bytes(data, b"<x:xmpmeta")?;
+    let end = find_bytes(&data[start..], b"</x:xmpmeta>").map_or(data.len(), |end| start + end);
+    let xmp = String::from_utf8_lossy(&data[start..end]);
+
+    XMP_DATES.iter().find_map(|property| {
+        let (_, rest) = xmp.split_once(property)?;
+        let value = rest
+            .strip_prefix("=\"")
+            .or_else(|| rest.strip_prefix('>'))?;
+        let value = &value[..value.find(['"', '<'])?];
+
+        DateTime::parse_from_rfc3339(value)
+            .map(|date| date.with_timezone(&Local))
+            .ok()
+            .or_else(|| {
+                ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"]
+                    .iter()
+                    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+                    .and_then(|date| Local.from_local_datetime(&date).single())
+            })
+    })
+}
+
+/// The largest colour JPEG embedded in a RAW file, which is the preview the camera shows
+fn raw_preview(data: &[u8]) -> Option<&[u8]> {
+    let mut offset = 0;
+    let mut largest: Option<(u32, &[u8])> = None;
+
+    while let Some(found) = find_bytes(&data[offset..], &[0xFF, 0xD8, 0xFF]) {
+        let candidate = &data[offset + found..];
+        offset += found + 1;
+
+        // The sensor data itself can be a greyscale lossless JPEG, which isn't something to show
+        let Ok(decoder) = JpegDecoder::new(Cursor::new(candidate)) else {
+            continue;
+        };
+
+        let (width, height) = decoder.dimensions();
+
+        if decoder.color_type() == ColorType::Rgb8
+            && largest.is_none_or(|(area, _)| width * height > area)
+        {
+            largest = Some((width * height, candidate));
+        }
+    }
+
+    largest.map(|(_, preview)| preview)
+}
+
+#[cfg(feature = "heif")]
+fn decode_heif(data: &[u8]) -> Result<DynamicImage, Error> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(data)?;
+    let handle = context.primary_image_handle()?;
+    let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)?;
+
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or_else(|| anyhow!("No pixels found in HEIF image"))?;
+
+    let row_len = plane.width as usize * 3;
+    let pixels = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|row| &row[..row_len])
+        .copied()
+        .collect();
+
+    image::RgbImage::from_raw(plane.width, plane.height, pixels)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| anyhow!("HEIF image is the wrong size"))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(_data: &[u8]) -> Result<DynamicImage, Error> {
+    Err(anyhow!(
+        "HEIF images can't be shown, as this server was built without the `heif` feature"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use exif::{experimental::Writer, Field, Rational};
+    use image::{codecs::jpeg::JpegEncoder, RgbImage};
+
+    use super::*;
+
+    fn encode(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut data), format)
+            .unwrap();
+
+        data
+    }
+
+    #[test]
+    fn test_detect_formats() {
+        let png = encode(4, 3, ImageFormat::Png);
+        let tiff = encode(4, 3, ImageFormat::Tiff);
+
+        assert_eq!(PhotoFormat::detect(&png, "a.png"), Some(PhotoFormat::Png));
+        assert_eq!(PhotoFormat::detect(&tiff, "a.tif"), Some(PhotoFormat::Tiff));
+        assert_eq!(PhotoFormat::detect(&tiff, "a.NEF"), Some(PhotoFormat::Raw));
+        assert_eq!(
+            PhotoFormat::detect(b"\0\0\0\x18ftypheic\0\0\0\0", "a.heic"),
+            Some(PhotoFormat::Heif)
+        );
+        assert_eq!(PhotoFormat::detect(b"not a photo", "a.jpg"), None);
+
+        assert_eq!(PhotoFormat::Png.dimensions(&png).unwrap(), (4, 3));
+        assert_eq!(PhotoFormat::Tiff.decode(&tiff).unwrap().width(), 4);
+    }
+
+    #[test]
+    fn test_raw_preview_and_metadata() {
+        // A TIFF with a date taken, followed by a small & large preview as cameras write them
+        let date = Field {
+            tag: Tag::DateTimeOriginal,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![b"2023:04:05 06:07:08".to_vec()]),
+        };
+
+        let degrees = |degrees, minutes, seconds| {
+            Value::Rational(vec![
+                Rational::from((degrees, 1)),
+                Rational::from((minutes, 1)),
+                Rational::from((seconds, 1)),
+            ])
+        };
+
+        let fields = [
+            date,
+            Field {
+                tag: Tag::GPSLatitude,
+                ifd_num: In::PRIMARY,
+                value: degrees(33, 51, 36),
+            },
+            Field {
+                tag: Tag::GPSLatitudeRef,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"S".to_vec()]),
+            },
+            Field {
+                tag: Tag::GPSLongitude,
+                ifd_num: In::PRIMARY,
+                value: degrees(151, 15, 0),
+            },
+            Field {
+                tag: Tag::GPSLongitudeRef,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"E".to_vec()]),
+            },
+            Field {
+                tag: Tag::Make,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"OM Digital Solutions".to_vec()]),
+            },
+            Field {
+                tag: Tag::Model,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"TG-7".to_vec()]),
+            },
+        ];
+
+        let mut writer = Writer::new();
+
+        for field in &fields {
+            writer.push_field(field);
+        }
+
+        let mut raw = Cursor::new(Vec::new());
+        writer.write(&mut raw, true).unwrap();
+
+        let mut raw = raw.into_inner();
+
+        for width in [16, 64] {
+            JpegEncoder::new(&mut raw)
+                .encode_image(&DynamicImage::ImageRgb8(RgbImage::new(width, 8)))
+                .unwrap();
+        }
+
+        let format = PhotoFormat::detect(&raw, "IMG_0001.CR2").unwrap();
+
+        assert_eq!(format, PhotoFormat::Raw);
+        assert_eq!(format.dimensions(&raw).unwrap(), (64, 8));
+
+        let metadata = format.metadata(&raw);
+
+        assert_eq!(
+            metadata.camera.as_deref(),
+            Some("OM Digital Solutions TG-7")
+        );
+
+        let (lat, lon) = metadata.location.unwrap();
+
+        assert!((lat + 33.86).abs() < 1e-9 && (lon - 151.25).abs() < 1e-9);
+        assert_eq!(
+            metadata.taken_at.unwrap().naive_local(),
+            NaiveDate::from_ymd_opt(2023, 4, 5)
+                .unwrap()
+                .and_hms_opt(6, 7, 8)
+                .unwrap()
+        );
+
+        let png = [
+            encode(4, 3, ImageFormat::Png),
+            br#"<x:xmpmeta><rdf:Description xmp:CreateDate="2021-01-02T03:04:05+00:00"/></x:xmpmeta>"#
+                .to_vec(),
+        ]
+        .concat();
+
+        assert_eq!(
+            PhotoFormat::Png.metadata(&png).taken_at.unwrap(),
+            Utc.with_ymd_and_hms(2021, 1, 2, 3, 4, 5).unwrap()
+        );
+    }
+}
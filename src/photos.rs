@@ -1,16 +1,11 @@
 use actix_web::error::{ErrorNotFound, ErrorUnauthorized};
 use anyhow::anyhow;
-use std::{
-    io::{BufWriter, Write},
-    path::{Path, PathBuf},
-    time::Duration,
-};
+use std::{ffi::OsStr, path::Path};
 
-use actix_files::NamedFile;
+use actix_files::file_extension_to_mime;
 use actix_multipart::Multipart;
 use actix_web::{error::ErrorBadRequest, web, Error, HttpResponse};
-use bytes::{Buf, BufMut, Bytes, BytesMut};
-use exif::{In, Tag};
+use bytes::{BufMut, Bytes, BytesMut};
 use futures::TryStreamExt;
 use image::{
     codecs::jpeg::JpegEncoder,
@@ -18,9 +13,7 @@ use image::{
     DynamicImage, Rgba,
 };
 use imageproc::drawing::draw_text_mut;
-use tokio::{fs::File, io::AsyncWriteExt};
 use tracing::*;
-use twoway::find_bytes;
 use uuid::Uuid;
 
 use rusttype::{point, Font, Scale};
@@ -30,7 +23,9 @@ use chrono::prelude::*;
 use crate::{
     graphql::WebContext,
     log_error,
-    schema::{CreatePhoto, Photo, PhotoQuery, User, UserLevel},
+    photo_format::PhotoFormat,
+    schema::{CreatePhoto, Photo, PhotoQuery, SealifeTag, User, UserLevel},
+    storage::Storage,
     token::Token,
 };
 use serde::Deserialize;
@@ -57,7 +52,7 @@ pub async fn open_photo(
     query: web::Query<OpenRequest>,
     context: web::Data<WebContext>,
     token: Token,
-) -> Result<NamedFile, Error> {
+) -> Result<HttpResponse, Error> {
     let (kind, id) = id.into_inner();
 
     let photo = context
@@ -74,8 +69,9 @@ pub async fn open_photo(
         PhotoKind::JpegLarge => photo.jpg_large_location(),
         PhotoKind::WebpLarge => photo.webp_large_location(),
         PhotoKind::Full => {
-            if photo.internal {
-                return Ok(NamedFile::open(photo.orig_location())?);
+            // The original may have where it was taken in it, so needs the same permissions as the location
+            if photo.internal && !photo.hide_location.unwrap_or_default() {
+                return stored_file(&*context.storage, &photo.orig_location()).await;
             }
 
             if let Some(user_id) = token.user_id {
@@ -86,7 +82,7 @@ pub async fn open_photo(
                     .map_err(|_| ErrorUnauthorized("No valid user found"))?;
 
                 if user.is_admin() || photo.user_id == user.id {
-                    return Ok(NamedFile::open(photo.orig_location())?);
+                    return stored_file(&*context.storage, &photo.orig_location()).await;
                 }
             }
 
@@ -100,12 +96,120 @@ pub async fn open_photo(
         .await
         .map_err(log_error)?;
 
-    tokio::task::spawn_blocking(move || resize_image(&photo, &user, query.force_rerender))
+    resize_image(&*context.storage, &photo, &user, query.force_rerender)
+        .await
+        .map_err(log_error)?;
+
+    stored_file(&*context.storage, &thumb_location).await
+}
+
+/// A thumbnail of a species tagged in a photo, cropped to its box
+pub async fn sealife_crop(
+    ids: web::Path<(Uuid, Uuid, String)>,
+    query: web::Query<OpenRequest>,
+    context: web::Data<WebContext>,
+) -> Result<HttpResponse, Error> {
+    let (photo_id, sealife_id, version) = ids.into_inner();
+
+    let photo = context
+        .handle
+        .photos(None, &PhotoQuery::id(photo_id))
+        .await
+        .map_err(log_error)?
+        .pop()
+        .ok_or_else(|| ErrorNotFound("No photo found"))?;
+
+    let tag = context
+        .handle
+        .sealife_tags(photo_id)
+        .await
+        .map_err(log_error)?
+        .into_iter()
+        .find(|tag| tag.sealife_id == sealife_id)
+        .ok_or_else(|| ErrorNotFound("Sealife isn't tagged in this photo"))?;
+
+    // Old versions aren't kept, so a stale link doesn't serve the new box
+    if tag.crop_version() != version {
+        return Err(ErrorNotFound("This crop has changed"));
+    }
+
+    let location = photo.crop_location(&tag);
+
+    let exists = context.storage.exists(&location).await.map_err(log_error)?;
+
+    if query.force_rerender || !exists {
+        let original = context
+            .storage
+            .read(&photo.orig_location())
+            .await
+            .map_err(log_error)?
+            .ok_or_else(|| ErrorNotFound("No photo found"))?;
+
+        let filename = photo.filename.clone();
+
+        let crop = tokio::task::spawn_blocking(move || {
+            let im = PhotoFormat::detect(&original, &filename)
+                .ok_or_else(|| anyhow!("Unsupported photo format: {filename}"))?
+                .decode(&original)?;
+
+            encode_jpeg(&crop_to_tag(&im, &tag), 80)
+        })
         .await
         .map_err(log_error)?
         .map_err(log_error)?;
 
-    Ok(NamedFile::open(thumb_location)?)
+        context
+            .storage
+            .write(&location, crop, "image/jpeg")
+            .await
+            .map_err(log_error)?;
+    }
+
+    stored_file(&*context.storage, &location).await
+}
+
+/// Crops a photo to a tag's box & shrinks it down to a thumbnail.  Like internal photos, these are too small to watermark
+fn crop_to_tag(im: &DynamicImage, tag: &SealifeTag) -> DynamicImage {
+    let (width, height) = (im.width() as f64, im.height() as f64);
+
+    let im = if tag.is_boxed() {
+        let (x, y) = ((tag.u_min * width).round(), (tag.v_min * height).round());
+        let crop_width = (tag.u_max * width).round() - x;
+        let crop_height = (tag.v_max * height).round() - y;
+
+        im.crop_imm(
+            x as u32,
+            y as u32,
+            (crop_width as u32).max(1),
+            (crop_height as u32).max(1),
+        )
+    } else {
+        im.clone()
+    };
+
+    if im.width() > CROP_WIDTH || im.height() > CROP_WIDTH {
+        im.resize(CROP_WIDTH, CROP_WIDTH, FilterType::Lanczos3)
+    } else {
+        im
+    }
+}
+
+/// Responds with a stored file, typed by its extension
+async fn stored_file(storage: &dyn Storage, key: &str) -> Result<HttpResponse, Error> {
+    let data = storage
+        .read(key)
+        .await
+        .map_err(log_error)?
+        .ok_or_else(|| ErrorNotFound("No photo found"))?;
+
+    let extension = Path::new(key)
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok()
+        .content_type(file_extension_to_mime(extension))
+        .body(data))
 }
 
 use once_cell::sync::Lazy;
@@ -116,12 +220,15 @@ pub static WATERMARK: Lazy<DynamicImage> =
 pub static FONT: Lazy<Font> =
     Lazy::new(|| Font::try_from_bytes(include_bytes!("./static/Asap-Bold.otf")).unwrap());
 
-pub fn image_dims<P: AsRef<Path>>(path: P) -> Result<(i32, i32), anyhow::Error> {
-    let im = image::open(path)?;
+pub fn image_dims(data: &[u8], filename: &str) -> Result<(i32, i32), anyhow::Error> {
+    let (width, height) = PhotoFormat::detect(data, filename)
+        .ok_or_else(|| anyhow!("Unsupported photo format: {filename}"))?
+        .dimensions(data)?;
 
-    Ok((im.width() as i32, im.height() as i32))
+    Ok((width as i32, height as i32))
 }
 
+const CROP_WIDTH: u32 = 512;
 const THUMB_WIDTH: u32 = 1000;
 const LARGE_WIDTH: u32 = 2000;
 
@@ -184,68 +291,94 @@ pub fn get_font_width(text: &str, scale: Scale) -> u32 {
         .unwrap_or_default() as u32
 }
 
-pub fn resize_image(photo: &Photo, user: &User, force_rerender: bool) -> Result<(), anyhow::Error> {
-    let jpg_location = PathBuf::from(photo.jpg_thumb_location());
-
-    // Return if the image is already at the new location
-    if std::fs::metadata(&jpg_location).is_ok() && !force_rerender {
+/// Makes the resized copies of a photo, unless they've already been made
+pub async fn resize_image(
+    storage: &dyn Storage,
+    photo: &Photo,
+    user: &User,
+    force_rerender: bool,
+) -> Result<(), anyhow::Error> {
+    // Return if the image has already been resized
+    if !force_rerender && storage.exists(&photo.jpg_thumb_location()).await? {
         return Ok(());
     }
 
-    let im = image::open(photo.orig_location())?;
+    let original = storage
+        .read(&photo.orig_location())
+        .await?
+        .ok_or_else(|| anyhow!("No original found for photo {}", photo.id))?;
+
+    let (photo, user) = (photo.clone(), user.clone());
+
+    let resized =
+        tokio::task::spawn_blocking(move || render_sizes(&original, &photo, &user)).await??;
+
+    // The jpeg thumbnail is written last, as that's what's checked to see if this is done
+    for (location, data, content_type) in resized.into_iter().rev() {
+        storage.write(&location, data, content_type).await?;
+    }
+
+    Ok(())
+}
+
+fn encode_jpeg(im: &DynamicImage, quality: u8) -> Result<Bytes, anyhow::Error> {
+    let mut data = Vec::new();
+
+    JpegEncoder::new_with_quality(&mut data, quality).encode_image(im)?;
+
+    Ok(data.into())
+}
+
+/// Resizes & watermarks a photo, returning each copy with its location & content type.
+///
+/// The copies are encoded from the pixels alone, so don't carry over any metadata such as where the photo was taken
+fn render_sizes(
+    original: &[u8],
+    photo: &Photo,
+    user: &User,
+) -> Result<Vec<(String, Bytes, &'static str)>, anyhow::Error> {
+    let im = PhotoFormat::detect(original, &photo.filename)
+        .ok_or_else(|| anyhow!("Unsupported photo format: {}", photo.filename))?
+        .decode(original)?;
 
     if photo.internal {
         let width = 512;
 
         let im = im.resize(width, width, FilterType::Lanczos3);
-        std::fs::create_dir_all(jpg_location.parent().unwrap())?;
-        let mut file = BufWriter::new(std::fs::File::create(jpg_location)?);
-        let mut encoder = JpegEncoder::new_with_quality(&mut file, 80);
-        encoder.encode_image(&im)?;
 
-        return Ok(());
+        return Ok(vec![(
+            photo.jpg_thumb_location(),
+            encode_jpeg(&im, 80)?,
+            "image/jpeg",
+        )]);
     }
 
+    let mut resized = Vec::new();
+
     for (width, jpg_location, webp_location) in [
         (
             THUMB_WIDTH,
-            PathBuf::from(photo.jpg_thumb_location()),
-            PathBuf::from(photo.webp_thumb_location()),
+            photo.jpg_thumb_location(),
+            photo.webp_thumb_location(),
         ),
         (
             LARGE_WIDTH,
-            PathBuf::from(photo.jpg_large_location()),
-            PathBuf::from(photo.webp_large_location()),
+            photo.jpg_large_location(),
+            photo.webp_large_location(),
         ),
     ] {
         let mut im = im.resize(width, width, FilterType::Lanczos3);
 
         add_overlay(&mut im, photo.date, user);
 
-        let mut error_count = 0;
-
-        while std::fs::create_dir_all(jpg_location.parent().unwrap()).is_err() {
-            std::thread::sleep(Duration::from_secs(10));
-            if error_count > 3 {
-                return Err(anyhow!("Could not create directory"));
-            } else {
-                debug!("Retrying directory creation");
-                error_count += error_count;
-            }
-        }
-
-        let mut file = BufWriter::new(std::fs::File::create(jpg_location)?);
-        let mut encoder = JpegEncoder::new_with_quality(&mut file, 50);
-        encoder.encode_image(&im)?;
-
-        let mut webp_file = BufWriter::new(std::fs::File::create(webp_location)?);
+        resized.push((jpg_location, encode_jpeg(&im, 50)?, "image/jpeg"));
 
         let webp_encoder =
             webp::Encoder::from_image(&im).map_err(|err| anyhow!("Error webp encoding:{err}"))?;
 
         let webp = webp_encoder.encode(50.0);
 
-        webp_file.write_all(&webp)?;
+        resized.push((webp_location, Bytes::copy_from_slice(&webp), "image/webp"));
     }
 
     /*
@@ -278,7 +411,7 @@ pub fn resize_image(photo: &Photo, user: &User, force_rerender: bool) -> Result<
     avif_file.write_all(&out_data)?;
     */
 
-    Ok(())
+    Ok(resized)
 }
 
 #[derive(Deserialize, Debug)]
@@ -289,6 +422,9 @@ pub struct UploadQuery {
     sealife_id: Option<Uuid>,
     #[serde(default)]
     internal: Option<bool>,
+    /// Seconds to add to the camera's clock, instead of the offset saved for the camera
+    #[serde(default)]
+    clock_offset: Option<i32>,
 }
 
 pub async fn save_files(
@@ -351,40 +487,83 @@ pub async fn save_files(
 
         let uuid_string = id.to_string();
 
-        let write_folder = format!(
-            "store/{}/{}/{}",
+        let write_path = format!(
+            "store/{}/{}/{}/{filename}",
             &uuid_string[0..2],
             &uuid_string[2..4],
             &uuid_string[4..]
         );
-        tokio::fs::create_dir_all(&write_folder).await?;
 
-        let mut bytes_written = 0;
+        // Held in memory until it's all arrived, as object stores take a whole file at a time
+        let mut data = BytesMut::new();
+
+        // Field in turn is stream of *Bytes* object
+        while let Ok(Some(chunk)) = field.try_next().await {
+            data.put(chunk);
+
+            if data.len() + quota >= max_quota {
+                return Ok(HttpResponse::InsufficientStorage().body("Upload Quota Exceeded"));
+            }
+        }
+
+        let bytes_written = data.len();
+        let data = data.freeze();
 
-        let write_path = format!("{write_folder}/{filename}");
+        let format = match PhotoFormat::detect(&data, &filename) {
+            Some(format) if format.is_supported() => format,
+            Some(_) => {
+                return Ok(HttpResponse::UnsupportedMediaType()
+                    .body(format!("`{filename}` is a HEIC photo, which this server can't show")))
+            }
+            None => {
+                return Ok(HttpResponse::UnsupportedMediaType().body(format!(
+                    "`{filename}` isn't a supported photo. Upload JPEG, PNG, WebP, TIFF, HEIC or camera RAW files"
+                )))
+            }
+        };
 
         debug!("Writing to:{}", write_path);
 
-        let mut file = File::create(&write_path).await?;
+        let extension = Path::new(&filename)
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
 
-        let mut jpeg_reader = JpegReader::new();
+        context
+            .storage
+            .write(
+                &write_path,
+                data.clone(),
+                file_extension_to_mime(extension).as_ref(),
+            )
+            .await
+            .map_err(log_error)?;
 
-        // Field in turn is stream of *Bytes* object
-        while let Ok(Some(chunk)) = field.try_next().await {
-            file.write_all(&chunk).await?;
-            if !jpeg_reader.has_enough() {
-                jpeg_reader.push(&chunk);
-            }
+        let (metadata, (width, height)) = tokio::task::spawn_blocking(move || {
+            let metadata = format.metadata(&data);
 
-            bytes_written += chunk.len();
+            format.dimensions(&data).map(|dims| (metadata, dims))
+        })
+        .await
+        .map_err(log_error)?
+        .map_err(log_error)?;
 
-            if bytes_written + quota >= max_quota {
-                tokio::fs::remove_dir_all(&write_folder).await?;
-                return Ok(HttpResponse::InsufficientStorage().body("Upload Quota Exceeded"));
-            }
-        }
+        let (width, height) = (width as i32, height as i32);
+
+        let camera_offset = match (query.clock_offset, &metadata.camera) {
+            (Some(offset), _) => offset,
+            (None, Some(camera)) => context
+                .handle
+                .camera_offset(user.id, camera)
+                .await
+                .map_err(log_error)?
+                .unwrap_or_default(),
+            (None, None) => 0,
+        };
 
-        let date = jpeg_reader.get_date();
+        let date = metadata
+            .taken_at
+            .map(|date| date + chrono::Duration::seconds(camera_offset.into()));
 
         let mut dive = None;
 
@@ -404,6 +583,14 @@ pub async fn save_files(
                     dive_site_id = dive.as_ref().and_then(|val| val.dive_site_id);
                 }
             }
+
+            if let (None, Some((lat, lon))) = (dive_site_id, metadata.location) {
+                dive_site_id = context
+                    .handle
+                    .nearest_dive_site(user.id, lat, lon, context.photo_site_radius)
+                    .await
+                    .map_err(log_error)?;
+            }
         }
 
         let photo = CreatePhoto {
@@ -416,15 +603,14 @@ pub async fn save_files(
             size: bytes_written as i32,
             sealife_id: query.sealife_id,
             internal: query.internal,
+            lat: metadata.location.map(|(lat, _)| lat),
+            lon: metadata.location.map(|(_, lon)| lon),
+            camera: metadata.camera,
+            camera_offset,
         };
 
         let mut new_photo = context.handle.add_photo(&photo).await.map_err(log_error)?;
 
-        let (width, height) = tokio::task::spawn_blocking(move || image_dims(write_path))
-            .await
-            .map_err(log_error)?
-            .map_err(log_error)?;
-
         context
             .handle
             .update_dims(new_photo.id, width, height)
@@ -435,81 +621,47 @@ pub async fn save_files(
         new_photo.height = height;
         photos.push(new_photo.clone());
 
-        tokio::task::spawn_blocking(move || resize_image(&new_photo, &user, false))
+        resize_image(&*context.storage, &new_photo, &user, false)
             .await
-            .map_err(log_error)?
             .map_err(log_error)?;
     }
     Ok(HttpResponse::Ok().json(&photos))
 }
 
-struct JpegReader {
-    buffer: BytesMut,
-    exif_len: u16,
-}
-
-impl JpegReader {
-    fn new() -> Self {
-        Self {
-            buffer: BytesMut::new(),
-            exif_len: 65535,
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
 
-    fn push(&mut self, chunk: &Bytes) {
-        self.buffer.put(chunk.clone());
+    use super::*;
 
-        // Check to see if the exif header length has been found
-        if self.exif_len == 65535 {
-            if let Some(idx) = find_bytes(&self.buffer, &[0xFF, 0xE1]) {
-                if self.buffer.len() > idx + 2 {
-                    let length_value: [u8; 2] = [self.buffer[idx + 2], self.buffer[idx + 3]];
+    #[test]
+    fn test_crop_to_tag() {
+        let im = DynamicImage::ImageRgb8(RgbImage::new(4000, 3000));
 
-                    self.exif_len = u16::from_be_bytes(length_value);
-                }
-            }
-        }
-    }
-
-    fn has_enough(&self) -> bool {
-        self.buffer.len() >= self.exif_len as usize
-    }
+        let mut tag = SealifeTag {
+            sealife_id: Uuid::nil(),
+            u_min: 0.25,
+            v_min: 0.5,
+            u_max: 0.3,
+            v_max: 0.6,
+        };
 
-    fn get_date(&self) -> Option<DateTime<Local>> {
-        if self.has_enough() {
-            let exifreader = exif::Reader::new();
+        let crop = crop_to_tag(&im, &tag);
+        assert_eq!((crop.width(), crop.height()), (200, 300));
 
-            let mut reader = self.buffer.clone().reader();
+        let version = tag.crop_version();
 
-            let attr = match exif::get_exif_attr_from_jpeg(&mut reader) {
-                Ok(attr) => attr,
-                Err(err) => {
-                    error!("Could not get exif attr: {}", err);
-                    return None;
-                }
-            };
+        // Bigger boxes are shrunk down to fit a thumbnail
+        tag.u_max = 0.75;
+        let crop = crop_to_tag(&im, &tag);
+        assert_eq!((crop.width(), crop.height()), (512, 77));
 
-            let exif = match exifreader.read_raw(attr) {
-                Ok(exif) => exif,
-                Err(err) => {
-                    error!("Could not read exif data: {}", err);
-                    return None;
-                }
-            };
-
-            if let Some(date_time) = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY) {
-                let date_string = date_time.display_value().to_string();
-                debug!(
-                    "Field Value:{:?}, Date String:{}",
-                    date_time.value, date_string
-                );
-
-                return NaiveDateTime::parse_from_str(&date_string, "%Y-%m-%d %H:%M:%S")
-                    .ok()
-                    .and_then(|date| Local.from_local_datetime(&date).single());
-            }
-        }
+        // Moving the box gives its crop a new version
+        assert_ne!(tag.crop_version(), version);
 
-        None
+        // Unboxed tags are the whole photo
+        tag.u_max = 0.;
+        let crop = crop_to_tag(&im, &tag);
+        assert_eq!((crop.width(), crop.height()), (512, 384));
     }
 }
@@ -1,160 +1,1493 @@
+use anyhow::anyhow;
 use anyhow::Result;
-use dive_deco::{BuhlmannConfig, BuhlmannModel, DecoModel, DecoStageType, Depth, Gas, Time};
+use chrono::{DateTime, Duration, Local};
+use dive_deco::{
+    BuhlmannConfig, BuhlmannModel, DecoModel, DecoStage, DecoStageType, Depth, Gas, Time,
+};
+use tracing::warn;
 
-use crate::schema::{DiveMetric, DivePlanInput, DiveSchedule, DiveStage, GasOutput, StageType};
+use crate::gas::checked_gas;
+use crate::schema::{
+    CylinderUsage, Dive, DiveMetric, DivePlanInput, DiveSchedule, DiveStage, GasInput, GasOutput,
+    PlanSegmentInput, StageType,
+};
 
-pub fn plan_dive(dive_plan: DivePlanInput) -> Result<DiveSchedule> {
-    let mut gasses = dive_plan
-        .deco_gasses
-        .into_iter()
-        .map(Gas::from)
-        .collect::<Vec<_>>();
+/// The ascent rate in m/min when the plan doesn't have one, the same as the deco ascent rate
+pub const DEFAULT_ASCENT_RATE: f32 = 10.;
 
-    let config = BuhlmannConfig::new()
-        .with_gradient_factors(dive_plan.gf_low as u8, dive_plan.gf_high as u8);
-    let mut model = BuhlmannModel::new(config);
+// Cylinders without a working pressure are assumed to be filled to 200 bar
+const DEFAULT_WORKING_PRESSURE: f32 = 200.;
 
-    // 18m/min
-    let descent_rate = 18.;
+// Rock bottom assumes two divers sharing gas, each breathing at twice their usual rate
+const STRESS_FACTOR: f32 = 4.;
 
-    let drop_to_bottom_time = dive_plan.depth / descent_rate;
+// The deco model treats 10m as 1 bar, which is water of this density in kg/ℓ
+const MODEL_SALINITY: f32 = 1.0197;
 
-    let back_gas = Gas::from(dive_plan.back_gas);
+// The water densities in kg/ℓ that can be planned for, from fresh water to the saltiest seas
+const MIN_SALINITY: f32 = 0.9;
+const MAX_SALINITY: f32 = 1.1;
 
-    gasses.push(back_gas);
+// The deco model puts stops every 3m, with the last one at 3m
+const STOP_INTERVAL: f32 = 3.;
+
+// The default ppO2 limits in bar for the gasses breathed at each level & on deco
+pub(crate) const DEFAULT_MAX_PPO2: f32 = 1.4;
+const DEFAULT_DECO_MAX_PPO2: f32 = 1.6;
+
+// Gasses with a ppO2 below this can't be relied on to keep a diver conscious
+const MIN_PPO2: f32 = 0.16;
+
+/// Converts between real depths and the depths the deco model uses
+#[derive(Clone, Copy)]
+pub(crate) struct Water {
+    /// How much deeper the water is to the model
+    factor: f32,
+    /// Surface pressure in bar
+    surface_pressure: f32,
+}
+
+impl Water {
+    /// Water of `salinity` in kg/ℓ, under a surface pressure in mbar
+    pub(crate) fn new(salinity: Option<f32>, surface_pressure: i32) -> Self {
+        Self {
+            factor: salinity.map(|val| val / MODEL_SALINITY).unwrap_or(1.),
+            surface_pressure: surface_pressure as f32 / 1000.,
+        }
+    }
+
+    fn model_depth(&self, depth: f32) -> Depth {
+        Depth::from_meters(depth * self.factor)
+    }
+
+    fn depth(&self, depth: Depth) -> f32 {
+        depth.as_meters() as f32 / self.factor
+    }
+
+    /// The ambient pressure in bar at a real depth
+    pub(crate) fn pressure(&self, depth: f32) -> f32 {
+        self.surface_pressure + depth * self.factor / 10.
+    }
+
+    /// The ppO2 of `gas` in bar at a real depth
+    pub(crate) fn ppo2(&self, gas: Gas, depth: f32) -> f32 {
+        GasOutput::from(gas).o2 / 100. * self.pressure(depth)
+    }
+
+    /// The maximum operating depth of `gas` in meters, rounded down
+    pub(crate) fn mod_depth(&self, gas: Gas, max_ppo2: f32) -> f32 {
+        let o2 = GasOutput::from(gas).o2 / 100.;
+
+        ((max_ppo2 / o2 - self.surface_pressure) * 10. / self.factor)
+            .max(0.)
+            .floor()
+    }
+
+    /// The real depth at an ambient pressure in bar
+    pub(crate) fn pressure_depth(&self, pressure: f32) -> f32 {
+        (pressure - self.surface_pressure) * 10. / self.factor
+    }
+}
+
+/// The cumulative CNS% & OTU of the dive so far
+fn exposure(model: &BuhlmannModel) -> (f32, f32) {
+    let ox_tox = model.dive_state().ox_tox;
+
+    (ox_tox.cns() as f32, ox_tox.otu() as f32)
+}
+
+fn warn(warnings: &mut Vec<String>, warning: String) {
+    if !warnings.contains(&warning) {
+        warnings.push(warning);
+    }
+}
+
+/// The surface pressure in mbar at an altitude in meters, using the barometric formula
+pub(crate) fn surface_pressure(altitude: f32) -> i32 {
+    (1013.25 * (1. - 2.25577e-5 * altitude).powf(5.25588)).round() as i32
+}
+
+/// Tracks the gas used from each cylinder as the plan is built up
+#[derive(Clone)]
+struct GasPlan {
+    sac_rate: Option<f32>,
+    water: Water,
+    cylinders: Vec<PlannedCylinder>,
+}
+
+#[derive(Clone)]
+struct PlannedCylinder {
+    input: GasInput,
+    gas: Gas,
+    used: f32,
+    max_depth: f32,
+}
+
+impl GasPlan {
+    fn add_cylinder(&mut self, input: GasInput) {
+        let gas = Gas::from(input);
+
+        if !self.cylinders.iter().any(|val| val.gas == gas) {
+            self.cylinders.push(PlannedCylinder {
+                input,
+                gas,
+                used: 0.,
+                max_depth: 0.,
+            });
+        }
+    }
+
+    /// Breathes `gas` while going between two depths, returning the litres used when there is a SAC rate
+    fn breathe(&mut self, gas: Gas, start_depth: f32, end_depth: f32, seconds: f32) -> Option<f32> {
+        let sac_rate = self.sac_rate?;
+
+        let ambient = self.water.pressure((start_depth + end_depth) / 2.);
+        let litres = sac_rate * ambient * seconds / 60.;
+
+        if let Some(cylinder) = self.cylinders.iter_mut().find(|val| val.gas == gas) {
+            cylinder.used += litres;
+            cylinder.max_depth = cylinder.max_depth.max(start_depth).max(end_depth);
+        }
+
+        Some(litres)
+    }
+
+    fn usage(&self, ascent_rate: f32) -> Vec<CylinderUsage> {
+        let Some(sac_rate) = self.sac_rate else {
+            return Vec::new();
+        };
+
+        self.cylinders
+            .iter()
+            .filter(|cylinder| cylinder.input.litres > 0.)
+            .map(|cylinder| {
+                let size = cylinder.input.litres;
+                let start_pressure = cylinder
+                    .input
+                    .working_pressure
+                    .unwrap_or(DEFAULT_WORKING_PRESSURE);
+                let end_pressure = start_pressure - cylinder.used / size;
+
+                // Enough gas to get both divers from the deepest point to the surface, with a minute to sort out the problem
+                let ascent_time = cylinder.max_depth / ascent_rate + 1.;
+                let ambient = self.water.pressure(cylinder.max_depth / 2.);
+                let rock_bottom = sac_rate * STRESS_FACTOR * ambient * ascent_time / size;
+
+                CylinderUsage {
+                    gas: cylinder.gas.into(),
+                    size,
+                    start_pressure,
+                    end_pressure,
+                    litres_used: cylinder.used,
+                    rock_bottom,
+                    turn_pressure: start_pressure * 2. / 3.,
+                    below_reserve: end_pressure < rock_bottom,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Loads `model` with the dives logged before this one, followed by `surface_interval` minutes at the surface.
+///
+/// Dives without a profile are replayed as a square profile at their max depth
+fn replay_dives(
+    model: &mut BuhlmannModel,
+    previous_dives: &[(Dive, Vec<DiveMetric>)],
+    surface_interval: i32,
+) {
+    let air = Gas::air();
+
+    let mut last_end: Option<DateTime<Local>> = None;
+
+    for (dive, metrics) in previous_dives {
+        if let Some(end) = last_end {
+            let interval = (dive.date - end).num_seconds();
+
+            if interval > 0 {
+                model.record(Depth::zero(), Time::from_seconds(interval as f64), &air);
+            }
+        }
+
+        if metrics.is_empty() {
+            model.record(
+                Depth::from_meters(dive.depth),
+                Time::from_seconds(dive.duration as f64),
+                &air,
+            );
+        }
+
+        let mut gas = air;
+        let mut cur_time = 0;
+
+        for metric in metrics {
+            if metric.o2.is_some() || metric.he.is_some() {
+                let input = GasInput {
+                    o2: metric.o2.unwrap_or(21.),
+                    he: metric.he.unwrap_or(0.),
+                    ..Default::default()
+                };
+
+                // A logged mix that can't be breathed is a bad sample, so the previous gas carries on
+                match checked_gas(input) {
+                    Ok(val) => gas = val,
+                    Err(err) => warn!("Skipping gas change on dive {}: {err}", dive.id),
+                }
+            }
+
+            model.record_travel(
+                Depth::from_meters(metric.depth),
+                Time::from_seconds((metric.time - cur_time) as f64),
+                &gas,
+            );
+
+            cur_time = metric.time;
+        }
+
+        last_end = Some(dive.date + Duration::seconds(dive.duration.max(cur_time) as i64));
+    }
 
-    model.record_travel(
-        Depth::from_meters(dive_plan.depth),
-        Time::from_minutes(drop_to_bottom_time),
-        &back_gas,
-    );
     model.record(
-        Depth::from_meters(dive_plan.depth),
-        Time::from_minutes(dive_plan.time),
-        &back_gas,
+        Depth::zero(),
+        Time::from_minutes(surface_interval as f64),
+        &air,
     );
+}
 
-    // calculate deco runtime providing available gasses
-    let deco_runtime = model.deco(gasses).map_err(|err| anyhow::anyhow!("{err}"))?;
+/// Replays the deco on `model`, moving the stops shallower than `last_stop` onto it and staying there until it's clear to surface
+fn move_last_stop(
+    mut model: BuhlmannModel,
+    deco_stages: Vec<DecoStage>,
+    last_stop: Depth,
+    ascent_rate: f64,
+) -> Vec<DecoStage> {
+    let has_shallow_stops = deco_stages
+        .iter()
+        .any(|stage| stage.stage_type == DecoStageType::DecoStop && stage.end_depth < last_stop);
+
+    if !has_shallow_stops {
+        return deco_stages;
+    }
 
     let mut stages = Vec::new();
+    let mut gas = model.dive_state().gas;
 
-    let mut metrics = Vec::new();
+    for stage in deco_stages {
+        // The stops & gas switches shallower than the last stop are replaced by a longer last stop
+        if stage.start_depth <= last_stop && stage.end_depth < last_stop {
+            continue;
+        }
 
-    let mut cur_time = 0;
+        match stage.stage_type {
+            DecoStageType::Ascent if stage.end_depth < last_stop => {
+                // Stop the ascent at the last stop instead
+                let duration =
+                    Time::from_minutes((stage.start_depth - last_stop).as_meters() / ascent_rate);
 
-    metrics.push(DiveMetric {
-        time: 0,
-        depth: 0.,
-        pressure: None,
-        temperature: None,
-        o2: Some(dive_plan.back_gas.o2).filter(|val| *val != 21.),
-        he: Some(dive_plan.back_gas.he).filter(|val| *val != 0.),
-    });
+                model.record_travel(last_stop, duration, &stage.gas);
 
-    stages.push(DiveStage {
-        stage_type: StageType::DESCEND,
-        time: drop_to_bottom_time as i32,
-        depth: dive_plan.depth,
-        gas: Some(back_gas.into()),
-    });
+                stages.push(DecoStage {
+                    end_depth: last_stop,
+                    duration,
+                    ..stage
+                });
+            }
+            DecoStageType::Ascent => {
+                model.record_travel(stage.end_depth, stage.duration, &stage.gas);
+                stages.push(stage);
+            }
+            DecoStageType::DecoStop => {
+                model.record(stage.end_depth, stage.duration, &stage.gas);
+                stages.push(stage);
+            }
+            DecoStageType::GasSwitch => stages.push(stage),
+        }
 
-    cur_time += drop_to_bottom_time as i32 * 60;
+        gas = stage.gas;
+    }
+
+    let mut duration = Time::zero();
 
-    metrics.push(DiveMetric {
-        time: cur_time,
-        depth: dive_plan.depth,
-        pressure: None,
-        temperature: None,
-        o2: None,
-        he: None,
+    // Capped at a day, in case the ceiling never clears
+    while model.ceiling().as_meters() > 0. && duration < Time::from_minutes(1440.) {
+        model.record(last_stop, Time::from_minutes(1.), &gas);
+        duration += Time::from_minutes(1.);
+    }
+
+    stages.push(DecoStage {
+        stage_type: DecoStageType::DecoStop,
+        start_depth: last_stop,
+        end_depth: last_stop,
+        duration,
+        gas,
     });
 
-    stages.push(DiveStage {
-        stage_type: StageType::STAY,
-        time: dive_plan.time,
-        depth: dive_plan.depth,
-        gas: None,
+    stages.push(DecoStage {
+        stage_type: DecoStageType::Ascent,
+        start_depth: last_stop,
+        end_depth: Depth::zero(),
+        duration: Time::from_minutes(last_stop.as_meters() / ascent_rate),
+        gas,
     });
 
-    for _ in 0..dive_plan.time {
-        cur_time += 60;
+    stages
+}
 
-        metrics.push(DiveMetric {
-            time: cur_time,
-            depth: dive_plan.depth,
-            pressure: None,
-            temperature: None,
-            o2: None,
-            he: None,
+/// A closed circuit loop, held at a constant ppO2 with the rest made up of diluent
+#[derive(Clone, Copy)]
+struct Loop {
+    setpoint: f32,
+    deco_setpoint: f32,
+    diluent: Gas,
+}
+
+impl Loop {
+    /// The gas breathed from the loop at a real depth.  The ppO2 can't go above the ambient pressure or below the diluent's
+    fn gas(&self, water: &Water, depth: f32, deco: bool) -> Gas {
+        let setpoint = if deco {
+            self.deco_setpoint
+        } else {
+            self.setpoint
+        };
+
+        let diluent = self.diluent.gas_pressures_compound(1.);
+
+        let o2 = (setpoint as f64 / water.pressure(depth) as f64).clamp(diluent.o2, 1.);
+
+        // The inert part of the loop keeps the diluent's ratio of helium to nitrogen
+        let he = if diluent.o2 < 1. {
+            (diluent.he / (1. - diluent.o2) * (1. - o2)).min(1. - o2)
+        } else {
+            0.
+        };
+
+        Gas::new(o2, he)
+    }
+}
+
+/// A plan as it's built up, which can be cloned to plan a bailout from any point
+#[derive(Clone)]
+struct Planner {
+    model: BuhlmannModel,
+    water: Water,
+    gas_plan: GasPlan,
+    closed_circuit: Option<Loop>,
+    /// Whether the plan is on the final ascent
+    decompressing: bool,
+    descent_rate: f32,
+    ascent_rate: f32,
+    last_stop: f32,
+    max_ppo2: f32,
+    deco_max_ppo2: f32,
+    stages: Vec<DiveStage>,
+    metrics: Vec<DiveMetric>,
+    warnings: Vec<String>,
+    cur_time: i32,
+    cur_depth: f32,
+    cur_gas: Gas,
+}
+
+impl Planner {
+    /// The loop setpoint, while still breathing from the loop
+    fn setpoint(&self) -> Option<f32> {
+        self.closed_circuit.map(|closed_circuit| {
+            if self.decompressing {
+                closed_circuit.deco_setpoint
+            } else {
+                closed_circuit.setpoint
+            }
+        })
+    }
+
+    fn push_stage(
+        &mut self,
+        stage_type: StageType,
+        time: i32,
+        depth: f32,
+        gas: Option<Gas>,
+        gas_used: Option<f32>,
+    ) {
+        let (cns, otu) = exposure(&self.model);
+
+        self.stages.push(DiveStage {
+            stage_type,
+            time,
+            depth,
+            gas: gas.map(GasOutput::from),
+            gas_used,
+            setpoint: self.setpoint(),
+            cns,
+            otu,
         });
     }
 
-    for stage in deco_runtime.deco_stages {
-        let time = stage.duration.as_minutes() as i32;
+    fn push_metric(&mut self, gas: Option<Gas>) {
+        let gas = gas.map(GasOutput::from);
+
+        self.metrics.push(DiveMetric {
+            time: self.cur_time,
+            depth: self.cur_depth,
+            o2: gas.as_ref().map(|val| val.o2),
+            he: gas.as_ref().map(|val| val.he),
+            setpoint: self.setpoint(),
+            ..Default::default()
+        });
+    }
+
+    /// Switches to an open circuit gas, before travelling so the new level is reached on it
+    fn switch_gas(&mut self, gas: Gas) {
+        self.push_stage(StageType::GAS_CHANGE, 0, self.cur_depth, Some(gas), None);
+
+        self.model
+            .record(self.water.model_depth(self.cur_depth), Time::zero(), &gas);
+
+        self.push_metric(Some(gas));
+
+        self.cur_gas = gas;
+    }
+
+    fn travel(&mut self, depth: f32) {
+        let (stage_type, rate) = if depth > self.cur_depth {
+            (StageType::DESCEND, self.descent_rate)
+        } else {
+            (StageType::ASCEND, self.ascent_rate)
+        };
+
+        let travel_time = (depth - self.cur_depth).abs() / rate;
+
+        let gas_used = match self.closed_circuit {
+            // The loop gas changes with depth, so travel is recorded a stop at a time
+            Some(closed_circuit) => {
+                let steps = ((depth - self.cur_depth).abs() / STOP_INTERVAL)
+                    .ceil()
+                    .max(1.);
+                let step = (depth - self.cur_depth) / steps;
+
+                for idx in 0..steps as i32 {
+                    let start = self.cur_depth + step * idx as f32;
+
+                    let gas =
+                        closed_circuit.gas(&self.water, start + step / 2., self.decompressing);
+
+                    self.model.record_travel(
+                        self.water.model_depth(start + step),
+                        Time::from_minutes(travel_time / steps),
+                        &gas,
+                    );
+                }
+
+                None
+            }
+            None => {
+                self.model.record_travel(
+                    self.water.model_depth(depth),
+                    Time::from_minutes(travel_time),
+                    &self.cur_gas,
+                );
+
+                self.gas_plan
+                    .breathe(self.cur_gas, self.cur_depth, depth, travel_time * 60.)
+            }
+        };
+
+        // Like the open circuit deco, ascents between stops that take under a minute aren't listed
+        if !self.decompressing || travel_time >= 1. {
+            self.push_stage(
+                stage_type,
+                travel_time as i32,
+                depth,
+                Some(self.cur_gas),
+                gas_used,
+            );
+        }
+
+        self.cur_time += (travel_time * 60.) as i32;
+        self.cur_depth = depth;
+
+        self.push_metric(None);
+    }
+
+    /// Stays at the current depth, returning the gas used
+    fn stay(&mut self, minutes: i32) -> Option<f32> {
+        let gas = match self.closed_circuit {
+            Some(closed_circuit) => {
+                closed_circuit.gas(&self.water, self.cur_depth, self.decompressing)
+            }
+            None => self.cur_gas,
+        };
+
+        self.model.record(
+            self.water.model_depth(self.cur_depth),
+            Time::from_minutes(minutes as f64),
+            &gas,
+        );
+
+        for _ in 0..minutes {
+            self.cur_time += 60;
+
+            self.push_metric(None);
+        }
+
+        match self.closed_circuit {
+            Some(_) => None,
+            None => {
+                self.gas_plan
+                    .breathe(gas, self.cur_depth, self.cur_depth, minutes as f32 * 60.)
+            }
+        }
+    }
+
+    fn segment(&mut self, segment: PlanSegmentInput, back_gas: Gas) -> Result<()> {
+        if self.closed_circuit.is_none() {
+            let gas = segment.gas.map(Gas::from).unwrap_or(back_gas);
+
+            if let Some(input) = segment.gas {
+                self.gas_plan.add_cylinder(input);
+            }
+
+            self.check_gas(gas, segment.depth)?;
+
+            if gas != self.cur_gas {
+                self.switch_gas(gas);
+            }
+        }
+
+        if segment.depth != self.cur_depth {
+            self.travel(segment.depth);
+        }
+
+        let gas_used = self.stay(segment.time);
+
+        self.push_stage(StageType::STAY, segment.time, segment.depth, None, gas_used);
+
+        Ok(())
+    }
+
+    /// Checks an open circuit gas can be breathed on the way to `depth`
+    fn check_gas(&mut self, gas: Gas, depth: f32) -> Result<()> {
+        let deepest = self.cur_depth.max(depth);
+
+        if self.water.ppo2(gas, deepest) > self.max_ppo2 {
+            return Err(anyhow!(
+                "{} can't be breathed at {deepest}m, its MOD at a ppO2 of {} bar is {}m",
+                GasOutput::from(gas),
+                self.max_ppo2,
+                self.water.mod_depth(gas, self.max_ppo2)
+            ));
+        }
+
+        let shallowest = self.cur_depth.min(depth);
+
+        if self.water.ppo2(gas, shallowest) < MIN_PPO2 {
+            warn(
+                &mut self.warnings,
+                format!("{} is hypoxic at {shallowest}m", GasOutput::from(gas)),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Comes off the loop onto open circuit `gas`
+    fn bail_out(&mut self, gas: Gas) -> Result<()> {
+        self.closed_circuit = None;
 
-        cur_time += stage.duration.as_seconds() as i32;
+        self.check_gas(gas, self.cur_depth)?;
 
-        let (o2, he) = if stage.stage_type == DecoStageType::GasSwitch {
-            let gas = GasOutput::from(stage.gas);
+        self.switch_gas(gas);
 
-            (Some(gas.o2), Some(gas.he))
+        Ok(())
+    }
+
+    /// Ascends to the surface on open circuit, using `gasses` for deco
+    fn deco(&mut self, gasses: Vec<Gas>) -> Result<()> {
+        self.decompressing = true;
+
+        // calculate deco runtime providing available gasses
+        let deco_runtime = self.model.deco(gasses).map_err(|err| anyhow!("{err}"))?;
+
+        let deco_stages = if self.last_stop > STOP_INTERVAL {
+            move_last_stop(
+                self.model.clone(),
+                deco_runtime.deco_stages,
+                self.water.model_depth(self.last_stop),
+                self.ascent_rate as f64,
+            )
         } else {
-            (None, None)
+            deco_runtime.deco_stages
         };
 
+        for stage in deco_stages {
+            let time = stage.duration.as_minutes() as i32;
+
+            self.cur_time += stage.duration.as_seconds() as i32;
+
+            let start_depth = self.water.depth(stage.start_depth);
+            let end_depth = self.water.depth(stage.end_depth);
+
+            // The deco is simulated on a copy of the model, so replay it to track oxygen exposure
+            match stage.stage_type {
+                DecoStageType::Ascent => {
+                    self.model
+                        .record_travel(stage.end_depth, stage.duration, &stage.gas)
+                }
+                DecoStageType::DecoStop => {
+                    self.model
+                        .record(stage.end_depth, stage.duration, &stage.gas)
+                }
+                DecoStageType::GasSwitch => {
+                    if self.water.ppo2(stage.gas, start_depth) > self.deco_max_ppo2 {
+                        warn(
+                            &mut self.warnings,
+                            format!(
+                                "{} is switched to at {start_depth}m, beyond its MOD of {}m at a ppO2 of {} bar",
+                                GasOutput::from(stage.gas),
+                                self.water.mod_depth(stage.gas, self.deco_max_ppo2),
+                                self.deco_max_ppo2
+                            ),
+                        );
+                    }
+                }
+            }
+
+            if self.water.ppo2(stage.gas, end_depth) < MIN_PPO2 {
+                warn(
+                    &mut self.warnings,
+                    format!("{} is hypoxic at {end_depth}m", GasOutput::from(stage.gas)),
+                );
+            }
+
+            let gas_used = self.gas_plan.breathe(
+                stage.gas,
+                start_depth,
+                end_depth,
+                stage.duration.as_seconds() as f32,
+            );
+
+            self.cur_depth = end_depth;
+            self.cur_gas = stage.gas;
+
+            self.push_metric(
+                Some(stage.gas).filter(|_| stage.stage_type == DecoStageType::GasSwitch),
+            );
+
+            if time == 0
+                && (stage.stage_type == DecoStageType::Ascent
+                    || stage.stage_type == DecoStageType::DecoStop)
+            {
+                continue;
+            }
+
+            self.push_stage(
+                stage.stage_type.into(),
+                time,
+                end_depth,
+                Some(stage.gas),
+                gas_used,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Ascends to the surface on the loop, stopping every 3m until the ceiling clears
+    fn closed_circuit_deco(&mut self) {
+        self.decompressing = true;
+
+        let last_stop = self.water.model_depth(self.last_stop).as_meters() as f32;
+
+        let mut stop_time = 0;
+
+        // Capped at a day, in case the ceiling never clears
+        let cut_off = self.cur_time + 86400;
+
+        loop {
+            let ceiling = self.model.ceiling().as_meters() as f32;
+
+            let mut stop = STOP_INTERVAL * (ceiling / STOP_INTERVAL).ceil();
+
+            if stop > 0. && stop < last_stop {
+                stop = last_stop;
+            }
+
+            let stop = stop / self.water.factor;
+
+            if stop < self.cur_depth {
+                if stop_time > 0 {
+                    self.push_stage(StageType::STAY, stop_time / 60, self.cur_depth, None, None);
+
+                    stop_time = 0;
+                }
+
+                self.travel(stop);
+            } else if self.cur_depth <= 0. || self.cur_time > cut_off {
+                break;
+            } else {
+                self.stay(1);
+
+                stop_time += 60;
+            }
+        }
+    }
+
+    /// Surfaces the plan, with `bottom_end` being the seconds into the dive the final ascent started
+    /// and `on_loop` whether the dive was ever on a closed circuit, including bailouts from one
+    fn finish(
+        self,
+        start_model: BuhlmannModel,
+        bottom_end: i32,
+        on_loop: bool,
+    ) -> Result<DiveSchedule> {
+        let mut metrics = self.metrics;
+
         metrics.push(DiveMetric {
-            time: cur_time,
-            depth: stage.end_depth.as_meters() as f32,
-            pressure: None,
-            temperature: None,
-            o2,
-            he,
+            time: self.cur_time,
+            depth: 0.,
+            ..Default::default()
         });
 
-        if time == 0
-            && (stage.stage_type == DecoStageType::Ascent
-                || stage.stage_type == DecoStageType::DecoStop)
-        {
-            continue;
+        // The chart replays the profile on open circuit, so can't show the ceiling of a loop
+        let chart_model = Some(start_model).filter(|_| !on_loop);
+
+        let small_chart = crate::chart::render_dive(
+            metrics.clone(),
+            chart_model.clone(),
+            Some(469.),
+            Some(288.),
+            Default::default(),
+        )?;
+
+        let big_chart = crate::chart::render_dive(
+            metrics.clone(),
+            chart_model,
+            Some(958.),
+            Some(400.),
+            Default::default(),
+        )?;
+
+        let cylinders = self.gas_plan.usage(self.ascent_rate);
+
+        let (cns, otu) = exposure(&self.model);
+
+        let bottom_time = bottom_end / 60;
+        let tts = (self.cur_time - bottom_end) / 60;
+
+        Ok(DiveSchedule {
+            runtime: tts + bottom_time,
+            tts,
+            bottom_time,
+            stages: self.stages,
+            below_reserve: cylinders.iter().any(|val| val.below_reserve),
+            cylinders,
+            cns,
+            otu,
+            warnings: self.warnings,
+            bailout: None,
+            small_chart,
+            big_chart,
+            metrics,
+        })
+    }
+}
+
+/// Plans a dive, replaying `previous_dives` first when the plan has a surface interval.
+///
+/// Closed circuit plans come with an open circuit bailout schedule, from the point of the dive with the longest ascent
+pub fn plan_dive(
+    dive_plan: DivePlanInput,
+    previous_dives: &[(Dive, Vec<DiveMetric>)],
+) -> Result<DiveSchedule> {
+    // 18m/min
+    let descent_rate = dive_plan.descent_rate.unwrap_or(18.);
+
+    let ascent_rate = dive_plan.ascent_rate.unwrap_or(DEFAULT_ASCENT_RATE);
+
+    let last_stop = dive_plan.last_stop.unwrap_or(STOP_INTERVAL);
+
+    let surface_pressure = surface_pressure(dive_plan.altitude.unwrap_or_default());
+
+    // The deco model panics on a bad config, so these need checking up front
+    if !(1..=100).contains(&dive_plan.gf_low)
+        || !(1..=100).contains(&dive_plan.gf_high)
+        || dive_plan.gf_low > dive_plan.gf_high
+    {
+        return Err(anyhow!(
+            "Gradient factors must be between 1 and 100, with the low not above the high"
+        ));
+    }
+
+    if descent_rate <= 0. {
+        return Err(anyhow!("Descent rate must be above 0"));
+    }
+
+    if !(1. ..=30.).contains(&ascent_rate) {
+        return Err(anyhow!("Ascent rate must be between 1 and 30 m/min"));
+    }
+
+    if !(500..=1500).contains(&surface_pressure) {
+        return Err(anyhow!("Altitude is too high to plan for"));
+    }
+
+    if dive_plan
+        .salinity
+        .is_some_and(|val| !(MIN_SALINITY..=MAX_SALINITY).contains(&val))
+    {
+        return Err(anyhow!(
+            "Salinity must be between {MIN_SALINITY} & {MAX_SALINITY} kg/ℓ"
+        ));
+    }
+
+    let max_ppo2 = dive_plan.max_ppo2.unwrap_or(DEFAULT_MAX_PPO2);
+    let deco_max_ppo2 = dive_plan.deco_max_ppo2.unwrap_or(DEFAULT_DECO_MAX_PPO2);
+
+    if max_ppo2 <= MIN_PPO2 || deco_max_ppo2 <= MIN_PPO2 {
+        return Err(anyhow!("The max ppO2 must be above {MIN_PPO2} bar"));
+    }
+
+    if last_stop < STOP_INTERVAL {
+        return Err(anyhow!(
+            "The last stop can't be shallower than {STOP_INTERVAL}m"
+        ));
+    }
+
+    // Gasses panic when they're an impossible mix, so every one is checked before any are made
+    let segment_gasses = dive_plan
+        .segments
+        .iter()
+        .flatten()
+        .filter_map(|segment| segment.gas);
+
+    for gas in [dive_plan.back_gas]
+        .into_iter()
+        .chain(dive_plan.deco_gasses.iter().copied())
+        .chain(segment_gasses)
+        .chain(dive_plan.closed_circuit.map(|val| val.diluent))
+    {
+        checked_gas(gas)?;
+    }
+
+    let config = BuhlmannConfig::new()
+        .with_gradient_factors(dive_plan.gf_low as u8, dive_plan.gf_high as u8)
+        .with_deco_ascent_rate(ascent_rate as f64)
+        .with_surface_pressure(surface_pressure);
+
+    let mut model = BuhlmannModel::new(config);
+
+    if let Some(surface_interval) = dive_plan.surface_interval {
+        if surface_interval < 0 {
+            return Err(anyhow!("The surface interval can't be negative"));
         }
 
-        stages.push(DiveStage {
-            stage_type: stage.stage_type.into(),
-            time,
-            depth: stage.end_depth.as_meters() as f32,
-            gas: Some(stage.gas.into()),
-        });
+        replay_dives(&mut model, previous_dives, surface_interval);
+    }
+
+    // The charts need to start with the same residual gas as the plan
+    let start_model = model.clone();
+
+    let water = Water::new(dive_plan.salinity, surface_pressure);
+
+    // A plan without segments is a single level at `depth` for `time`
+    let segments = match dive_plan.segments {
+        Some(segments) if !segments.is_empty() => segments,
+        _ => vec![PlanSegmentInput {
+            depth: dive_plan.depth,
+            time: dive_plan.time,
+            gas: None,
+        }],
+    };
+
+    let closed_circuit = match dive_plan.closed_circuit {
+        Some(input) => {
+            let deco_setpoint = input.deco_setpoint.unwrap_or(input.setpoint);
+
+            if !(MIN_PPO2..=max_ppo2).contains(&input.setpoint)
+                || !(MIN_PPO2..=deco_max_ppo2).contains(&deco_setpoint)
+            {
+                return Err(anyhow!(
+                    "Setpoints must be between {MIN_PPO2} bar & the max ppO2"
+                ));
+            }
+
+            if segments.iter().any(|segment| segment.gas.is_some()) {
+                return Err(anyhow!(
+                    "Closed circuit levels are all breathed from the loop, so can't have a gas"
+                ));
+            }
+
+            let diluent = Gas::from(input.diluent);
+
+            let deepest = segments.iter().map(|val| val.depth).fold(0., f32::max);
+
+            if water.ppo2(diluent, deepest) > max_ppo2 {
+                return Err(anyhow!(
+                    "The diluent {} can't be breathed at {deepest}m, its MOD at a ppO2 of {max_ppo2} bar is {}m",
+                    GasOutput::from(diluent),
+                    water.mod_depth(diluent, max_ppo2)
+                ));
+            }
+
+            Some(Loop {
+                setpoint: input.setpoint,
+                deco_setpoint,
+                diluent,
+            })
+        }
+        None => None,
+    };
+
+    let mut gas_plan = GasPlan {
+        sac_rate: dive_plan.sac_rate,
+        water,
+        cylinders: Vec::new(),
+    };
+
+    gas_plan.add_cylinder(dive_plan.back_gas);
+
+    for gas in &dive_plan.deco_gasses {
+        gas_plan.add_cylinder(*gas);
     }
 
-    metrics.push(DiveMetric {
-        time: cur_time,
+    let mut gasses = dive_plan
+        .deco_gasses
+        .into_iter()
+        .map(Gas::from)
+        .collect::<Vec<_>>();
+
+    let back_gas = Gas::from(dive_plan.back_gas);
+
+    gasses.push(back_gas);
+
+    for segment in &segments {
+        if let Some(gas) = segment.gas.map(Gas::from) {
+            if !gasses.contains(&gas) {
+                gasses.push(gas);
+            }
+        }
+    }
+
+    let first_gas = closed_circuit.map(|val| val.diluent).unwrap_or(back_gas);
+
+    let mut planner = Planner {
+        model,
+        water,
+        gas_plan,
+        closed_circuit,
+        decompressing: false,
+        descent_rate,
+        ascent_rate,
+        last_stop,
+        max_ppo2,
+        deco_max_ppo2,
+        stages: Vec::new(),
+        metrics: Vec::new(),
+        warnings: Vec::new(),
+        cur_time: 0,
+        cur_depth: 0.,
+        cur_gas: first_gas,
+    };
+
+    let first_gas = GasOutput::from(first_gas);
+
+    planner.metrics.push(DiveMetric {
+        time: 0,
         depth: 0.,
-        pressure: None,
-        temperature: None,
-        o2: None,
-        he: None,
+        o2: Some(first_gas.o2).filter(|val| *val != 21.),
+        he: Some(first_gas.he).filter(|val| *val != 0.),
+        setpoint: planner.setpoint(),
+        ..Default::default()
     });
 
-    let small_chart = crate::chart::render_dive(
-        metrics.clone(),
-        Some(BuhlmannModel::new(config)),
-        Some(469.),
-        Some(288.),
-    )?;
-
-    let big_chart = crate::chart::render_dive(
-        metrics,
-        Some(BuhlmannModel::new(config)),
-        Some(958.),
-        Some(400.),
-    )?;
-
-    Ok(DiveSchedule {
-        runtime: deco_runtime.tts.as_minutes() as i32 + drop_to_bottom_time as i32 + dive_plan.time,
-        tts: deco_runtime.tts.as_minutes() as i32,
-        stages,
-        small_chart,
-        big_chart,
-    })
+    // The bailout with the longest ascent, & when its ascent started
+    let mut bailout: Option<(Planner, i32)> = None;
+
+    for segment in segments {
+        planner.segment(segment, back_gas)?;
+
+        if planner.closed_circuit.is_some() {
+            let mut branch = planner.clone();
+
+            let bottom_end = branch.cur_time;
+
+            branch.bail_out(back_gas)?;
+            branch.deco(gasses.clone())?;
+
+            let is_worse = bailout.as_ref().is_none_or(|(worst, worst_end)| {
+                branch.cur_time - bottom_end > worst.cur_time - worst_end
+            });
+
+            if is_worse {
+                bailout = Some((branch, bottom_end));
+            }
+        }
+    }
+
+    let bottom_end = planner.cur_time;
+
+    let on_loop = planner.closed_circuit.is_some();
+
+    if on_loop {
+        planner.closed_circuit_deco();
+    } else {
+        planner.deco(gasses)?;
+    }
+
+    let bailout = bailout
+        .map(|(branch, bottom_end)| branch.finish(start_model.clone(), bottom_end, on_loop))
+        .transpose()?;
+
+    let mut schedule = planner.finish(start_model, bottom_end, on_loop)?;
+
+    schedule.bailout = bailout.map(Box::new);
+
+    Ok(schedule)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ClosedCircuitInput;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_multi_level_plan() {
+        let air = GasInput {
+            litres: 0.,
+            o2: 21.,
+            he: 0.,
+            working_pressure: None,
+        };
+
+        let nitrox = GasInput { o2: 32., ..air };
+
+        let plan = DivePlanInput {
+            time: 0,
+            depth: 0.,
+            gf_low: 30,
+            gf_high: 70,
+            back_gas: air,
+            segments: Some(vec![
+                PlanSegmentInput {
+                    depth: 30.,
+                    time: 10,
+                    gas: None,
+                },
+                PlanSegmentInput {
+                    depth: 18.,
+                    time: 20,
+                    gas: Some(nitrox),
+                },
+            ]),
+            ..Default::default()
+        };
+
+        let schedule = plan_dive(plan, &[]).expect("Plans dive");
+
+        let levels = schedule
+            .stages
+            .iter()
+            .take(5)
+            .map(|stage| (stage.stage_type, stage.depth))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            levels,
+            vec![
+                (StageType::DESCEND, 30.),
+                (StageType::STAY, 30.),
+                (StageType::GAS_CHANGE, 30.),
+                (StageType::ASCEND, 18.),
+                (StageType::STAY, 18.),
+            ]
+        );
+
+        assert!(schedule.runtime >= 31);
+    }
+
+    #[test]
+    fn test_gas_plan() {
+        let plan = DivePlanInput {
+            time: 25,
+            depth: 20.,
+            gf_low: 30,
+            gf_high: 70,
+            back_gas: GasInput {
+                litres: 12.,
+                o2: 21.,
+                he: 0.,
+                working_pressure: None,
+            },
+            sac_rate: Some(20.),
+            ..Default::default()
+        };
+
+        let schedule = plan_dive(plan, &[]).expect("Plans dive");
+
+        let bottom = schedule
+            .stages
+            .iter()
+            .find(|stage| stage.stage_type == StageType::STAY)
+            .expect("Has a bottom stage");
+
+        // 20 litres/min at ~3 bar for 25 minutes
+        assert_eq!(bottom.gas_used, Some(20. * 3.013 * 25.));
+
+        let cylinder = &schedule.cylinders[0];
+
+        assert_eq!(cylinder.start_pressure, 200.);
+        assert!((cylinder.rock_bottom - 40.).abs() < 1.);
+        assert!(cylinder.end_pressure < 200. - 1500. / 12.);
+        assert!(!cylinder.below_reserve);
+        assert!(!schedule.below_reserve);
+    }
+
+    #[test]
+    fn test_last_stop() {
+        let plan = DivePlanInput {
+            time: 30,
+            depth: 40.,
+            gf_low: 30,
+            gf_high: 70,
+            back_gas: GasInput {
+                o2: 21.,
+                ..Default::default()
+            },
+            last_stop: Some(6.),
+            salinity: Some(1.03),
+            ..Default::default()
+        };
+
+        let schedule = plan_dive(plan, &[]).expect("Plans dive");
+
+        let stops = schedule
+            .stages
+            .iter()
+            .filter(|stage| stage.stage_type == StageType::STAY)
+            .map(|stage| stage.depth)
+            .collect::<Vec<_>>();
+
+        assert!(stops.iter().all(|depth| *depth >= 6.), "{stops:?}");
+        assert!(stops.iter().any(|depth| (depth - 6.).abs() < 0.01));
+    }
+
+    #[test]
+    fn test_salinity() {
+        let plan = DivePlanInput {
+            time: 20,
+            depth: 30.,
+            gf_low: 30,
+            gf_high: 70,
+            back_gas: GasInput {
+                o2: 21.,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        for salinity in [0., -1., 2.] {
+            let plan = DivePlanInput {
+                salinity: Some(salinity),
+                ..plan.clone()
+            };
+
+            assert!(plan_dive(plan, &[]).is_err(), "{salinity}");
+        }
+
+        let fresh = DivePlanInput {
+            salinity: Some(1.),
+            ..plan
+        };
+
+        plan_dive(fresh, &[]).expect("Plans dive");
+    }
+
+    #[test]
+    fn test_oxygen_exposure() {
+        let air = GasInput {
+            o2: 21.,
+            ..Default::default()
+        };
+
+        let ean50 = GasInput {
+            o2: 50.,
+            ..Default::default()
+        };
+
+        let plan = DivePlanInput {
+            time: 30,
+            depth: 40.,
+            gf_low: 30,
+            gf_high: 70,
+            back_gas: air,
+            segments: Some(vec![PlanSegmentInput {
+                depth: 40.,
+                time: 30,
+                gas: Some(ean50),
+            }]),
+            ..Default::default()
+        };
+
+        let err = plan_dive(plan.clone(), &[]).expect_err("EAN50 is beyond its MOD");
+
+        assert!(err.to_string().contains("MOD"), "{err}");
+
+        let schedule = plan_dive(
+            DivePlanInput {
+                segments: None,
+                deco_gasses: vec![ean50],
+                deco_max_ppo2: Some(1.4),
+                ..plan
+            },
+            &[],
+        )
+        .expect("Plans dive");
+
+        assert!(schedule.warnings.iter().any(|val| val.starts_with("EAN50")));
+
+        let cns = schedule
+            .stages
+            .iter()
+            .map(|stage| stage.cns)
+            .collect::<Vec<_>>();
+
+        assert!(cns.windows(2).all(|val| val[0] <= val[1]), "{cns:?}");
+        assert!(schedule.cns > 0. && schedule.otu > 0.);
+        assert!(cns.last().is_some_and(|val| *val <= schedule.cns));
+
+        let schedule = plan_dive(
+            DivePlanInput {
+                time: 20,
+                depth: 60.,
+                gf_low: 30,
+                gf_high: 70,
+                back_gas: GasInput {
+                    o2: 10.,
+                    he: 70.,
+                    ..Default::default()
+                },
+                deco_gasses: vec![air],
+                ..Default::default()
+            },
+            &[],
+        )
+        .expect("Plans dive");
+
+        assert!(schedule
+            .warnings
+            .contains(&"10/70 is hypoxic at 0m".to_string()));
+    }
+
+    #[test]
+    fn test_repetitive_dive() {
+        let plan = DivePlanInput {
+            time: 20,
+            depth: 30.,
+            gf_low: 30,
+            gf_high: 70,
+            back_gas: GasInput {
+                o2: 21.,
+                ..Default::default()
+            },
+            surface_interval: Some(60),
+            ..Default::default()
+        };
+
+        let dive = Dive {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            date: Local::now(),
+            duration: 2700,
+            depth: 30.,
+            dive_site_id: None,
+            dive_number: 1,
+            description: String::new(),
+            published: false,
+            deco_model: None,
+            buddy: String::new(),
+            divemaster: String::new(),
+            tags: Vec::new(),
+            visibility: 0,
+            rating: 0,
+        };
+
+        let metrics = [(0, 0.), (120, 30.), (2400, 30.), (2700, 0.)]
+            .into_iter()
+            .map(|(time, depth)| DiveMetric {
+                time,
+                depth,
+                ..Default::default()
+            })
+            .collect();
+
+        let first = plan_dive(plan.clone(), &[]).expect("Plans dive");
+        let second = plan_dive(plan, &[(dive, metrics)]).expect("Plans dive");
+
+        assert!(second.tts > first.tts, "{} > {}", second.tts, first.tts);
+        assert!(second.cns > first.cns);
+    }
+
+    #[test]
+    fn test_impossible_gasses() {
+        let air = GasInput {
+            o2: 21.,
+            ..Default::default()
+        };
+
+        let impossible = GasInput {
+            o2: 80.,
+            he: 30.,
+            ..Default::default()
+        };
+
+        let plan = DivePlanInput {
+            time: 20,
+            depth: 30.,
+            gf_low: 30,
+            gf_high: 70,
+            back_gas: air,
+            ..Default::default()
+        };
+
+        let plans = [
+            DivePlanInput {
+                back_gas: impossible,
+                ..plan.clone()
+            },
+            DivePlanInput {
+                deco_gasses: vec![impossible],
+                ..plan.clone()
+            },
+            DivePlanInput {
+                segments: Some(vec![PlanSegmentInput {
+                    depth: 30.,
+                    time: 20,
+                    gas: Some(impossible),
+                }]),
+                ..plan.clone()
+            },
+            DivePlanInput {
+                closed_circuit: Some(ClosedCircuitInput {
+                    setpoint: 1.3,
+                    deco_setpoint: None,
+                    diluent: impossible,
+                }),
+                ..plan.clone()
+            },
+        ];
+
+        for plan in plans {
+            assert!(plan_dive(plan, &[]).is_err());
+        }
+
+        // A bad mix in a logged dive is skipped rather than failing the plan
+        let dive = Dive {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            date: Local::now(),
+            duration: 600,
+            depth: 10.,
+            dive_site_id: None,
+            dive_number: 1,
+            description: String::new(),
+            published: false,
+            deco_model: None,
+            buddy: String::new(),
+            divemaster: String::new(),
+            tags: Vec::new(),
+            visibility: 0,
+            rating: 0,
+        };
+
+        let metrics = vec![DiveMetric {
+            time: 60,
+            depth: 10.,
+            o2: Some(150.),
+            ..Default::default()
+        }];
+
+        let repetitive = DivePlanInput {
+            surface_interval: Some(60),
+            ..plan
+        };
+
+        plan_dive(repetitive, &[(dive, metrics)]).expect("Plans dive");
+    }
+
+    #[test]
+    fn test_closed_circuit() {
+        let trimix = GasInput {
+            o2: 21.,
+            he: 35.,
+            ..Default::default()
+        };
+
+        let ean50 = GasInput {
+            o2: 50.,
+            ..Default::default()
+        };
+
+        let plan = DivePlanInput {
+            time: 20,
+            depth: 50.,
+            gf_low: 30,
+            gf_high: 70,
+            back_gas: trimix,
+            deco_gasses: vec![ean50],
+            closed_circuit: Some(ClosedCircuitInput {
+                setpoint: 1.3,
+                deco_setpoint: None,
+                diluent: trimix,
+            }),
+            ..Default::default()
+        };
+
+        let schedule = plan_dive(plan.clone(), &[]).expect("Plans dive");
+
+        assert!(schedule
+            .stages
+            .iter()
+            .all(|stage| stage.setpoint == Some(1.3)));
+
+        let bailout = schedule.bailout.expect("Has a bailout");
+
+        assert!(
+            bailout.tts > schedule.tts,
+            "{} > {}",
+            bailout.tts,
+            schedule.tts
+        );
+        assert_eq!(bailout.bottom_time, schedule.bottom_time);
+        assert!(bailout
+            .stages
+            .iter()
+            .any(|stage| stage.stage_type == StageType::GAS_CHANGE));
+        assert!(bailout.bailout.is_none());
+        assert!(!bailout.big_chart.contains("url(#horizontalPipeDeco)"));
+
+        // The loop's ppO2 is held higher than the trimix, so there's less deco than open circuit
+        let open_circuit = plan_dive(
+            DivePlanInput {
+                closed_circuit: None,
+                ..plan.clone()
+            },
+            &[],
+        )
+        .expect("Plans dive");
+
+        assert!(schedule.tts < open_circuit.tts);
+
+        let err = plan_dive(
+            DivePlanInput {
+                closed_circuit: Some(ClosedCircuitInput {
+                    setpoint: 1.6,
+                    deco_setpoint: None,
+                    diluent: trimix,
+                }),
+                ..plan
+            },
+            &[],
+        )
+        .expect_err("Setpoint is above the max ppO2");
+
+        assert!(err.to_string().contains("Setpoints"), "{err}");
+    }
 }
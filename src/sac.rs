@@ -0,0 +1,162 @@
+use crate::schema::{ConsumptionSegment, DiveCylinder, DiveMetric, GasConsumption};
+
+/// How long each segment of a dive is, in seconds
+const SEGMENT_LENGTH: i32 = 300;
+
+/// Works out the surface air consumption from the pressure samples of the primary cylinder.
+///
+/// When there are no pressure samples, the start & end pressures of the cylinder are used across the whole dive instead
+pub fn gas_consumption(
+    metrics: &[DiveMetric],
+    cylinder: Option<&DiveCylinder>,
+) -> Option<GasConsumption> {
+    let size = cylinder.and_then(|val| val.size).filter(|val| *val > 0.);
+
+    let samples = metrics
+        .iter()
+        .filter_map(|val| Some((val.time, val.pressure?)))
+        .collect::<Vec<_>>();
+
+    let (first, last) = match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) if first.0 < last.0 => (*first, *last),
+        _ => {
+            let cylinder = cylinder?;
+
+            (
+                (metrics.first()?.time, cylinder.start_pressure?),
+                (metrics.last()?.time, cylinder.end_pressure?),
+            )
+        }
+    };
+
+    let (sac, average_depth) = segment_sac(metrics, first, last)?;
+
+    let mut segments = Vec::new();
+    let mut start = first;
+
+    for sample in samples.iter().copied() {
+        if sample.0 - start.0 < SEGMENT_LENGTH && sample != last {
+            continue;
+        }
+
+        // Pressure going up is a different cylinder or a noisy transmitter, so isn't worth showing
+        if let Some((sac, average_depth)) = segment_sac(metrics, start, sample) {
+            segments.push(ConsumptionSegment {
+                start: start.0,
+                end: sample.0,
+                average_depth,
+                sac,
+                rmv: size.map(|size| sac * size),
+            });
+        }
+
+        start = sample;
+    }
+
+    Some(GasConsumption {
+        sac,
+        rmv: size.map(|size| sac * size),
+        average_depth,
+        segments,
+    })
+}
+
+/// Returns the SAC in bar/min along with the average depth between two `(time, pressure)` samples
+fn segment_sac(metrics: &[DiveMetric], start: (i32, f32), end: (i32, f32)) -> Option<(f32, f32)> {
+    let minutes = (end.0 - start.0) as f32 / 60.;
+    let used = start.1 - end.1;
+
+    if minutes <= 0. || used < 0. {
+        return None;
+    }
+
+    let average_depth = average_depth(metrics, start.0, end.0)?;
+
+    // 10m of sea water is roughly 1 bar, on top of the 1 bar at the surface
+    let ambient = average_depth / 10. + 1.;
+
+    Some((used / minutes / ambient, average_depth))
+}
+
+/// The time weighted average depth between `start` and `end`
+fn average_depth(metrics: &[DiveMetric], start: i32, end: i32) -> Option<f32> {
+    let mut area = 0.;
+    let mut total = 0.;
+
+    for pair in metrics.windows(2) {
+        let (from, to) = (&pair[0], &pair[1]);
+
+        let overlap = to.time.min(end) - from.time.max(start);
+
+        if overlap <= 0 {
+            continue;
+        }
+
+        area += (from.depth + to.depth) / 2. * overlap as f32;
+        total += overlap as f32;
+    }
+
+    (total > 0.).then(|| area / total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric(time: i32, depth: f32, pressure: Option<f32>) -> DiveMetric {
+        DiveMetric {
+            time,
+            depth,
+            pressure,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gas_consumption() {
+        // 10 minutes at 10m, using 40 bar from a 10l cylinder
+        let metrics = vec![
+            metric(0, 10., Some(200.)),
+            metric(300, 10., Some(180.)),
+            metric(600, 10., Some(160.)),
+        ];
+
+        let cylinder = DiveCylinder {
+            size: Some(10.),
+            ..Default::default()
+        };
+
+        let consumption = gas_consumption(&metrics, Some(&cylinder)).expect("Has consumption");
+
+        assert_eq!(consumption.sac, 2.);
+        assert_eq!(consumption.rmv, Some(20.));
+        assert_eq!(consumption.average_depth, 10.);
+        assert_eq!(consumption.segments.len(), 2);
+        assert_eq!(consumption.segments[1].start, 300);
+        assert_eq!(consumption.segments[1].end, 600);
+    }
+
+    #[test]
+    fn test_gas_consumption_from_cylinder() {
+        let metrics = vec![
+            metric(0, 0., None),
+            metric(600, 20., None),
+            metric(1200, 0., None),
+        ];
+
+        let cylinder = DiveCylinder {
+            start_pressure: Some(200.),
+            end_pressure: Some(140.),
+            ..Default::default()
+        };
+
+        let consumption = gas_consumption(&metrics, Some(&cylinder)).expect("Has consumption");
+
+        // Averages 10m, so 60 bar over 20 minutes at 2 bar is 1.5 bar/min
+        assert_eq!(consumption.sac, 1.5);
+        assert_eq!(consumption.rmv, None);
+        assert!(consumption.segments.is_empty());
+
+        assert_eq!(gas_consumption(&metrics, None), None);
+    }
+}
@@ -6,11 +6,14 @@ mod dive_plan;
 mod dive_site;
 mod email_verification;
 mod feedback;
+mod gas_calculator;
 mod og_reference;
 mod password_reset;
 mod photo;
 mod region;
 mod sealife;
+mod stats;
+mod subsurface;
 mod user;
 
 pub use categories::*;
@@ -20,9 +23,12 @@ pub use dive_plan::*;
 pub use dive_site::*;
 pub use email_verification::*;
 pub use feedback::*;
+pub use gas_calculator::*;
 pub use og_reference::*;
 pub use password_reset::*;
 pub use photo::*;
 pub use region::*;
 pub use sealife::*;
+pub use stats::*;
+pub use subsurface::*;
 pub use user::*;
@@ -1,6 +1,7 @@
 use crate::{
     escape::{md_to_text, truncate},
     graphql::SchemaContext,
+    sac::gas_consumption,
 };
 use async_graphql::*;
 use chrono::prelude::*;
@@ -8,9 +9,11 @@ use divedb_core::FromRow;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::{DiveComment, DiveCommentQuery, DiveSite, Photo, PhotoQuery, PublicUserInfo};
+use super::{
+    DiveComment, DiveCommentQuery, DiveSite, Photo, PhotoQuery, PublicUserInfo, SavedDivePlan,
+};
 
-#[derive(Serialize, Deserialize, Debug, Clone, SimpleObject, FromRow)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, SimpleObject, FromRow)]
 pub struct DiveMetric {
     pub time: i32,
     pub depth: f32,
@@ -18,6 +21,45 @@ pub struct DiveMetric {
     pub temperature: Option<f32>,
     pub o2: Option<f32>,
     pub he: Option<f32>,
+    /// Which dive computer recorded this, with `0` being the primary computer
+    pub computer: i32,
+    /// Measured ppO2 in bar, averaged across the oxygen cells
+    pub ppo2: Option<f32>,
+    /// The CCR setpoint in bar
+    pub setpoint: Option<f32>,
+    /// CNS oxygen toxicity as a percentage
+    pub cns: Option<f32>,
+    /// No decompression limit in seconds
+    pub ndl: Option<i32>,
+    /// Time to surface in seconds
+    pub tts: Option<i32>,
+    /// Heart rate in beats per minute
+    pub heart_rate: Option<i32>,
+}
+
+/// A dive computer used on a dive, with `computer` being `0` for the primary computer
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, SimpleObject, FromRow)]
+pub struct DiveComputer {
+    pub computer: i32,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+}
+
+/// Tank pressure from an additional sensor, where `sensor` is the cylinder index
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, SimpleObject, FromRow)]
+pub struct SensorPressure {
+    pub computer: i32,
+    pub time: i32,
+    pub sensor: i32,
+    pub pressure: f32,
+}
+
+/// Everything recorded by the dive computers on a dive
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DiveProfile {
+    pub metrics: Vec<DiveMetric>,
+    pub computers: Vec<DiveComputer>,
+    pub pressures: Vec<SensorPressure>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, FromRow)]
@@ -32,6 +74,55 @@ pub struct Dive {
     pub description: String,
     pub published: bool,
     pub deco_model: Option<String>,
+    pub buddy: String,
+    pub divemaster: String,
+    pub tags: Vec<String>,
+    pub visibility: i32,
+    pub rating: i32,
+}
+
+/// Surface air consumption, with `sac` in bar/min and `rmv` in litres/min.  The `rmv` is only known when the cylinder size is
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, SimpleObject)]
+pub struct GasConsumption {
+    pub sac: f32,
+    pub rmv: Option<f32>,
+    pub average_depth: f32,
+    pub segments: Vec<ConsumptionSegment>,
+}
+
+/// Consumption over part of a dive, with `start` and `end` in seconds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, SimpleObject)]
+pub struct ConsumptionSegment {
+    pub start: i32,
+    pub end: i32,
+    pub average_depth: f32,
+    pub sac: f32,
+    pub rmv: Option<f32>,
+}
+
+/// A cylinder used on a dive. Sizes are in litres, pressures in bar and the mix in percent
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Default, PartialEq, SimpleObject, InputObject, FromRow,
+)]
+#[graphql(input_name = "DiveCylinderInput")]
+pub struct DiveCylinder {
+    pub description: Option<String>,
+    pub size: Option<f32>,
+    pub working_pressure: Option<f32>,
+    pub start_pressure: Option<f32>,
+    pub end_pressure: Option<f32>,
+    pub o2: Option<f32>,
+    pub he: Option<f32>,
+}
+
+/// A weight system used on a dive, in kilograms
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Default, PartialEq, SimpleObject, InputObject, FromRow,
+)]
+#[graphql(input_name = "DiveWeightInput")]
+pub struct DiveWeight {
+    pub description: Option<String>,
+    pub weight: f32,
 }
 
 #[Object]
@@ -64,6 +155,55 @@ impl Dive {
         self.depth as f64
     }
 
+    async fn buddy(&self) -> &str {
+        &self.buddy
+    }
+
+    async fn divemaster(&self) -> &str {
+        &self.divemaster
+    }
+
+    async fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Visibility from 1 to 5, or 0 if not recorded
+    async fn visibility(&self) -> i32 {
+        self.visibility
+    }
+
+    /// Rating from 1 to 5, or 0 if not rated
+    async fn rating(&self) -> i32 {
+        self.rating
+    }
+
+    async fn cylinders(&self, context: &Context<'_>) -> FieldResult<Vec<DiveCylinder>> {
+        Ok(context
+            .data::<SchemaContext>()?
+            .web
+            .handle
+            .dive_cylinders(self.id)
+            .await?)
+    }
+
+    async fn weights(&self, context: &Context<'_>) -> FieldResult<Vec<DiveWeight>> {
+        Ok(context
+            .data::<SchemaContext>()?
+            .web
+            .handle
+            .dive_weights(self.id)
+            .await?)
+    }
+
+    async fn computers(&self, context: &Context<'_>) -> FieldResult<Vec<DiveComputer>> {
+        Ok(context
+            .data::<SchemaContext>()?
+            .web
+            .handle
+            .dive_computers(self.id)
+            .await?)
+    }
+
     async fn latest_photos(&self, context: &Context<'_>) -> FieldResult<Vec<Photo>> {
         let query = PhotoQuery {
             dive: Some(self.id),
@@ -136,6 +276,30 @@ impl Dive {
             .await?)
     }
 
+    async fn gas_consumption(&self, context: &Context<'_>) -> FieldResult<Option<GasConsumption>> {
+        let handle = &context.data::<SchemaContext>()?.web.handle;
+
+        let metrics = handle.dive_metrics(self.id).await?;
+        let cylinders = handle.dive_cylinders(self.id).await?;
+
+        Ok(gas_consumption(&metrics, cylinders.first()))
+    }
+
+    /// The saved plans this dive was executed as, which only the diver can see
+    async fn plans(&self, context: &Context<'_>) -> FieldResult<Vec<SavedDivePlan>> {
+        let context = context.data::<SchemaContext>()?;
+
+        if context.con.user.as_ref().map(|val| val.id) != Some(self.user_id) {
+            return Ok(Vec::new());
+        }
+
+        Ok(context
+            .web
+            .handle
+            .dive_plans(self.user_id, Some(self.id))
+            .await?)
+    }
+
     async fn has_metrics(&self, context: &Context<'_>) -> FieldResult<bool> {
         Ok(context
             .data::<SchemaContext>()?
@@ -174,6 +338,9 @@ impl Dive {
     }
 }
 
+/// Creates or updates a dive.
+///
+/// The buddy, divemaster, tags, visibility, rating, cylinders & weights are left as they are when not provided
 #[derive(Serialize, Deserialize, Debug, Clone, InputObject)]
 pub struct CreateDive {
     pub id: Option<Uuid>,
@@ -184,6 +351,13 @@ pub struct CreateDive {
     pub description: String,
     pub published: bool,
     pub deco_model: Option<String>,
+    pub buddy: Option<String>,
+    pub divemaster: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub visibility: Option<i32>,
+    pub rating: Option<i32>,
+    pub cylinders: Option<Vec<DiveCylinder>>,
+    pub weights: Option<Vec<DiveWeight>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, InputObject, Default)]
@@ -1,8 +1,16 @@
 use async_graphql::*;
+use chrono::prelude::*;
 use dive_deco::{DecoStageType, Gas};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug, Clone, InputObject)]
+use divedb_core::FromRow;
+
+use crate::{graphql::SchemaContext, SITE_URL};
+
+use super::{Dive, DiveMetric, DiveQuery};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, InputObject)]
 pub struct DivePlanInput {
     pub time: i32,
     pub depth: f32,
@@ -10,13 +18,57 @@ pub struct DivePlanInput {
     pub gf_high: usize,
     pub back_gas: GasInput,
     pub deco_gasses: Vec<GasInput>,
+    /// Surface air consumption in litres/min, used to work out the gas needed for each stage
+    pub sac_rate: Option<f32>,
+    /// Descent rate in m/min, defaulting to 18 m/min
+    pub descent_rate: Option<f32>,
+    /// Ascent rate in m/min, defaulting to 10 m/min
+    pub ascent_rate: Option<f32>,
+    /// Depth of the last deco stop in meters, defaulting to 3m
+    pub last_stop: Option<f32>,
+    /// Water density in kg/ℓ, i.e, `1.0` for fresh & `1.03` for salt water.  Defaults to 10m being 1 bar
+    pub salinity: Option<f32>,
+    /// Altitude of the dive site in meters, defaulting to sea level
+    pub altitude: Option<f32>,
+    /// The highest ppO2 allowed for the gasses breathed at each level, defaulting to 1.4 bar
+    pub max_ppo2: Option<f32>,
+    /// The highest ppO2 allowed for deco gasses, defaulting to 1.6 bar
+    pub deco_max_ppo2: Option<f32>,
+    /// Minutes since the end of the diver's last logged dive.  When provided, the dives logged in the day before it are replayed first, so the plan accounts for residual inert gas
+    pub surface_interval: Option<i32>,
+    /// The levels of a multi-level dive, in order.  When provided, these are used instead of `depth` and `time`
+    pub segments: Option<Vec<PlanSegmentInput>>,
+    /// Plans the dive on a closed circuit rebreather.  The back gas & deco gasses are then the open circuit bailout
+    pub closed_circuit: Option<ClosedCircuitInput>,
 }
 
+/// A closed circuit rebreather held at a constant setpoint
 #[derive(Serialize, Deserialize, Copy, Debug, Clone, InputObject)]
+pub struct ClosedCircuitInput {
+    /// The ppO2 of the loop in bar
+    pub setpoint: f32,
+    /// The ppO2 of the loop in bar during the final ascent, defaulting to the setpoint
+    pub deco_setpoint: Option<f32>,
+    pub diluent: GasInput,
+}
+
+/// A level of a dive plan, travelled to from the previous level
+#[derive(Serialize, Deserialize, Copy, Debug, Clone, InputObject)]
+pub struct PlanSegmentInput {
+    pub depth: f32,
+    pub time: i32,
+    /// The gas breathed at this level, defaulting to the back gas
+    pub gas: Option<GasInput>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Debug, Clone, Default, InputObject)]
 pub struct GasInput {
+    /// The size of the cylinder in litres
     pub litres: f32,
     pub o2: f32,
     pub he: f32,
+    /// The pressure the cylinder is filled to in bar, defaulting to 200 bar
+    pub working_pressure: Option<f32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, SimpleObject)]
@@ -36,6 +88,18 @@ impl From<Gas> for GasOutput {
     }
 }
 
+impl std::fmt::Display for GasOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.he > 0. {
+            write!(f, "{:.0}/{:.0}", self.o2, self.he)
+        } else if self.o2 == 21. {
+            write!(f, "Air")
+        } else {
+            write!(f, "EAN{:.0}", self.o2)
+        }
+    }
+}
+
 impl From<GasInput> for Gas {
     fn from(val: GasInput) -> Self {
         Gas::new((val.o2 / 100.) as f64, (val.he / 100.) as f64)
@@ -46,9 +110,41 @@ impl From<GasInput> for Gas {
 pub struct DiveSchedule {
     pub runtime: i32,
     pub tts: i32,
+    /// Minutes from the start of the dive until the final ascent
+    pub bottom_time: i32,
     pub stages: Vec<DiveStage>,
+    /// The gas used from each cylinder, when a SAC rate is provided
+    pub cylinders: Vec<CylinderUsage>,
+    /// Whether any cylinder ends the dive below its rock bottom reserve
+    pub below_reserve: bool,
+    /// The CNS oxygen toxicity percentage at the end of the dive
+    pub cns: f32,
+    /// The oxygen tolerance units accumulated over the dive
+    pub otu: f32,
+    /// Problems with the gasses breathed, such as a deco gas beyond its MOD or a hypoxic mix used shallow
+    pub warnings: Vec<String>,
+    /// For closed circuit plans, the open circuit ascent from the point of the dive that takes longest to surface
+    pub bailout: Option<Box<DiveSchedule>>,
     pub small_chart: String,
     pub big_chart: String,
+    /// The planned profile, for comparing against a logged dive
+    #[graphql(skip)]
+    pub metrics: Vec<DiveMetric>,
+}
+
+/// The planned use of a cylinder, with pressures in bar and volumes in litres
+#[derive(Serialize, Deserialize, Debug, Clone, SimpleObject)]
+pub struct CylinderUsage {
+    pub gas: GasOutput,
+    pub size: f32,
+    pub start_pressure: f32,
+    pub end_pressure: f32,
+    pub litres_used: f32,
+    /// The minimum gas needed to get two divers from the deepest point this gas is breathed to the surface
+    pub rock_bottom: f32,
+    /// The pressure to turn the dive at when following the rule of thirds
+    pub turn_pressure: f32,
+    pub below_reserve: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, SimpleObject)]
@@ -57,6 +153,14 @@ pub struct DiveStage {
     pub time: i32,
     pub depth: f32,
     pub gas: Option<GasOutput>,
+    /// Litres of gas used during this stage, when a SAC rate is provided
+    pub gas_used: Option<f32>,
+    /// The loop setpoint in bar, when on a closed circuit rebreather
+    pub setpoint: Option<f32>,
+    /// The CNS oxygen toxicity percentage at the end of this stage
+    pub cns: f32,
+    /// The oxygen tolerance units accumulated by the end of this stage
+    pub otu: f32,
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -78,3 +182,155 @@ impl From<DecoStageType> for StageType {
         }
     }
 }
+
+/// A dive plan saved by a user.  Anyone with the id of a plan can view it, so it can be shared by URL
+#[derive(Debug, Clone, FromRow)]
+pub struct SavedDivePlan {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub notes: String,
+    pub plan: postgres_types::Json<DivePlanInput>,
+    pub dive_id: Option<Uuid>,
+    pub created: DateTime<Local>,
+    pub updated: DateTime<Local>,
+    /// The dives replayed before this plan when it was saved
+    pub previous_dive_ids: Vec<Uuid>,
+}
+
+#[Object]
+impl SavedDivePlan {
+    async fn id(&self) -> Uuid {
+        self.id
+    }
+
+    async fn user_id(&self) -> Uuid {
+        self.user_id
+    }
+
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notes(&self) -> &str {
+        &self.notes
+    }
+
+    /// The `DivePlanInput` the plan was saved with
+    async fn plan(&self) -> Json<&DivePlanInput> {
+        Json(&self.plan.0)
+    }
+
+    async fn url(&self) -> String {
+        format!("{}/dives/plan/{}", &*SITE_URL, self.id)
+    }
+
+    async fn created(&self) -> DateTime<Utc> {
+        self.created.into()
+    }
+
+    async fn updated(&self) -> DateTime<Utc> {
+        self.updated.into()
+    }
+
+    async fn dive_id(&self) -> Option<Uuid> {
+        self.dive_id
+    }
+
+    /// The dive this plan was executed as
+    async fn dive(&self, context: &Context<'_>) -> FieldResult<Option<Dive>> {
+        let Some(dive_id) = self.dive_id else {
+            return Ok(None);
+        };
+
+        let context = context.data::<SchemaContext>()?;
+
+        let mut dives = context
+            .web
+            .handle
+            .dives(
+                context.con.user.as_ref().map(|val| val.id),
+                &DiveQuery::id(dive_id),
+            )
+            .await?;
+
+        Ok(dives.pop())
+    }
+
+    /// How the linked dive went compared to this plan
+    async fn comparison(&self, context: &Context<'_>) -> FieldResult<Option<DivePlanComparison>> {
+        let Some(dive_id) = self.dive_id else {
+            return Ok(None);
+        };
+
+        let context = context.data::<SchemaContext>()?;
+
+        Ok(Some(
+            crate::comparison::compare_dive(
+                &context.web.handle,
+                context.con.user.as_ref().map(|val| val.id),
+                dive_id,
+                self.plan.0.clone(),
+            )
+            .await?,
+        ))
+    }
+
+    /// The schedule for the plan, replanned with the current planner
+    async fn schedule(&self, context: &Context<'_>) -> FieldResult<DiveSchedule> {
+        let context = context.data::<SchemaContext>()?;
+
+        let previous_dives = context
+            .web
+            .handle
+            .dive_plan_previous_dives(self, context.con.user.as_ref().map(|val| val.id))
+            .await?;
+
+        Ok(crate::plan::plan_dive(
+            self.plan.0.clone(),
+            &previous_dives,
+        )?)
+    }
+}
+
+/// How a logged dive went compared to a plan for it
+#[derive(Serialize, Deserialize, Debug, Clone, SimpleObject)]
+pub struct DivePlanComparison {
+    pub schedule: DiveSchedule,
+    pub deviations: Vec<PlanDeviation>,
+    /// The logged profile, with the planned profile overlaid
+    pub chart: String,
+}
+
+/// Where a dive strayed from its plan.  `planned` & `actual` are in meters for depths, minutes for times & m/min for ascent rates
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, SimpleObject)]
+pub struct PlanDeviation {
+    pub deviation_type: DeviationType,
+    /// Seconds into the dive
+    pub time: i32,
+    pub depth: f32,
+    pub planned: f32,
+    pub actual: f32,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Enum)]
+pub enum DeviationType {
+    DEEPER,
+    LONGER_BOTTOM_TIME,
+    MISSED_STOP,
+    SHORTENED_STOP,
+    FAST_ASCENT,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, InputObject)]
+pub struct SaveDivePlan {
+    /// The plan to update, or a new plan when not provided
+    pub id: Option<Uuid>,
+    pub name: String,
+    pub notes: String,
+    pub plan: DivePlanInput,
+    /// The dive this plan was executed as
+    pub dive_id: Option<Uuid>,
+}
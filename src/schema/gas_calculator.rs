@@ -0,0 +1,50 @@
+use async_graphql::*;
+use serde::{Deserialize, Serialize};
+
+use super::{GasInput, GasOutput};
+
+/// What to find the best mix for
+#[derive(Serialize, Deserialize, Copy, Debug, Clone, InputObject)]
+pub struct BestMixInput {
+    pub depth: f32,
+    /// The highest ppO2 allowed at `depth`, defaulting to 1.4 bar
+    pub max_ppo2: Option<f32>,
+    /// The deepest equivalent narcotic depth allowed, defaulting to 30m
+    pub max_end: Option<f32>,
+    /// Whether oxygen counts as narcotic, defaulting to true
+    pub oxygen_narcotic: Option<bool>,
+    /// Water density in kg/ℓ, defaulting to 10m being 1 bar
+    pub salinity: Option<f32>,
+    /// Altitude in meters, defaulting to sea level
+    pub altitude: Option<f32>,
+}
+
+/// A cylinder to partial pressure blend, with pressures in bar
+#[derive(Serialize, Deserialize, Copy, Debug, Clone, InputObject)]
+pub struct GasBlendInput {
+    /// What's in the cylinder now
+    pub current: GasInput,
+    pub current_pressure: f32,
+    pub target: GasInput,
+    pub target_pressure: f32,
+    /// The gas the cylinder is topped up with after the helium & oxygen, defaulting to air
+    pub top_up: Option<GasInput>,
+}
+
+/// How to blend a cylinder, treating the gasses as ideal
+#[derive(Serialize, Deserialize, Debug, Clone, SimpleObject)]
+pub struct GasBlend {
+    /// The pressure to drain the cylinder down to before blending.  This is the current pressure when nothing needs draining
+    pub drain_to: f32,
+    /// The gasses to add, in order
+    pub steps: Vec<BlendStep>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, SimpleObject)]
+pub struct BlendStep {
+    pub gas: GasOutput,
+    /// The bar of this gas to add
+    pub added: f32,
+    /// The pressure the cylinder is filled to after this step
+    pub pressure: f32,
+}
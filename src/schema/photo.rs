@@ -1,8 +1,14 @@
-use super::{Dive, DiveQuery, DiveSite, PublicUserInfo, Sealife, SealifeQuery};
-use crate::graphql::SchemaContext;
+use std::collections::HashMap;
+
+use super::{Dive, DiveQuery, DiveSite, PublicUserInfo, Sealife, SealifeQuery, User};
+use crate::{db::DbHandle, graphql::SchemaContext};
 use async_graphql::*;
+use async_trait::async_trait;
 use chrono::prelude::*;
+use dataloader::non_cached::Loader;
+use dataloader::BatchFn;
 use serde::{Deserialize, Serialize};
+use tracing::*;
 use uuid::Uuid;
 
 use divedb_core::FromRow;
@@ -23,6 +29,10 @@ pub struct Photo {
     pub description: String,
     pub copyright: Option<String>,
     pub internal: bool,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub camera: Option<String>,
+    pub camera_offset: i32,
 }
 
 impl Photo {
@@ -68,12 +78,51 @@ impl Photo {
         )
     }
 
+    /// The filename resized jpeg copies are kept under, which only differs from the original when it's not a jpeg itself
+    fn jpg_filename(&self) -> String {
+        match self.filename.rsplit_once('.') {
+            Some((stem, extension))
+                if !["jpg", "jpeg"].contains(&extension.to_lowercase().as_str()) =>
+            {
+                format!("{stem}.jpg")
+            }
+            _ => self.filename.clone(),
+        }
+    }
+
     pub fn jpg_thumb_location(&self) -> String {
-        format!("{}/{}", self.thumb_prefix(), self.filename)
+        format!("{}/{}", self.thumb_prefix(), self.jpg_filename())
     }
 
     pub fn jpg_large_location(&self) -> String {
-        format!("{}/{}", self.large_prefix(), self.filename)
+        format!("{}/{}", self.large_prefix(), self.jpg_filename())
+    }
+
+    /// Whether where the photo was taken can be shown, which is hidden for sensitive sealife unless it's the photographer or an editor looking
+    fn location_visible(&self, user: Option<&User>) -> bool {
+        !self.hide_location.unwrap_or_default()
+            || user.is_some_and(|user| user.is_editor() || user.id == self.user_id)
+    }
+
+    /// Where the cropped thumbnail of a species tagged in the photo is kept.  Each box gets its own, so moving one doesn't serve a cached crop
+    pub fn crop_location(&self, tag: &SealifeTag) -> String {
+        format!(
+            "crops/{}/{}-{}.jpg",
+            self.uuid_folder(),
+            tag.sealife_id,
+            tag.crop_version()
+        )
+    }
+
+    /// Every location the photo is stored at: the original & its resized copies
+    pub fn locations(&self) -> [String; 5] {
+        [
+            self.orig_location(),
+            self.jpg_thumb_location(),
+            self.webp_thumb_location(),
+            self.jpg_large_location(),
+            self.webp_large_location(),
+        ]
     }
 }
 
@@ -146,25 +195,62 @@ impl Photo {
             .pop())
     }
 
-    async fn dive_site(&self, context: &Context<'_>) -> FieldResult<Option<DiveSite>> {
-        let mut hide_location = self.hide_location.unwrap_or_default();
+    /// Every species tagged in the photo, with where they are in it
+    async fn sealife_tags(&self, context: &Context<'_>) -> FieldResult<Vec<SealifeTag>> {
+        Ok(context
+            .data::<SchemaContext>()?
+            .web
+            .sealife_tag_batch
+            .load(self.id)
+            .await)
+    }
 
+    /// The latitude the photo was taken at
+    async fn lat(&self, context: &Context<'_>) -> FieldResult<Option<f64>> {
         let context = context.data::<SchemaContext>()?;
 
-        let mut editor = false;
+        Ok(self
+            .lat
+            .filter(|_| self.location_visible(context.con.user.as_ref())))
+    }
 
-        if let Some(user) = context.con.user.as_ref() {
-            if user.is_editor() || user.id == self.user_id {
-                hide_location = false;
-            }
+    /// The longitude the photo was taken at
+    async fn lon(&self, context: &Context<'_>) -> FieldResult<Option<f64>> {
+        let context = context.data::<SchemaContext>()?;
 
-            editor = user.is_editor();
-        }
+        Ok(self
+            .lon
+            .filter(|_| self.location_visible(context.con.user.as_ref())))
+    }
 
-        if hide_location {
+    /// The camera the photo was taken with, which is only shown to the photographer as it can include a serial number
+    async fn camera(&self, context: &Context<'_>) -> FieldResult<Option<&str>> {
+        let context = context.data::<SchemaContext>()?;
+
+        Ok(self
+            .camera
+            .as_deref()
+            .filter(|_| context.con.user.as_ref().map(|user| user.id) == Some(self.user_id)))
+    }
+
+    /// The seconds added to the camera's clock to get the date
+    async fn camera_offset(&self) -> i32 {
+        self.camera_offset
+    }
+
+    async fn dive_site(&self, context: &Context<'_>) -> FieldResult<Option<DiveSite>> {
+        let context = context.data::<SchemaContext>()?;
+
+        if !self.location_visible(context.con.user.as_ref()) {
             return Ok(None);
         }
 
+        let editor = context
+            .con
+            .user
+            .as_ref()
+            .is_some_and(|user| user.is_editor());
+
         if let Some(id) = self.dive_site_id {
             let dive_site = context.web.dive_batch.load(id).await;
 
@@ -228,6 +314,154 @@ pub struct CreatePhoto {
     pub internal: Option<bool>,
     pub dive_site_id: Option<Uuid>,
     pub sealife_id: Option<Uuid>,
+    /// Where the photo was taken, which is only set when uploading
+    #[graphql(skip)]
+    pub lat: Option<f64>,
+    #[graphql(skip)]
+    pub lon: Option<f64>,
+    /// The camera the photo was taken with & the seconds its clock was corrected by, also only set when uploading
+    #[graphql(skip)]
+    pub camera: Option<String>,
+    #[graphql(skip)]
+    pub camera_offset: i32,
+}
+
+#[derive(Clone)]
+pub struct SealifeTagBatcher {
+    pub handle: DbHandle,
+}
+
+/// Loads the tags of each photo.  This doesn't cache, as tags change whenever a photo is tagged
+pub type SealifeTagLoader = Loader<Uuid, Vec<SealifeTag>, SealifeTagBatcher>;
+
+impl SealifeTagBatcher {
+    pub fn new(handle: &DbHandle) -> SealifeTagLoader {
+        let batcher = SealifeTagBatcher {
+            handle: handle.clone(),
+        };
+
+        Loader::new(batcher)
+    }
+}
+
+#[async_trait]
+impl BatchFn<Uuid, Vec<SealifeTag>> for SealifeTagBatcher {
+    async fn load(&mut self, keys: &[Uuid]) -> HashMap<Uuid, Vec<SealifeTag>> {
+        debug!("Got load for keys:{:?}", keys);
+
+        // Photos without tags still need an entry
+        let mut hashmap = keys
+            .iter()
+            .map(|key| (*key, Vec::new()))
+            .collect::<HashMap<_, _>>();
+
+        match self.handle.sealife_tags_batch(keys).await {
+            Ok(tags) => {
+                for (photo_id, tag) in tags {
+                    hashmap.entry(photo_id).or_default().push(tag);
+                }
+            }
+            Err(err) => {
+                error!("Error getting keys:{}", err);
+            }
+        }
+
+        hashmap
+    }
+}
+
+/// A species tagged in a photo, boxed by fractions of the photo's width (`u`) & height (`v`) from the top left.  The box is all zeros when the species isn't boxed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, SimpleObject, FromRow)]
+#[graphql(complex)]
+pub struct SealifeTag {
+    pub sealife_id: Uuid,
+    pub u_min: f64,
+    pub v_min: f64,
+    pub u_max: f64,
+    pub v_max: f64,
+}
+
+impl SealifeTag {
+    pub fn is_boxed(&self) -> bool {
+        self.u_max > self.u_min && self.v_max > self.v_min
+    }
+
+    /// A short hash of the box, which changes whenever the box does
+    pub fn crop_version(&self) -> String {
+        let bytes = [self.u_min, self.v_min, self.u_max, self.v_max]
+            .iter()
+            .flat_map(|val| val.to_be_bytes())
+            .collect::<Vec<_>>();
+
+        format!(
+            "{:08x}",
+            Uuid::new_v5(&self.sealife_id, &bytes).as_fields().0
+        )
+    }
+}
+
+#[ComplexObject]
+impl SealifeTag {
+    /// The cropped thumbnail is at `/api/photos/crop/{photoId}/{sealifeId}/{cropVersion}`
+    #[graphql(name = "cropVersion")]
+    async fn crop_version_field(&self) -> String {
+        self.crop_version()
+    }
+
+    async fn sealife(&self, context: &Context<'_>) -> FieldResult<Sealife> {
+        Ok(context
+            .data::<SchemaContext>()?
+            .web
+            .sealife_batch
+            .load(self.sealife_id)
+            .await)
+    }
+}
+
+/// A species to tag in a photo, boxed by fractions of the photo's size from the top left.  Leaving out the box tags the whole photo
+#[derive(Serialize, Deserialize, Debug, Clone, InputObject)]
+pub struct SealifeTagInput {
+    pub sealife_id: Uuid,
+    pub u_min: Option<f64>,
+    pub v_min: Option<f64>,
+    pub u_max: Option<f64>,
+    pub v_max: Option<f64>,
+}
+
+impl TryFrom<SealifeTagInput> for SealifeTag {
+    type Error = anyhow::Error;
+
+    fn try_from(input: SealifeTagInput) -> Result<Self, Self::Error> {
+        let tag = SealifeTag {
+            sealife_id: input.sealife_id,
+            u_min: input.u_min.unwrap_or_default(),
+            v_min: input.v_min.unwrap_or_default(),
+            u_max: input.u_max.unwrap_or_default(),
+            v_max: input.v_max.unwrap_or_default(),
+        };
+
+        let in_photo = [tag.u_min, tag.v_min, tag.u_max, tag.v_max]
+            .iter()
+            .all(|val| (0. ..=1.).contains(val));
+
+        let unboxed = [tag.u_min, tag.v_min, tag.u_max, tag.v_max] == [0.; 4];
+
+        if !in_photo || !(tag.is_boxed() || unboxed) {
+            return Err(anyhow::anyhow!(
+                "Sealife boxes need to be within the photo, with the minimums below the maximums"
+            ));
+        }
+
+        Ok(tag)
+    }
+}
+
+/// How far a camera's clock is out, as the seconds to add to it to get the real time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, SimpleObject, InputObject, FromRow)]
+#[graphql(input_name = "CameraOffsetInput")]
+pub struct CameraOffset {
+    pub camera: String,
+    pub clock_offset: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, InputObject, Default)]
@@ -0,0 +1,94 @@
+use async_graphql::*;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::graphql::SchemaContext;
+
+use super::{Dive, DiveSite};
+
+/// Logbook statistics for a user, across the dives the viewer is able to see
+#[derive(Serialize, Deserialize, Debug, Clone, Default, SimpleObject)]
+pub struct DiveStats {
+    pub dive_count: i64,
+    /// Total time underwater in seconds
+    pub total_bottom_time: i64,
+    /// The average of each dive's maximum depth
+    pub average_depth: Option<f64>,
+    pub deepest_dive: Option<Dive>,
+    pub longest_dive: Option<Dive>,
+    pub min_temperature: Option<f32>,
+    pub max_temperature: Option<f32>,
+    pub dives_per_year: Vec<YearCount>,
+    pub dives_per_month: Vec<MonthCount>,
+    /// Dives grouped by maximum depth, in 5 meter increments
+    pub depth_histogram: Vec<DepthBucket>,
+    pub gas_mixes: Vec<GasMixCount>,
+    pub top_sites: Vec<SiteCount>,
+}
+
+/// How wide each bucket of the depth histogram is, in meters
+pub const DEPTH_BUCKET: i32 = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, SimpleObject)]
+pub struct YearCount {
+    pub year: i32,
+    pub dive_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, SimpleObject)]
+pub struct MonthCount {
+    pub year: i32,
+    pub month: i32,
+    pub dive_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, SimpleObject)]
+pub struct DepthBucket {
+    pub min_depth: i32,
+    pub max_depth: i32,
+    pub dive_count: i64,
+}
+
+/// A gas mix in percent, with the number of dives it was breathed or carried on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, SimpleObject)]
+pub struct GasMixCount {
+    pub o2: f32,
+    pub he: f32,
+    pub dive_count: i64,
+}
+
+/// The gas consumption of a single dive, with `sac` in bar/min and `rmv` in litres/min
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, SimpleObject)]
+pub struct SacTrendPoint {
+    pub dive_id: Uuid,
+    pub date: DateTime<Utc>,
+    pub sac: f32,
+    pub rmv: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SiteCount {
+    pub dive_site_id: Uuid,
+    pub dive_count: i64,
+}
+
+#[Object]
+impl SiteCount {
+    async fn dive_site_id(&self) -> &Uuid {
+        &self.dive_site_id
+    }
+
+    async fn dive_count(&self) -> i64 {
+        self.dive_count
+    }
+
+    async fn dive_site(&self, context: &Context<'_>) -> FieldResult<DiveSite> {
+        Ok(context
+            .data::<SchemaContext>()?
+            .web
+            .dive_batch
+            .load(self.dive_site_id)
+            .await)
+    }
+}
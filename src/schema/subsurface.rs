@@ -0,0 +1,87 @@
+use std::ops::AddAssign;
+
+use async_graphql::*;
+use chrono::prelude::*;
+use postgres_types::{FromSql, ToSql};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use divedb_core::FromRow;
+
+#[derive(Serialize, Deserialize, Debug, Clone, InputObject, Default)]
+pub struct SubsurfaceRemote {
+    pub url: Option<String>,
+    pub branch: Option<String>,
+    pub auth: Option<SubsurfaceAuth>,
+    pub ssh_key: Option<String>,
+    pub ssh_passphrase: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq, Enum)]
+pub enum SubsurfaceAuth {
+    #[default]
+    UserPass,
+    SshKey,
+    None,
+}
+
+/// The last commit that was imported for a user, so the next sync only has to look at what changed
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+pub struct SubsurfaceSync {
+    pub user_id: Uuid,
+    pub remote_url: String,
+    pub branch: String,
+    pub commit_id: String,
+    pub date: DateTime<Local>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, ToSql, FromSql, Enum)]
+pub enum SyncState {
+    Queued,
+    Cloning,
+    Parsing,
+    Importing,
+    Done,
+    Failed,
+}
+
+impl SyncState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, SyncState::Done | SyncState::Failed)
+    }
+}
+
+/// A background subsurface sync.  Credentials are never stored, only the progress of the job
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow, SimpleObject)]
+pub struct SyncJob {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub state: SyncState,
+    pub sites_created: i32,
+    pub sites_skipped: i32,
+    pub dives_created: i32,
+    pub dives_updated: i32,
+    pub dives_skipped: i32,
+    pub error: Option<String>,
+    pub created: DateTime<Local>,
+    pub updated: DateTime<Local>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ImportSummary {
+    pub sites_created: i32,
+    pub sites_skipped: i32,
+    pub dives_created: i32,
+    pub dives_updated: i32,
+    pub dives_skipped: i32,
+}
+
+impl AddAssign for ImportSummary {
+    fn add_assign(&mut self, other: Self) {
+        self.sites_created += other.sites_created;
+        self.sites_skipped += other.sites_skipped;
+        self.dives_created += other.dives_created;
+        self.dives_updated += other.dives_updated;
+        self.dives_skipped += other.dives_skipped;
+    }
+}
@@ -7,7 +7,7 @@ use uuid::Uuid;
 
 use crate::graphql::SchemaContext;
 
-use super::{Dive, DiveQuery, Photo, PhotoQuery};
+use super::{Dive, DiveQuery, DiveStats, Photo, PhotoQuery, SacTrendPoint};
 
 #[derive(Serialize, Deserialize, Debug, Clone, SimpleObject)]
 pub struct LoginResponse {
@@ -98,6 +98,26 @@ impl PublicUserInfo {
             .await?)
     }
 
+    async fn stats(&self, context: &Context<'_>) -> FieldResult<DiveStats> {
+        let context = context.data::<SchemaContext>()?;
+
+        Ok(context
+            .web
+            .handle
+            .user_stats(context.con.user.as_ref().map(|val| val.id), self.id)
+            .await?)
+    }
+
+    async fn sac_trend(&self, context: &Context<'_>) -> FieldResult<Vec<SacTrendPoint>> {
+        let context = context.data::<SchemaContext>()?;
+
+        Ok(context
+            .web
+            .handle
+            .user_sac_trend(context.con.user.as_ref().map(|val| val.id), self.id)
+            .await?)
+    }
+
     async fn latest_dives(&self, context: &Context<'_>) -> FieldResult<Vec<Dive>> {
         let query = DiveQuery {
             user_id: Some(self.id),
@@ -0,0 +1,431 @@
+use std::io::Cursor;
+
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    web, Error as ActixError, HttpResponse,
+};
+use anyhow::Result;
+use askama::Template;
+use image::ImageOutputFormat;
+use resvg::tiny_skia::Color;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    chart::rasterize,
+    graphql::WebContext,
+    log_error,
+    plan::plan_dive,
+    schema::{Dive, DiveMetric, DivePlanInput, DiveSchedule, GasOutput},
+    token::Token,
+};
+
+/// The contingency columns plan for going this many meters deeper
+const DEEPER_CONTINGENCY: f32 = 3.;
+
+/// The contingency columns plan for staying this many minutes longer
+const LONGER_CONTINGENCY: i32 = 3;
+
+const WIDTH: f64 = 460.;
+
+/// Where the first row of the table sits
+const TABLE_START: f64 = 96.;
+
+const ROW_HEIGHT: f64 = 20.;
+
+const LINE_HEIGHT: f64 = 18.;
+
+/// PNG slates are rendered at twice the size, so they stay sharp when printed
+const PNG_SCALE: f32 = 2.;
+
+#[derive(Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SlateFormat {
+    Svg,
+    Png,
+}
+
+/// Renders a saved plan as a slate.  Like the plan itself, anyone with the id can view it
+pub async fn saved_plan_slate(
+    context: web::Data<WebContext>,
+    path: web::Path<(Uuid, SlateFormat)>,
+    token: Token,
+) -> Result<HttpResponse, ActixError> {
+    let (plan_id, format) = path.into_inner();
+
+    let saved = context
+        .handle
+        .dive_plan(plan_id)
+        .await
+        .map_err(log_error)?
+        .ok_or_else(|| ErrorNotFound("No dive plan found"))?;
+
+    let previous_dives = context
+        .handle
+        .dive_plan_previous_dives(&saved, token.user_id)
+        .await
+        .map_err(log_error)?;
+
+    let svg = render_slate(&saved.name, &saved.plan.0, &previous_dives).map_err(ErrorBadRequest)?;
+
+    slate_response(svg, format)
+}
+
+/// Renders a slate for a plan posted as JSON, without having to save it first
+pub async fn plan_slate(
+    context: web::Data<WebContext>,
+    format: web::Path<SlateFormat>,
+    token: Token,
+    plan: web::Json<DivePlanInput>,
+) -> Result<HttpResponse, ActixError> {
+    let plan = plan.into_inner();
+
+    let previous_dives = match (plan.surface_interval, token.user_id) {
+        (None, _) => Vec::new(),
+        (Some(_), Some(user_id)) => context
+            .handle
+            .latest_day_dive_profiles(user_id)
+            .await
+            .map_err(log_error)?,
+        (Some(_), None) => return Ok(HttpResponse::Unauthorized().body("Unauthorized")),
+    };
+
+    let svg = render_slate("Dive Plan", &plan, &previous_dives).map_err(ErrorBadRequest)?;
+
+    slate_response(svg, format.into_inner())
+}
+
+fn slate_response(svg: String, format: SlateFormat) -> Result<HttpResponse, ActixError> {
+    match format {
+        SlateFormat::Svg => Ok(HttpResponse::Ok().content_type("image/svg+xml").body(svg)),
+        SlateFormat::Png => {
+            let img = rasterize(&svg, PNG_SCALE, Color::WHITE)?;
+
+            let mut output_body = Cursor::new(Vec::<u8>::new());
+
+            img.write_to(&mut output_body, ImageOutputFormat::Png)
+                .map_err(ErrorInternalServerError)?;
+
+            Ok(HttpResponse::Ok()
+                .content_type("image/png")
+                .body(output_body.into_inner()))
+        }
+    }
+}
+
+/// A stop on the final ascent, with times in seconds
+#[derive(Debug, Clone, PartialEq)]
+struct SlateStop {
+    depth: f32,
+    time: i32,
+    /// The runtime when leaving the stop
+    runtime: i32,
+    gas: String,
+}
+
+/// Renders the runtime table for a plan, alongside the same plan gone deeper & longer
+pub fn render_slate(
+    title: &str,
+    plan: &DivePlanInput,
+    previous_dives: &[(Dive, Vec<DiveMetric>)],
+) -> Result<String> {
+    let schedule = plan_dive(plan.clone(), previous_dives)?;
+
+    // A contingency that can't be planned, such as going past a gas's MOD, is left blank
+    let deeper = plan_dive(contingency(plan, DEEPER_CONTINGENCY, 0), previous_dives).ok();
+    let longer = plan_dive(contingency(plan, 0., LONGER_CONTINGENCY), previous_dives).ok();
+
+    let stops = slate_stops(&schedule);
+    let deeper_stops = deeper.as_ref().map(slate_stops).unwrap_or_default();
+    let longer_stops = longer.as_ref().map(slate_stops).unwrap_or_default();
+
+    let mut depths = stops
+        .iter()
+        .chain(&deeper_stops)
+        .chain(&longer_stops)
+        .map(|stop| stop.depth)
+        .collect::<Vec<_>>();
+
+    depths.sort_by(|a, b| b.total_cmp(a));
+    depths.dedup_by(|a, b| (*a - *b).abs() < 0.01);
+
+    let find = |stops: &[SlateStop], depth: f32| {
+        stops
+            .iter()
+            .find(|stop| (stop.depth - depth).abs() < 0.01)
+            .cloned()
+    };
+
+    let contingency_cell = |stop: Option<SlateStop>| {
+        stop.map(|stop| format!("{} / {}", minutes(stop.time), minutes(stop.runtime)))
+            .unwrap_or_else(|| "-".into())
+    };
+
+    let mut rows = Vec::new();
+
+    for depth in depths {
+        let stop = find(&stops, depth);
+
+        rows.push(SlateRow {
+            y: TABLE_START + rows.len() as f64 * ROW_HEIGHT,
+            depth: format!("{depth:.0}m"),
+            stop: stop
+                .as_ref()
+                .map(|stop| minutes(stop.time).to_string())
+                .unwrap_or_default(),
+            runtime: stop
+                .as_ref()
+                .map(|stop| minutes(stop.runtime).to_string())
+                .unwrap_or_default(),
+            gas: stop.map(|stop| stop.gas).unwrap_or_default(),
+            deeper: contingency_cell(find(&deeper_stops, depth)),
+            longer: contingency_cell(find(&longer_stops, depth)),
+        });
+    }
+
+    let surface_cell = |schedule: Option<&DiveSchedule>| {
+        schedule
+            .map(|val| format!("- / {}", surface_time(val)))
+            .unwrap_or_else(|| "-".into())
+    };
+
+    rows.push(SlateRow {
+        y: TABLE_START + rows.len() as f64 * ROW_HEIGHT,
+        depth: "0m".into(),
+        stop: String::new(),
+        runtime: surface_time(&schedule).to_string(),
+        gas: String::new(),
+        deeper: surface_cell(deeper.as_ref()),
+        longer: surface_cell(longer.as_ref()),
+    });
+
+    let table_end = TABLE_START + (rows.len() as f64 - 0.5) * ROW_HEIGHT;
+
+    let mut summary = vec![format!(
+        "Runtime {} min · TTS {} min · CNS {:.0}% · OTU {:.0}",
+        surface_time(&schedule),
+        schedule.tts,
+        schedule.cns,
+        schedule.otu
+    )];
+
+    if let Some(ref bailout) = schedule.bailout {
+        summary.push(format!(
+            "Bailout on {} · TTS {} min · Runtime {} min",
+            GasOutput::from(dive_deco::Gas::from(plan.back_gas)),
+            bailout.tts,
+            bailout.runtime
+        ));
+    }
+
+    let mut lines = Vec::new();
+
+    for text in summary.into_iter().chain(schedule.warnings.iter().cloned()) {
+        lines.push(SlateLine {
+            y: table_end + 22. + lines.len() as f64 * LINE_HEIGHT,
+            text,
+        });
+    }
+
+    let height = lines.last().map(|val| val.y).unwrap_or(table_end) + 14.;
+
+    let warnings = lines.split_off(lines.len() - schedule.warnings.len());
+
+    let max_depth = schedule
+        .metrics
+        .iter()
+        .map(|val| val.depth)
+        .fold(0., f32::max);
+
+    let breathing = match plan.closed_circuit {
+        Some(closed_circuit) => format!(
+            "CCR at {:.1} bar on {}",
+            closed_circuit.setpoint,
+            GasOutput::from(dive_deco::Gas::from(closed_circuit.diluent))
+        ),
+        None => GasOutput::from(dive_deco::Gas::from(plan.back_gas)).to_string(),
+    };
+
+    let slate = SlateTemplate {
+        width: WIDTH,
+        height,
+        title: title.into(),
+        subtitle: format!(
+            "{max_depth:.0}m for {} min on {breathing} · GF {}/{}",
+            schedule.bottom_time, plan.gf_low, plan.gf_high
+        ),
+        deeper_label: format!("+{DEEPER_CONTINGENCY}m"),
+        longer_label: format!("+{LONGER_CONTINGENCY} min"),
+        rows,
+        table_end,
+        summary: lines,
+        warnings,
+    };
+
+    Ok(slate.render()?)
+}
+
+/// The plan with every level `depth` meters deeper & the last level `time` minutes longer
+fn contingency(plan: &DivePlanInput, depth: f32, time: i32) -> DivePlanInput {
+    let mut plan = plan.clone();
+
+    plan.depth += depth;
+    plan.time += time;
+
+    if let Some(ref mut segments) = plan.segments {
+        for segment in segments.iter_mut() {
+            segment.depth += depth;
+        }
+
+        if let Some(last) = segments.last_mut() {
+            last.time += time;
+        }
+    }
+
+    plan
+}
+
+/// The stops after the bottom phase, with the gas or setpoint breathed at each
+fn slate_stops(schedule: &DiveSchedule) -> Vec<SlateStop> {
+    let bottom_end = schedule.bottom_time * 60;
+
+    let mut stops: Vec<SlateStop> = Vec::new();
+
+    let mut gas = String::from("Air");
+
+    for pair in schedule.metrics.windows(2) {
+        let (start, end) = (&pair[0], &pair[1]);
+
+        if let Some(setpoint) = start.setpoint {
+            gas = format!("SP {setpoint:.1}");
+        } else if start.o2.is_some() || start.he.is_some() {
+            gas = GasOutput {
+                o2: start.o2.unwrap_or(21.),
+                he: start.he.unwrap_or_default(),
+            }
+            .to_string();
+        }
+
+        #[allow(clippy::float_cmp)]
+        if start.time < bottom_end || start.depth != end.depth || start.depth <= 0. {
+            continue;
+        }
+
+        // Gas switches & stops at the same depth are one stop, on the gas switched to
+        match stops.last_mut() {
+            #[allow(clippy::float_cmp)]
+            Some(stop) if stop.depth == start.depth => {
+                if stop.time == 0 {
+                    stop.gas.clone_from(&gas);
+                }
+
+                stop.time += end.time - start.time;
+                stop.runtime = end.time;
+            }
+            _ => stops.push(SlateStop {
+                depth: start.depth,
+                time: end.time - start.time,
+                runtime: end.time,
+                gas: gas.clone(),
+            }),
+        }
+    }
+
+    stops.retain(|stop| stop.time > 0);
+
+    stops
+}
+
+/// The runtime in minutes when surfacing, rounded up like the stops
+fn surface_time(schedule: &DiveSchedule) -> i32 {
+    minutes(
+        schedule
+            .metrics
+            .last()
+            .map(|val| val.time)
+            .unwrap_or_default(),
+    )
+}
+
+/// Seconds as whole minutes, rounded up
+fn minutes(seconds: i32) -> i32 {
+    (seconds + 59) / 60
+}
+
+#[derive(Template)]
+#[template(path = "slate.svg", escape = "xml")]
+struct SlateTemplate {
+    width: f64,
+    height: f64,
+    title: String,
+    subtitle: String,
+    deeper_label: String,
+    longer_label: String,
+    rows: Vec<SlateRow>,
+    table_end: f64,
+    summary: Vec<SlateLine>,
+    warnings: Vec<SlateLine>,
+}
+
+struct SlateRow {
+    y: f64,
+    depth: String,
+    stop: String,
+    runtime: String,
+    gas: String,
+    deeper: String,
+    longer: String,
+}
+
+struct SlateLine {
+    y: f64,
+    text: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::GasInput;
+
+    #[test]
+    fn test_slate() {
+        let plan = DivePlanInput {
+            time: 30,
+            depth: 40.,
+            gf_low: 30,
+            gf_high: 70,
+            back_gas: GasInput {
+                o2: 21.,
+                ..Default::default()
+            },
+            deco_gasses: vec![GasInput {
+                o2: 50.,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let schedule = plan_dive(plan.clone(), &[]).expect("Plans dive");
+        let stops = slate_stops(&schedule);
+
+        assert!(stops.windows(2).all(|val| val[0].depth > val[1].depth));
+        assert_eq!(stops.last().map(|stop| stop.gas.as_str()), Some("EAN50"));
+
+        let deeper = plan_dive(contingency(&plan, DEEPER_CONTINGENCY, 0), &[]).expect("Plans dive");
+        let longer =
+            plan_dive(contingency(&plan, 0., LONGER_CONTINGENCY), &[]).expect("Plans dive");
+
+        assert!(deeper.tts > schedule.tts);
+        assert_eq!(
+            longer.bottom_time,
+            schedule.bottom_time + LONGER_CONTINGENCY
+        );
+
+        let svg = render_slate("Reef <wall>", &plan, &[]).expect("Renders slate");
+
+        assert!(svg.contains("Reef &lt;wall&gt;"));
+        assert!(svg.contains("+3m"));
+
+        rasterize(&svg, PNG_SCALE, Color::WHITE).expect("Renders PNG");
+    }
+}
@@ -5,23 +5,23 @@ use git2::{Cred, RemoteCallbacks};
 use std::collections::HashMap;
 use std::io::{self, BufRead};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use crate::{
     db::DbHandle,
     schema::{
-        CreateDive, CreateDiveSite, Difficulty, Dive, DiveMetric, DiveQuery, DiveSite,
-        DiveSiteQuery,
+        CreateDive, CreateDiveSite, Difficulty, Dive, DiveComputer, DiveCylinder, DiveMetric,
+        DiveProfile, DiveQuery, DiveSite, DiveSiteQuery, DiveWeight, ImportSummary, SensorPressure,
+        SubsurfaceAuth, SubsurfaceRemote, SubsurfaceSync,
     },
 };
 use std::{
-    fs::{read_dir, File},
+    fs::{create_dir_all, read_dir, remove_dir_all, File},
     mem,
 };
 use tracing::*;
 use uuid::Uuid;
 
-use tempdir::TempDir;
-
 use chrono::prelude::*;
 
 pub struct SubsurfaceRepo {
@@ -29,37 +29,142 @@ pub struct SubsurfaceRepo {
 }
 
 //Randomly generated Uuid for a unique name space to ensure sites are consistent
-const SITE_NAMESPACE: Uuid = Uuid::from_u128(68368825254275185855298742576399799974);
+pub(crate) const SITE_NAMESPACE: Uuid = Uuid::from_u128(68368825254275185855298742576399799974);
 
 //Same for dives
-const DIVE_NAMESPACE: Uuid = Uuid::from_u128(6214112353904840326363746027736122008);
+pub(crate) const DIVE_NAMESPACE: Uuid = Uuid::from_u128(6214112353904840326363746027736122008);
+
+const SUBSURFACE_CLOUD_URL: &str = "https://ssrf-cloud-eu.subsurface-divelog.org/git";
 
+#[derive(Default)]
 pub struct Repository {
-    sites: Vec<DiveSite>,
-    dives: Vec<Dive>,
-    dive_metrics: HashMap<Uuid, Vec<DiveMetric>>,
+    pub sites: Vec<DiveSite>,
+    pub dives: Vec<Dive>,
+    pub dive_metrics: HashMap<Uuid, Vec<DiveMetric>>,
+    pub dive_cylinders: HashMap<Uuid, Vec<DiveCylinder>>,
+    pub dive_weights: HashMap<Uuid, Vec<DiveWeight>>,
+    pub dive_computers: HashMap<Uuid, Vec<DiveComputer>>,
+    pub dive_pressures: HashMap<Uuid, Vec<SensorPressure>>,
+    /// The commit this was parsed from, if it came from git
+    pub head: Option<SubsurfaceSync>,
+    /// Whether the source records buddies, tags, visibility & ratings.  When it doesn't, the ones already set are kept
+    pub details: bool,
 }
 
-pub fn sync_repository(user_id: Uuid, email: &str, password: &str) -> Result<Repository, Error> {
-    let site_ns = Uuid::new_v5(&SITE_NAMESPACE, email.as_bytes());
-    let dive_ns = Uuid::new_v5(&DIVE_NAMESPACE, email.as_bytes());
+/// Checks that a remote is something we are happy to clone from.
+///
+/// Local `file://` remotes can read anything on the server, so are only allowed when explicitly enabled
+pub fn validate_remote(remote: &SubsurfaceRemote, allow_local: bool) -> Result<(), Error> {
+    let Some(ref url) = remote.url else {
+        return Ok(());
+    };
 
-    let (_, d2, d3, d4) = site_ns.as_fields();
+    if url.starts_with("https://") || url.starts_with("ssh://") {
+        return Ok(());
+    }
+
+    // scp style ssh urls, i.e, `git@example.com:divelog.git`.  Git treats anything with a `/` before the colon as a local path
+    if let Some((login, path)) = url.split_once(':') {
+        if let Some((user, host)) = login.split_once('@') {
+            if !user.is_empty()
+                && !host.is_empty()
+                && !login.contains('/')
+                && !login.starts_with('.')
+                && !path.is_empty()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    if allow_local && url.starts_with("file://") {
+        return Ok(());
+    }
+
+    Err(anyhow!("Unsupported remote url: {url}"))
+}
 
-    let temp_dir = TempDir::new("subsurface")?;
+pub fn sync_repository(
+    user_id: Uuid,
+    email: &str,
+    password: &str,
+    remote: &SubsurfaceRemote,
+    cache_dir: &Path,
+    previous: Option<&SubsurfaceSync>,
+) -> Result<Repository, Error> {
+    let checkout = fetch_repository(user_id, email, password, remote, cache_dir)?;
+
+    parse_repository(user_id, email, &checkout, previous)
+}
+
+/// A user's cached clone, checked out at the latest commit of the synced branch
+pub struct Checkout {
+    pub dir: PathBuf,
+    pub url: String,
+    pub branch: String,
+}
+
+pub fn fetch_repository(
+    user_id: Uuid,
+    email: &str,
+    password: &str,
+    remote: &SubsurfaceRemote,
+    cache_dir: &Path,
+) -> Result<Checkout, Error> {
+    let url = remote
+        .url
+        .clone()
+        .unwrap_or_else(|| format!("{SUBSURFACE_CLOUD_URL}/{email}"));
+
+    // The cloud uses the email address as the branch name
+    let branch = remote.branch.clone().unwrap_or_else(|| email.to_string());
+
+    let dir = cache_dir.join(user_id.to_string());
+
+    if let Err(err) = update_git(&dir, &url, &branch, email, password, remote) {
+        let err = err.downcast::<git2::Error>()?;
 
-    if let Err(err) = clone_git(temp_dir.path(), email, password) {
-        if err.class() == git2::ErrorClass::Http {
+        if err.class() == git2::ErrorClass::Http && remote.url.is_none() {
             return Err(anyhow!("Failed to login to Subsurface cloud with the provided credentials.  Please check your email and password."));
         }
 
+        if err.class() == git2::ErrorClass::Http || err.class() == git2::ErrorClass::Ssh {
+            return Err(anyhow!(
+                "Failed to authenticate with the Subsurface remote: {}",
+                err.message()
+            ));
+        }
+
         return Err(err.into());
     }
 
-    let dir = temp_dir.path().to_path_buf();
+    Ok(Checkout { dir, url, branch })
+}
+
+pub fn parse_repository(
+    user_id: Uuid,
+    email: &str,
+    checkout: &Checkout,
+    previous: Option<&SubsurfaceSync>,
+) -> Result<Repository, Error> {
+    let site_ns = Uuid::new_v5(&SITE_NAMESPACE, email.as_bytes());
+    let dive_ns = Uuid::new_v5(&DIVE_NAMESPACE, email.as_bytes());
 
-    let mut dives_sites = dir.clone();
-    dives_sites.push("01-Divesites");
+    let (_, d2, d3, d4) = site_ns.as_fields();
+
+    let Checkout { dir, url, branch } = checkout;
+
+    let git_repo = git2::Repository::open(dir)?;
+
+    let head = git_repo.head()?.peel_to_commit()?;
+
+    // Only look at what has changed if we're syncing from the same place as last time
+    let changed = previous
+        .filter(|previous| &previous.remote_url == url && &previous.branch == branch)
+        .and_then(|previous| changed_paths(&git_repo, &previous.commit_id, &head).transpose())
+        .transpose()?;
+
+    let dives_sites = dir.join("01-Divesites");
 
     let mut sites = HashMap::new();
 
@@ -131,11 +236,90 @@ pub fn sync_repository(user_id: Uuid, email: &str, password: &str) -> Result<Rep
         }
     }
 
+    let folders = match changed {
+        Some(ref changed) => changed_dive_folders(dir, changed),
+        None => dive_folders(dir)?,
+    };
+
+    debug!("Parsing {} dive folders", folders.len());
+
     let mut dives = Vec::new();
     let mut dive_metrics = HashMap::new();
+    let mut dive_cylinders = HashMap::new();
+    let mut dive_weights = HashMap::new();
+    let mut dive_computers = HashMap::new();
+    let mut dive_pressures = HashMap::new();
 
     let (_, d2, d3, d4) = dive_ns.as_fields();
 
+    for (year, month, folder) in folders {
+        let mut subsurface_dive = parse_dive(year, month, folder)?;
+
+        let dive_id = Uuid::from_fields(subsurface_dive.dive_number, d2, d3, d4);
+
+        let dive = Dive {
+            user_id,
+            date: subsurface_dive.date,
+            depth: subsurface_dive.depth,
+            duration: subsurface_dive.duration,
+            description: subsurface_dive.description,
+            dive_number: 0,
+            published: false,
+            dive_site_id: subsurface_dive
+                .dive_site_id
+                .and_then(|id| sites.get(&id))
+                .map(|site| site.id),
+            id: dive_id,
+            deco_model: subsurface_dive.extra_data.remove("Deco model"),
+            buddy: subsurface_dive.buddy,
+            divemaster: subsurface_dive.divemaster,
+            tags: subsurface_dive.tags,
+            visibility: subsurface_dive.visibility,
+            rating: subsurface_dive.rating,
+        };
+
+        dive_metrics.insert(dive_id, subsurface_dive.metrics);
+        dive_cylinders.insert(dive_id, subsurface_dive.cylinders);
+        dive_weights.insert(dive_id, subsurface_dive.weights);
+        dive_computers.insert(dive_id, subsurface_dive.computers);
+        dive_pressures.insert(dive_id, subsurface_dive.pressures);
+
+        dives.push(dive);
+    }
+
+    let mut sites = sites.into_values().collect::<Vec<_>>();
+
+    // On an incremental sync we only need the sites that changed, or that changed dives point to
+    if let Some(ref changed) = changed {
+        sites.retain(|site| {
+            changed.iter().any(|path| path.starts_with("01-Divesites"))
+                || dives.iter().any(|dive| dive.dive_site_id == Some(site.id))
+        });
+    }
+
+    Ok(Repository {
+        sites,
+        dives,
+        dive_metrics,
+        dive_cylinders,
+        dive_weights,
+        dive_computers,
+        dive_pressures,
+        head: Some(SubsurfaceSync {
+            user_id,
+            remote_url: url.clone(),
+            branch: branch.clone(),
+            commit_id: head.id().to_string(),
+            date: Local::now(),
+        }),
+        details: true,
+    })
+}
+
+// Walks the `YYYY/MM/DD-Day-HH=MM=SS` folders of the repository
+fn dive_folders(dir: &Path) -> Result<Vec<(i32, u32, PathBuf)>, Error> {
+    let mut folders = Vec::new();
+
     for entry in read_dir(dir)? {
         let dir_entry = entry?;
 
@@ -168,38 +352,7 @@ pub fn sync_repository(user_id: Uuid, email: &str, password: &str) -> Result<Rep
                                     let dir_entry = entry?;
 
                                     if dir_entry.file_type()?.is_dir() {
-                                        let folder = dir_entry.path();
-
-                                        let mut subsurface_dive = parse_dive(year, month, folder)?;
-
-                                        let dive_id = Uuid::from_fields(
-                                            subsurface_dive.dive_number,
-                                            d2,
-                                            d3,
-                                            d4,
-                                        );
-
-                                        let dive = Dive {
-                                            user_id,
-                                            date: subsurface_dive.date,
-                                            depth: subsurface_dive.depth,
-                                            duration: subsurface_dive.duration,
-                                            description: subsurface_dive.description,
-                                            dive_number: 0,
-                                            published: false,
-                                            dive_site_id: subsurface_dive
-                                                .dive_site_id
-                                                .and_then(|id| sites.get(&id))
-                                                .map(|site| site.id),
-                                            id: dive_id,
-                                            deco_model: subsurface_dive
-                                                .extra_data
-                                                .remove("Deco model"),
-                                        };
-
-                                        dive_metrics.insert(dive_id, subsurface_dive.metrics);
-
-                                        dives.push(dive);
+                                        folders.push((year, month, dir_entry.path()));
                                     }
                                 }
                             }
@@ -210,11 +363,65 @@ pub fn sync_repository(user_id: Uuid, email: &str, password: &str) -> Result<Rep
         }
     }
 
-    Ok(Repository {
-        sites: sites.into_values().collect(),
-        dives,
-        dive_metrics,
-    })
+    Ok(folders)
+}
+
+// Maps changed file paths back to the dive folders that still exist in the checkout
+fn changed_dive_folders(dir: &Path, changed: &[PathBuf]) -> Vec<(i32, u32, PathBuf)> {
+    let mut folders = Vec::new();
+
+    for path in changed {
+        let mut components = path.components();
+
+        let (Some(year), Some(month), Some(day)) =
+            (components.next(), components.next(), components.next())
+        else {
+            continue;
+        };
+
+        let (Ok(year_num), Ok(month_num)) = (
+            year.as_os_str().to_string_lossy().parse::<i32>(),
+            month.as_os_str().to_string_lossy().parse::<u32>(),
+        ) else {
+            continue;
+        };
+
+        let folder = dir.join(year).join(month).join(day);
+
+        // Deleted dives won't have a folder anymore
+        if folder.is_dir() && !folders.iter().any(|(_, _, existing)| existing == &folder) {
+            folders.push((year_num, month_num, folder));
+        }
+    }
+
+    folders
+}
+
+/// Returns the paths that changed between the previously synced commit and `head`,
+/// or `None` if the previous commit is no longer in the history
+fn changed_paths(
+    repo: &git2::Repository,
+    previous: &str,
+    head: &git2::Commit,
+) -> Result<Option<Vec<PathBuf>>, Error> {
+    let Some(previous) = git2::Oid::from_str(previous)
+        .ok()
+        .and_then(|oid| repo.find_commit(oid).ok())
+    else {
+        info!("Previous commit {previous} not found, syncing everything");
+        return Ok(None);
+    };
+
+    let diff = repo.diff_tree_to_tree(Some(&previous.tree()?), Some(&head.tree()?), None)?;
+
+    let paths = diff
+        .deltas()
+        .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+        .flatten()
+        .map(Path::to_path_buf)
+        .collect();
+
+    Ok(Some(paths))
 }
 
 fn dive_id_from_hex(input: &str) -> Result<u32, Error> {
@@ -242,6 +449,15 @@ pub struct SubsurfaceDive {
     pub depth: f32,
     pub metrics: Vec<DiveMetric>,
     pub extra_data: HashMap<String, String>,
+    pub buddy: String,
+    pub divemaster: String,
+    pub tags: Vec<String>,
+    pub visibility: i32,
+    pub rating: i32,
+    pub cylinders: Vec<DiveCylinder>,
+    pub weights: Vec<DiveWeight>,
+    pub computers: Vec<DiveComputer>,
+    pub pressures: Vec<SensorPressure>,
 }
 
 pub fn parse_dive(year: i32, month: u32, folder: PathBuf) -> Result<SubsurfaceDive, Error> {
@@ -266,13 +482,16 @@ pub fn parse_dive(year: i32, month: u32, folder: PathBuf) -> Result<SubsurfaceDi
 
     let mut dive_site_id = None;
 
-    let mut depth = 0.0;
-
-    let mut metrics = vec![];
     let mut dive_number = 0;
     let mut description = String::new();
-    let mut extra_data = HashMap::new();
-    let mut gas_changes = HashMap::new();
+    let mut buddy = String::new();
+    let mut divemaster = String::new();
+    let mut tags = Vec::new();
+    let mut visibility = 0;
+    let mut rating = 0;
+    let mut cylinders = Vec::new();
+    let mut weights = Vec::new();
+    let mut computers = Vec::new();
 
     for entry in read_dir(folder)? {
         let file = entry?.path();
@@ -295,6 +514,31 @@ pub fn parse_dive(year: i32, month: u32, folder: PathBuf) -> Result<SubsurfaceDi
                 if line.starts_with("divesiteid") {
                     dive_site_id = Some(dive_id_from_hex(&line[("divesiteid".len() + 1)..])?);
                 }
+                if !in_notes {
+                    if let Some(val) = line.strip_prefix("buddy ") {
+                        buddy = parse_quoted(val).join(", ");
+                    }
+                    if let Some(val) = line.strip_prefix("divemaster ") {
+                        divemaster = parse_quoted(val).join(", ");
+                    }
+                    // Tags lines look like:
+                    // tags "boat", "night"
+                    if let Some(val) = line.strip_prefix("tags ") {
+                        tags = parse_quoted(val);
+                    }
+                    if let Some(val) = line.strip_prefix("visibility ") {
+                        visibility = val.trim().parse()?;
+                    }
+                    if let Some(val) = line.strip_prefix("rating ") {
+                        rating = val.trim().parse()?;
+                    }
+                    if let Some(val) = line.strip_prefix("cylinder ") {
+                        cylinders.push(parse_cylinder(val));
+                    }
+                    if let Some(val) = line.strip_prefix("weightsystem ") {
+                        weights.push(parse_weight(val));
+                    }
+                }
                 if in_notes {
                     in_notes = !parse_notes(line.trim_start_matches('\t'), &mut description);
                     if in_notes {
@@ -308,72 +552,239 @@ pub fn parse_dive(year: i32, month: u32, folder: PathBuf) -> Result<SubsurfaceDi
                     }
                 }
             }
-        } else if file_name == "Divecomputer" {
-            let mut lines = read_lines(&file)?;
+        } else if let Some(computer) = divecomputer_index(&file_name) {
+            computers.push(parse_divecomputer(&file, computer)?);
+        }
+    }
 
-            let mut first_metric_line = None;
+    computers.sort_by_key(|parsed| parsed.computer.computer);
 
-            //We skip ahead to the first time entry in the file
-            while let Some(Ok(line)) = lines.next() {
-                if line.starts_with("keyvalue") {
-                    if let Some((key, value)) = parse_keyvalue_simple(&line) {
-                        extra_data.insert(key.to_string(), value.to_string());
-                    }
-                }
+    let mut metrics = vec![];
+    let mut dive_computers = vec![];
+    let mut pressures = vec![];
+    let mut extra_data = HashMap::new();
+    let mut depth = 0.0;
+    let mut duration = 0;
+
+    for (idx, parsed) in computers.into_iter().enumerate() {
+        // The first computer is the primary one, and is what the dive summary comes from
+        if idx == 0 {
+            depth = parsed
+                .metrics
+                .iter()
+                .map(|val| val.depth)
+                .fold(0.0, f32::max);
+            duration = parsed
+                .metrics
+                .last()
+                .map(|val| val.time)
+                .unwrap_or_default();
+            extra_data = parsed.extra_data;
+        }
 
-                if line.starts_with("event") {
-                    if let Some((time_seconds, o2, he)) = parse_gas_change(&line) {
-                        gas_changes.insert(time_seconds, (o2, he));
-                    }
-                }
+        metrics.extend(parsed.metrics);
+        pressures.extend(parsed.pressures);
+        dive_computers.push(parsed.computer);
+    }
 
-                if line.starts_with("  ") {
-                    first_metric_line = Some(line);
-                    break;
-                }
-            }
+    let subsurface_dive = SubsurfaceDive {
+        dive_site_id,
+        dive_number,
+        date,
+        duration,
+        description,
+        depth,
+        metrics,
+        extra_data,
+        buddy,
+        divemaster,
+        tags,
+        visibility,
+        rating,
+        cylinders,
+        weights,
+        computers: dive_computers,
+        pressures,
+    };
 
-            if let Some(line) = first_metric_line {
-                let first_metric = parse_metric(line, &mut gas_changes)?;
-                depth = first_metric.depth;
+    Ok(subsurface_dive)
+}
 
-                metrics.push(first_metric);
+// The primary computer is `Divecomputer`, with any others being `Divecomputer-001` etc..
+fn divecomputer_index(file_name: &str) -> Option<i32> {
+    match file_name.strip_prefix("Divecomputer")? {
+        "" => Some(0),
+        suffix => suffix.strip_prefix('-')?.parse().ok(),
+    }
+}
 
-                while let Some(Ok(line)) = lines.next() {
-                    let metric = parse_metric(line, &mut gas_changes)?;
+struct ParsedComputer {
+    computer: DiveComputer,
+    metrics: Vec<DiveMetric>,
+    pressures: Vec<SensorPressure>,
+    extra_data: HashMap<String, String>,
+}
 
-                    if metric.depth > depth {
-                        depth = metric.depth;
-                    }
+fn parse_divecomputer(file: &Path, computer: i32) -> Result<ParsedComputer, Error> {
+    let mut lines = read_lines(file)?;
 
-                    metrics.push(metric);
-                }
+    let mut model = None;
+    let mut extra_data = HashMap::new();
+    let mut gas_changes = HashMap::new();
+    let mut cells = HashMap::new();
+    let mut metrics = vec![];
+    let mut pressures = vec![];
+
+    let mut first_metric_line = None;
+
+    //We skip ahead to the first time entry in the file
+    while let Some(Ok(line)) = lines.next() {
+        if let Some(val) = line.strip_prefix("model ") {
+            model = parse_quoted(val).pop();
+        }
+
+        if line.starts_with("keyvalue") {
+            if let Some((key, value)) = parse_keyvalue_simple(&line) {
+                extra_data.insert(key.to_string(), value.to_string());
+            }
+        }
+
+        if line.starts_with("event") {
+            if let Some((time_seconds, o2, he)) = parse_gas_change(&line) {
+                gas_changes.insert(time_seconds, (o2, he));
             }
         }
+
+        if line.starts_with("  ") {
+            first_metric_line = Some(line);
+            break;
+        }
     }
 
-    let duration = if !metrics.is_empty() {
-        metrics[metrics.len() - 1].time
-    } else {
-        0
-    };
+    for line in first_metric_line
+        .into_iter()
+        .chain(lines.map_while(Result::ok))
+    {
+        let (metric, sensors) =
+            parse_metric(line, computer, metrics.last(), &mut cells, &mut gas_changes)?;
+
+        metrics.push(metric);
+        pressures.extend(sensors);
+    }
 
     if !gas_changes.is_empty() {
         warn!("Missing metrics to associate to gas changes: {gas_changes:?}");
     }
 
-    let subsurface_dive = SubsurfaceDive {
-        dive_site_id,
-        dive_number,
-        date,
-        duration,
-        description,
-        depth,
+    // Subsurface's `deviceid` is a hash it derives rather than a serial, so only the reported one is kept
+    let serial = extra_data.get("Serial").cloned();
+
+    Ok(ParsedComputer {
+        computer: DiveComputer {
+            computer,
+            model,
+            serial,
+        },
         metrics,
+        pressures,
         extra_data,
-    };
+    })
+}
 
-    Ok(subsurface_dive)
+/// Returns all the quoted strings on a line, i.e, `"boat", "night"`
+fn parse_quoted(line: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut current: Option<String> = None;
+    let mut escaped = false;
+
+    for ch in line.chars() {
+        match current {
+            Some(ref mut value) if escaped => {
+                value.push(ch);
+                escaped = false;
+            }
+            Some(_) if ch == '\\' => escaped = true,
+            Some(_) if ch == '"' => values.extend(current.take()),
+            Some(ref mut value) => value.push(ch),
+            None if ch == '"' => current = Some(String::new()),
+            None => (),
+        }
+    }
+
+    values
+}
+
+/// Parses `key=value` pairs, where the value may be quoted, i.e, `weight=6.0kg description="belt"`
+fn parse_attributes(line: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = line.trim_start();
+
+    while let Some(equals) = rest.find('=') {
+        let key = rest[..equals].trim().to_string();
+        let value = &rest[equals + 1..];
+
+        if value.starts_with('"') {
+            let mut end = value.len();
+            let mut escaped = false;
+
+            for (idx, ch) in value.char_indices().skip(1) {
+                if escaped {
+                    escaped = false;
+                } else if ch == '\\' {
+                    escaped = true;
+                } else if ch == '"' {
+                    end = idx + 1;
+                    break;
+                }
+            }
+
+            attributes.insert(key, parse_quoted(&value[..end]).pop().unwrap_or_default());
+            rest = value[end..].trim_start();
+        } else {
+            let end = value.find(' ').unwrap_or(value.len());
+
+            attributes.insert(key, value[..end].to_string());
+            rest = value[end..].trim_start();
+        }
+    }
+
+    attributes
+}
+
+// Strips the unit from a value like `207.0bar`
+fn parse_unit(value: Option<&String>, unit: &str) -> Option<f32> {
+    parse_value(value?, unit)
+}
+
+fn parse_value(value: &str, unit: &str) -> Option<f32> {
+    value.strip_suffix(unit)?.parse().ok()
+}
+
+// Cylinder lines look like:
+// cylinder vol=11.1l workpressure=207.0bar description="AL80" o2=32.0% start=200.0bar end=50.0bar
+fn parse_cylinder(line: &str) -> DiveCylinder {
+    let attributes = parse_attributes(line);
+
+    DiveCylinder {
+        description: attributes.get("description").cloned(),
+        size: parse_unit(attributes.get("vol"), "l"),
+        working_pressure: parse_unit(attributes.get("workpressure"), "bar"),
+        start_pressure: parse_unit(attributes.get("start"), "bar"),
+        end_pressure: parse_unit(attributes.get("end"), "bar"),
+        o2: parse_unit(attributes.get("o2"), "%"),
+        he: parse_unit(attributes.get("he"), "%"),
+    }
+}
+
+// Weight lines look like:
+// weightsystem weight=6.0kg description="belt"
+fn parse_weight(line: &str) -> DiveWeight {
+    let attributes = parse_attributes(line);
+
+    DiveWeight {
+        description: attributes.get("description").cloned(),
+        weight: parse_unit(attributes.get("weight"), "kg").unwrap_or_default(),
+    }
 }
 
 fn parse_keyvalue_simple(line: &str) -> Option<(&str, &str)> {
@@ -446,9 +857,12 @@ fn parse_notes(line: &str, result: &mut String) -> bool {
 
 fn parse_metric(
     line: String,
+    computer: i32,
+    previous: Option<&DiveMetric>,
+    cells: &mut HashMap<String, f32>,
     gas_changes: &mut HashMap<i32, (Option<f32>, Option<f32>)>,
-) -> Result<DiveMetric, Error> {
-    //Parses a metric string like ` 2:20 2.48m 30.2°C 199.75bar tts=0:16`
+) -> Result<(DiveMetric, Vec<SensorPressure>), Error> {
+    //Parses a metric string like ` 2:20 2.48m 30.2°C 199.75bar 180.5bar:1 tts=0:16`
     //Time & depth are always first, then there are various other metrics
 
     let mut metrics = line.split_whitespace();
@@ -462,29 +876,63 @@ fn parse_metric(
 
     let depth: f32 = depth_val[0..depth_val.len() - 1].parse()?;
 
-    let mut pressure = None;
-    let mut temperature = None;
+    // Subsurface only writes the named values when they change, so they carry over from the previous sample.
+    // The oxygen cells are tracked separately, as only the cells that changed are written
+    let mut metric = DiveMetric {
+        time,
+        depth,
+        computer,
+        ..Default::default()
+    };
+
+    if let Some(previous) = previous {
+        metric.setpoint = previous.setpoint;
+        metric.cns = previous.cns;
+        metric.ndl = previous.ndl;
+        metric.tts = previous.tts;
+        metric.heart_rate = previous.heart_rate;
+    }
+
+    let mut sensors = Vec::new();
 
     for val in metrics {
-        if val.ends_with("bar") {
-            pressure = Some(val[0..val.len() - 3].parse()?);
-        } else if val.ends_with("°C") {
-            temperature = Some(val[0..val.len() - 3].parse()?);
+        if let Some((key, value)) = val.split_once('=') {
+            match key {
+                "po2" => metric.setpoint = parse_value(value, "bar"),
+                "cns" => metric.cns = parse_value(value, "%"),
+                "heartbeat" => metric.heart_rate = value.parse().ok(),
+                "ndl" => metric.ndl = parse_time(value),
+                "tts" => metric.tts = parse_time(value),
+                // The oxygen cells are `sensor1`, `sensor2` etc..
+                key if key.starts_with("sensor") => {
+                    if let Some(ppo2) = parse_value(value, "bar") {
+                        cells.insert(key.to_string(), ppo2);
+                    }
+                }
+                _ => (),
+            }
+        } else if let Some((value, sensor)) = val.split_once("bar:") {
+            // Additional sensors are suffixed with the cylinder index
+            sensors.push(SensorPressure {
+                computer,
+                time,
+                sensor: sensor.parse()?,
+                pressure: value.parse()?,
+            });
+        } else if let Some(value) = val.strip_suffix("bar") {
+            metric.pressure = Some(value.parse()?);
+        } else if let Some(value) = val.strip_suffix("°C") {
+            metric.temperature = Some(value.parse()?);
         }
     }
 
-    let (o2, he) = gas_changes.remove(&time).unwrap_or((None, None));
+    if !cells.is_empty() {
+        metric.ppo2 = Some(cells.values().sum::<f32>() / cells.len() as f32);
+    }
 
-    let metric = DiveMetric {
-        time,
-        depth,
-        pressure,
-        temperature,
-        o2,
-        he,
-    };
+    (metric.o2, metric.he) = gas_changes.remove(&time).unwrap_or((None, None));
 
-    Ok(metric)
+    Ok((metric, sensors))
 }
 
 pub async fn resolve_existing_sites(
@@ -524,10 +972,18 @@ pub async fn resolve_existing_sites(
     Ok(())
 }
 
-pub async fn import_repository(user_id: Uuid, repo: Repository, db: DbHandle) -> Result<(), Error> {
+pub async fn import_repository(
+    user_id: Uuid,
+    repo: Repository,
+    db: DbHandle,
+) -> Result<ImportSummary, Error> {
     let dives = &repo.dives;
     let db = &db;
 
+    // The sites are imported concurrently, so the counts are shared behind a lock
+    let summary = Mutex::new(ImportSummary::default());
+    let summary = &summary;
+
     stream::iter(repo.sites)
         .map(Ok)
         .try_for_each_concurrent(0, |site| async move {
@@ -543,6 +999,7 @@ pub async fn import_repository(user_id: Uuid, repo: Repository, db: DbHandle) ->
                 .first()
             {
                 trace!("Site exists:{}, skipping", existing_site.id);
+                summary.lock().unwrap().sites_skipped += 1;
             } else {
                 //Resolve the depth & difficulty based upon site data.
                 let mut depth = 0.0;
@@ -581,6 +1038,7 @@ pub async fn import_repository(user_id: Uuid, repo: Repository, db: DbHandle) ->
                 };
 
                 db.create_dive_site(user_id, &request).await?;
+                summary.lock().unwrap().sites_created += 1;
             }
 
             Ok(()) as Result<(), Error>
@@ -588,19 +1046,33 @@ pub async fn import_repository(user_id: Uuid, repo: Repository, db: DbHandle) ->
         .await?;
 
     let mut metrics = repo.dive_metrics;
+    let mut cylinders = repo.dive_cylinders;
+    let mut weights = repo.dive_weights;
+    let mut computers = repo.dive_computers;
+    let mut pressures = repo.dive_pressures;
 
     let handle = &db;
+    let details = repo.details;
 
     stream::iter(repo.dives)
         .map(|dive| {
             let id = dive.id;
-            Ok((dive, metrics.remove(&id)))
+
+            let profile = metrics.remove(&id).map(|metrics| DiveProfile {
+                metrics,
+                computers: computers.remove(&id).unwrap_or_default(),
+                pressures: pressures.remove(&id).unwrap_or_default(),
+            });
+
+            Ok((dive, profile, cylinders.remove(&id), weights.remove(&id)))
         })
-        .try_for_each_concurrent(1, |(dive, metrics)| async move {
+        .try_for_each_concurrent(1, |(dive, profile, cylinders, weights)| async move {
             let mut should_update = true;
 
             let mut is_published = false;
 
+            let mut exists = false;
+
             if let Some(existing_dive) = handle
                 .dives(
                     Some(dive.user_id),
@@ -612,6 +1084,7 @@ pub async fn import_repository(user_id: Uuid, repo: Repository, db: DbHandle) ->
                 .await?
                 .first()
             {
+                exists = true;
                 is_published = existing_dive.published;
                 if existing_dive.date != dive.date
                     || existing_dive.duration != dive.duration
@@ -619,18 +1092,41 @@ pub async fn import_repository(user_id: Uuid, repo: Repository, db: DbHandle) ->
                     || existing_dive.dive_site_id != dive.dive_site_id
                     || existing_dive.description != dive.description
                     || existing_dive.deco_model != dive.deco_model
+                    || (details
+                        && (existing_dive.buddy != dive.buddy
+                            || existing_dive.divemaster != dive.divemaster
+                            || existing_dive.tags != dive.tags
+                            || existing_dive.visibility != dive.visibility
+                            || existing_dive.rating != dive.rating))
                 {
                     debug!("Changes to dive: before: {existing_dive:?}, after: {dive:?}");
                 } else if !handle.has_metrics(dive.id).await?
-                    && metrics.as_ref().is_some_and(|m| !m.is_empty())
+                    && profile.as_ref().is_some_and(|p| !p.metrics.is_empty())
                 {
                     debug!("Dive has no metrics: {}, updating", dive.id);
+                } else if computers_changed(handle, dive.id, profile.as_ref()).await? {
+                    debug!("Dive computers changed: {}, updating", dive.id);
+                } else if equipment_changed(
+                    handle,
+                    dive.id,
+                    cylinders.as_deref(),
+                    weights.as_deref(),
+                )
+                .await?
+                {
+                    debug!("Dive equipment changed: {}, updating", dive.id);
                 } else {
                     debug!("Dive exists:{}, skipping", existing_dive.id);
                     should_update = false;
                 }
             }
 
+            match (exists, should_update) {
+                (false, _) => summary.lock().unwrap().dives_created += 1,
+                (true, true) => summary.lock().unwrap().dives_updated += 1,
+                (true, false) => summary.lock().unwrap().dives_skipped += 1,
+            }
+
             if should_update {
                 debug!("Syncing {:?}", dive);
                 let request = CreateDive {
@@ -642,9 +1138,16 @@ pub async fn import_repository(user_id: Uuid, repo: Repository, db: DbHandle) ->
                     description: dive.description,
                     published: is_published,
                     deco_model: dive.deco_model,
+                    buddy: details.then_some(dive.buddy),
+                    divemaster: details.then_some(dive.divemaster),
+                    tags: details.then_some(dive.tags),
+                    visibility: details.then_some(dive.visibility),
+                    rating: details.then_some(dive.rating),
+                    cylinders,
+                    weights,
                 };
 
-                handle.create_dive(dive.user_id, &request, metrics).await?;
+                handle.create_dive(dive.user_id, &request, profile).await?;
             }
 
             Ok(()) as Result<(), Error>
@@ -653,9 +1156,50 @@ pub async fn import_repository(user_id: Uuid, repo: Repository, db: DbHandle) ->
 
     handle.refresh_dives(user_id).await?;
 
-    debug!("Finished subsurface sync for {user_id}");
+    // Only record the commit once everything has been imported, so a failed sync is retried in full
+    if let Some(head) = repo.head {
+        handle.update_subsurface_sync(&head).await?;
+    }
+
+    let summary = summary.lock().unwrap().clone();
 
-    Ok(())
+    debug!("Finished subsurface sync for {user_id}: {summary:?}");
+
+    Ok(summary)
+}
+
+async fn equipment_changed(
+    handle: &DbHandle,
+    dive_id: Uuid,
+    cylinders: Option<&[DiveCylinder]>,
+    weights: Option<&[DiveWeight]>,
+) -> Result<bool, Error> {
+    if let Some(cylinders) = cylinders {
+        if handle.dive_cylinders(dive_id).await? != cylinders {
+            return Ok(true);
+        }
+    }
+
+    if let Some(weights) = weights {
+        if handle.dive_weights(dive_id).await? != weights {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+async fn computers_changed(
+    handle: &DbHandle,
+    dive_id: Uuid,
+    profile: Option<&DiveProfile>,
+) -> Result<bool, Error> {
+    match profile {
+        Some(profile) if !profile.computers.is_empty() => {
+            Ok(handle.dive_computers(dive_id).await? != profile.computers)
+        }
+        _ => Ok(false),
+    }
 }
 
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
@@ -666,28 +1210,407 @@ where
     Ok(io::BufReader::new(file).lines())
 }
 
-fn clone_git(path: &Path, email: &str, password: &str) -> Result<(), git2::Error> {
-    // This has started failing with:
-    //    `an unknown git error occurred; code=NotFound (-3)`
-    // So we're using the git client for now...
+// Fetches into the cached clone at `path`, cloning it fresh if it's missing or points somewhere else
+fn update_git(
+    path: &Path,
+    url: &str,
+    branch: &str,
+    email: &str,
+    password: &str,
+    remote: &SubsurfaceRemote,
+) -> Result<(), Error> {
+    let mut fo = git2::FetchOptions::new();
+    fo.remote_callbacks(remote_callbacks(email, password, remote));
 
-    let mut callbacks = RemoteCallbacks::new();
+    let existing = git2::Repository::open(path).ok().filter(|repo| {
+        repo.find_remote("origin")
+            .is_ok_and(|origin| origin.url() == Some(url))
+    });
 
-    callbacks.credentials(|_, _, _| Cred::userpass_plaintext(email, password));
+    let repo = match existing {
+        Some(repo) => {
+            debug!("Fetching into existing clone at {}", path.display());
 
-    let mut fo = git2::FetchOptions::new();
-    fo.remote_callbacks(callbacks);
+            repo.find_remote("origin")?
+                .fetch::<&str>(&[], Some(&mut fo), None)?;
 
-    let mut builder = git2::build::RepoBuilder::new();
-    builder.fetch_options(fo);
+            repo
+        }
+        None => {
+            if path.exists() {
+                remove_dir_all(path)?;
+            }
+
+            create_dir_all(path)?;
+
+            debug!("Cloning {url} into {}", path.display());
 
-    let repo = builder.clone(
-        &format!("https://ssrf-cloud-eu.subsurface-divelog.org/git/{email}"),
-        path,
-    )?;
+            let mut builder = git2::build::RepoBuilder::new();
+            builder.fetch_options(fo);
 
-    repo.set_head(&format!("refs/remotes/origin/{email}"))?;
-    repo.checkout_head(None)?;
+            builder.clone(url, path)?
+        }
+    };
+
+    repo.set_head(&format!("refs/remotes/origin/{branch}"))?;
+    repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
 
     Ok(())
 }
+
+fn remote_callbacks<'a>(
+    email: &'a str,
+    password: &'a str,
+    remote: &'a SubsurfaceRemote,
+) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+
+    // libgit2 will keep asking for credentials if they are rejected, so only offer them once
+    let mut attempted = false;
+
+    match remote.auth.unwrap_or_default() {
+        SubsurfaceAuth::UserPass => {
+            callbacks.credentials(move |_, _, _| {
+                if attempted {
+                    return Err(git2::Error::from_str("Invalid username or password"));
+                }
+                attempted = true;
+
+                Cred::userpass_plaintext(email, password)
+            });
+        }
+        SubsurfaceAuth::SshKey => {
+            let key = remote.ssh_key.as_deref().unwrap_or_default();
+            let passphrase = remote.ssh_passphrase.as_deref();
+
+            callbacks.credentials(move |_, username, _| {
+                if attempted {
+                    return Err(git2::Error::from_str("SSH key was rejected"));
+                }
+                attempted = true;
+
+                Cred::ssh_key_from_memory(username.unwrap_or("git"), None, key, passphrase)
+            });
+        }
+        SubsurfaceAuth::None => (),
+    }
+
+    callbacks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn write_dive(path: &Path, folder: &str, number: u32) {
+        let dive_folder = path.join(folder);
+        fs::create_dir_all(&dive_folder).unwrap();
+        fs::write(
+            dive_folder.join(format!("Dive-{number}")),
+            "divesiteid 0000abcd\nnotes \"Hello\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dive_folder.join("Divecomputer"),
+            "model \"Test\"\n  0:10 1.5m\n  1:00 10.0m 20.0°C 200.0bar\n  2:00 0.0m\n",
+        )
+        .unwrap();
+    }
+
+    // Commits everything in the working directory onto the `test` branch
+    fn commit_all(path: &Path, message: &str) {
+        let repo = git2::Repository::open(path).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+
+        let parent = repo
+            .find_reference("refs/heads/test")
+            .ok()
+            .and_then(|reference| reference.peel_to_commit().ok());
+
+        repo.commit(
+            Some("refs/heads/test"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            parent.iter().collect::<Vec<_>>().as_slice(),
+        )
+        .unwrap();
+    }
+
+    // Builds a small subsurface style repository, with one site & one dive
+    fn fixture_repository(path: &Path) {
+        git2::Repository::init(path).unwrap();
+
+        fs::create_dir_all(path.join("01-Divesites")).unwrap();
+        fs::write(
+            path.join("01-Divesites/Site-0000abcd"),
+            "name \"Test Site\"\ngps -34.075304 137.544702\n",
+        )
+        .unwrap();
+
+        write_dive(path, "2023/01/14-Sat-09=30=00", 1);
+
+        commit_all(path, "Fixture");
+    }
+
+    #[test]
+    fn test_validate_remote() {
+        let remote = |url: &str| SubsurfaceRemote {
+            url: Some(url.into()),
+            ..Default::default()
+        };
+
+        for url in [
+            "https://example.com/divelog.git",
+            "ssh://git@example.com/divelog.git",
+            "git@example.com:divelog.git",
+        ] {
+            validate_remote(&remote(url), false).expect(url);
+        }
+
+        for url in [
+            "http://example.com/divelog.git",
+            "file:///srv/divelog",
+            "/srv/x@y:z",
+            "./x@y:z",
+            "x/y@z:divelog.git",
+            "@example.com:divelog.git",
+            "git@example.com:",
+            "/srv/divelog",
+        ] {
+            assert!(validate_remote(&remote(url), false).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_sync_local_remote() {
+        let origin = TempDir::new("subsurface_origin").unwrap();
+        let cache = TempDir::new("subsurface_cache").unwrap();
+        fixture_repository(origin.path());
+
+        let remote = SubsurfaceRemote {
+            url: Some(format!("file://{}", origin.path().display())),
+            branch: Some("test".into()),
+            auth: Some(SubsurfaceAuth::None),
+            ..Default::default()
+        };
+
+        validate_remote(&remote, true).expect("Local remotes are allowed");
+        assert!(validate_remote(&remote, false).is_err());
+
+        let user_id = Uuid::new_v4();
+
+        let repo = sync_repository(user_id, "test@example.com", "", &remote, cache.path(), None)
+            .expect("Syncs correctly");
+
+        assert_eq!(repo.sites.len(), 1);
+        assert_eq!(repo.dives.len(), 1);
+
+        let dive = &repo.dives[0];
+
+        assert_eq!(dive.description, "Hello");
+        assert_eq!(dive.depth, 10.0);
+        assert_eq!(dive.duration, 120);
+        assert_eq!(dive.dive_site_id, Some(repo.sites[0].id));
+        assert_eq!(repo.dive_metrics[&dive.id].len(), 3);
+        assert_eq!(
+            repo.dive_computers[&dive.id],
+            vec![DiveComputer {
+                computer: 0,
+                model: Some("Test".into()),
+                serial: None,
+            }]
+        );
+
+        // A second sync should only pick up the newly added dive
+        write_dive(origin.path(), "2023/02/03-Fri-11=00=00", 2);
+        commit_all(origin.path(), "Second dive");
+
+        let incremental = sync_repository(
+            user_id,
+            "test@example.com",
+            "",
+            &remote,
+            cache.path(),
+            repo.head.as_ref(),
+        )
+        .expect("Syncs incrementally");
+
+        assert_eq!(incremental.dives.len(), 1);
+        assert_ne!(incremental.dives[0].id, dive.id);
+        assert_eq!(incremental.sites.len(), 1);
+        assert_ne!(
+            incremental.head.map(|head| head.commit_id),
+            repo.head.map(|head| head.commit_id)
+        );
+    }
+
+    #[test]
+    fn test_parse_dive_details() {
+        let dir = TempDir::new("subsurface_dive").unwrap();
+        let folder = dir.path().join("14-Sat-09=30=00");
+        fs::create_dir_all(&folder).unwrap();
+
+        fs::write(
+            folder.join("Dive-7"),
+            r#"rating 4
+visibility 3
+tags "boat", "night"
+buddy "Jane Doe"
+divemaster "Bob"
+cylinder vol=11.1l workpressure=207.0bar description="AL80" o2=32.0% start=200.0bar end=50.0bar
+cylinder vol=7.0l description="Stage \"1\"" o2=50.0%
+weightsystem weight=6.0kg description="belt"
+notes "Saw a seal"
+"#,
+        )
+        .unwrap();
+
+        fs::write(
+            folder.join("Divecomputer"),
+            "model \"Perdix\"\nkeyvalue \"Serial\" \"1234\"\n  0:10 1.5m 200.0bar 210.0bar:1\n  1:00 12.0m 190.0bar 205.0bar:1 po2=1.2bar\n",
+        )
+        .unwrap();
+
+        fs::write(
+            folder.join("Divecomputer-001"),
+            "model \"Teric\"\ndeviceid 0a0b0c0d\n  0:10 1.6m\n  1:00 12.4m\n  1:10 0.0m\n",
+        )
+        .unwrap();
+
+        let dive = parse_dive(2023, 1, folder).expect("Parses dive");
+
+        assert_eq!(dive.dive_number, 7);
+        assert_eq!(dive.rating, 4);
+        assert_eq!(dive.visibility, 3);
+        assert_eq!(dive.tags, vec!["boat", "night"]);
+        assert_eq!(dive.buddy, "Jane Doe");
+        assert_eq!(dive.divemaster, "Bob");
+        assert_eq!(dive.description, "Saw a seal");
+
+        assert_eq!(
+            dive.cylinders,
+            vec![
+                DiveCylinder {
+                    description: Some("AL80".into()),
+                    size: Some(11.1),
+                    working_pressure: Some(207.0),
+                    start_pressure: Some(200.0),
+                    end_pressure: Some(50.0),
+                    o2: Some(32.0),
+                    he: None,
+                },
+                DiveCylinder {
+                    description: Some("Stage \"1\"".into()),
+                    size: Some(7.0),
+                    o2: Some(50.0),
+                    ..Default::default()
+                }
+            ]
+        );
+
+        assert_eq!(
+            dive.weights,
+            vec![DiveWeight {
+                description: Some("belt".into()),
+                weight: 6.0,
+            }]
+        );
+
+        // The summary comes from the primary computer, with the rest kept alongside
+        assert_eq!(dive.depth, 12.0);
+        assert_eq!(dive.duration, 60);
+        assert_eq!(dive.metrics.len(), 5);
+        assert_eq!(dive.metrics[1].pressure, Some(190.0));
+        assert_eq!(
+            dive.metrics
+                .iter()
+                .filter(|metric| metric.computer == 1)
+                .count(),
+            3
+        );
+
+        assert_eq!(
+            dive.computers,
+            vec![
+                DiveComputer {
+                    computer: 0,
+                    model: Some("Perdix".into()),
+                    serial: Some("1234".into()),
+                },
+                DiveComputer {
+                    computer: 1,
+                    model: Some("Teric".into()),
+                    serial: None,
+                }
+            ]
+        );
+
+        assert_eq!(
+            dive.pressures,
+            vec![
+                SensorPressure {
+                    computer: 0,
+                    time: 10,
+                    sensor: 1,
+                    pressure: 210.0,
+                },
+                SensorPressure {
+                    computer: 0,
+                    time: 60,
+                    sensor: 1,
+                    pressure: 205.0,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_metric_details() {
+        let mut cells = HashMap::new();
+        let mut gas_changes = HashMap::new();
+
+        let (first, _) = parse_metric(
+            "  1:00 12.0m ndl=99:00 cns=3% po2=1.3bar sensor1=1.0bar sensor2=1.5bar heartbeat=80"
+                .into(),
+            0,
+            None,
+            &mut cells,
+            &mut gas_changes,
+        )
+        .expect("Parses metric");
+
+        assert_eq!(first.ndl, Some(5940));
+        assert_eq!(first.cns, Some(3.0));
+        assert_eq!(first.setpoint, Some(1.3));
+        assert_eq!(first.ppo2, Some(1.25));
+        assert_eq!(first.heart_rate, Some(80));
+        assert_eq!(first.tts, None);
+
+        // Only the values that changed are written, with the rest carried over
+        let (second, _) = parse_metric(
+            "  1:10 12.5m tts=2:30 sensor2=2.0bar".into(),
+            0,
+            Some(&first),
+            &mut cells,
+            &mut gas_changes,
+        )
+        .expect("Parses metric");
+
+        assert_eq!(second.ndl, Some(5940));
+        assert_eq!(second.tts, Some(150));
+        assert_eq!(second.setpoint, Some(1.3));
+        assert_eq!(second.ppo2, Some(1.5));
+        assert_eq!(second.heart_rate, Some(80));
+    }
+}
@@ -0,0 +1,238 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Error};
+use futures::{stream, Stream, StreamExt};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, Semaphore,
+};
+use tracing::*;
+use uuid::Uuid;
+
+use crate::{
+    db::DbHandle,
+    graphql::WebContext,
+    schema::{SubsurfaceRemote, SyncJob, SyncState},
+    subsurface,
+};
+
+/// Everything needed to run a sync.  This is only ever kept in memory so passwords & keys are never persisted
+pub struct SyncRequest {
+    pub job_id: Uuid,
+    pub user_id: Uuid,
+    pub email: String,
+    pub password: String,
+    pub remote: SubsurfaceRemote,
+}
+
+/// Queues subsurface syncs to run in the background, and broadcasts their progress
+pub struct SyncQueue {
+    /// Identifies the jobs queued by this process, so that they can be kept alive
+    worker: Uuid,
+    sender: mpsc::UnboundedSender<SyncRequest>,
+    updates: broadcast::Sender<SyncJob>,
+}
+
+impl SyncQueue {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<SyncRequest>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (updates, _) = broadcast::channel(64);
+
+        (
+            SyncQueue {
+                worker: Uuid::new_v4(),
+                sender,
+                updates,
+            },
+            receiver,
+        )
+    }
+
+    /// Queues a sync for the user, or returns the sync that is already in progress
+    pub async fn enqueue(
+        &self,
+        handle: &DbHandle,
+        user_id: Uuid,
+        email: String,
+        password: String,
+        remote: SubsurfaceRemote,
+    ) -> Result<SyncJob, Error> {
+        let Some(job) = handle.create_sync_job(user_id, self.worker).await? else {
+            return handle
+                .sync_jobs(user_id, None)
+                .await?
+                .into_iter()
+                .find(|job| !job.state.is_finished())
+                .ok_or_else(|| anyhow!("A sync has just finished, please try again"));
+        };
+
+        self.sender
+            .send(SyncRequest {
+                job_id: job.id,
+                user_id,
+                email,
+                password,
+                remote,
+            })
+            .map_err(|_| anyhow!("Sync worker is not running"))?;
+
+        Ok(job)
+    }
+
+    /// Streams the current state of a job, then each update until it has finished
+    pub async fn subscribe(
+        &self,
+        handle: &DbHandle,
+        user_id: Uuid,
+        id: Uuid,
+    ) -> Result<impl Stream<Item = SyncJob>, Error> {
+        // Subscribe before loading the job so that no updates are missed in between
+        let receiver = self.updates.subscribe();
+
+        let job = handle
+            .sync_jobs(user_id, Some(id))
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Sync job not found"))?;
+
+        let finished = job.state.is_finished();
+
+        let updates = stream::unfold(
+            (receiver, finished),
+            move |(mut receiver, finished)| async move {
+                if finished {
+                    return None;
+                }
+
+                loop {
+                    match receiver.recv().await {
+                        Ok(job) if job.id == id => {
+                            let finished = job.state.is_finished();
+                            return Some((job, (receiver, finished)));
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            },
+        );
+
+        Ok(stream::once(async move { job }).chain(updates))
+    }
+
+    fn publish(&self, job: SyncJob) {
+        // Errors only mean there are no subscribers
+        self.updates.send(job).ok();
+    }
+}
+
+/// Keeps this process's jobs alive, and fails the jobs of processes that have stopped
+pub async fn run_heartbeat(web: Arc<WebContext>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = web.handle.touch_sync_jobs(web.sync_queue.worker).await {
+            error!("Could not update sync jobs: {err:?}");
+        }
+
+        match web.handle.fail_stale_sync_jobs().await {
+            Ok(0) => (),
+            Ok(stale) => warn!("Marked {stale} interrupted sync jobs as failed"),
+            Err(err) => error!("Could not fail interrupted sync jobs: {err:?}"),
+        }
+    }
+}
+
+/// How many syncs can be cloning & importing at once
+const MAX_CONCURRENT_SYNCS: usize = 4;
+
+/// Runs each queued sync in its own task, so a slow remote doesn't hold up everyone else.  Two syncs never share a cached clone, as a user can only have one unfinished job
+pub async fn run_worker(mut receiver: mpsc::UnboundedReceiver<SyncRequest>, web: Arc<WebContext>) {
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_SYNCS));
+
+    while let Some(request) = receiver.recv().await {
+        let Ok(permit) = permits.clone().acquire_owned().await else {
+            break;
+        };
+
+        let web = web.clone();
+
+        tokio::spawn(async move {
+            let job_id = request.job_id;
+
+            if let Err(err) = run_job(request, &web).await {
+                error!("Sync job {job_id} failed: {err:?}");
+
+                match web
+                    .handle
+                    .update_sync_job(job_id, SyncState::Failed, Some(&err.to_string()))
+                    .await
+                {
+                    Ok(job) => web.sync_queue.publish(job),
+                    Err(err) => error!("Could not mark sync job {job_id} as failed: {err:?}"),
+                }
+            }
+
+            drop(permit);
+        });
+    }
+}
+
+async fn run_job(request: SyncRequest, web: &WebContext) -> Result<(), Error> {
+    let SyncRequest {
+        job_id,
+        user_id,
+        email,
+        password,
+        remote,
+    } = request;
+
+    let handle = &web.handle;
+    let queue = &web.sync_queue;
+
+    queue.publish(
+        handle
+            .update_sync_job(job_id, SyncState::Cloning, None)
+            .await?,
+    );
+
+    let subsurface_dir = web.subsurface_dir.clone();
+
+    let (checkout, email) = tokio::task::spawn_blocking(move || {
+        let checkout =
+            subsurface::fetch_repository(user_id, &email, &password, &remote, &subsurface_dir)?;
+
+        Ok::<_, Error>((checkout, email))
+    })
+    .await??;
+
+    queue.publish(
+        handle
+            .update_sync_job(job_id, SyncState::Parsing, None)
+            .await?,
+    );
+
+    let previous = handle.subsurface_sync(user_id).await?;
+
+    let mut repo = tokio::task::spawn_blocking(move || {
+        subsurface::parse_repository(user_id, &email, &checkout, previous.as_ref())
+    })
+    .await??;
+
+    queue.publish(
+        handle
+            .update_sync_job(job_id, SyncState::Importing, None)
+            .await?,
+    );
+
+    subsurface::resolve_existing_sites(user_id, &mut repo, handle.clone()).await?;
+
+    let summary = subsurface::import_repository(user_id, repo, handle.clone()).await?;
+
+    queue.publish(handle.finish_sync_job(job_id, &summary).await?);
+
+    Ok(())
+}
@@ -57,6 +57,19 @@ pub struct Token {
     pub user_id: Option<Uuid>,
 }
 
+impl Token {
+    pub fn decrypt<I: AsRef<[u8]>>(web: &WebContext, token: I) -> Token {
+        Token {
+            user_id: web
+                .cipher
+                .base64_decrypt(token)
+                .ok()
+                .and_then(|val| TryInto::try_into(val).ok())
+                .map(Uuid::from_bytes),
+        }
+    }
+}
+
 impl FromRequest for Token {
     type Error = ActixError;
     type Future = Ready<Result<Token, ActixError>>;
@@ -64,14 +77,7 @@ impl FromRequest for Token {
     fn from_request(req: &HttpRequest, _pl: &mut Payload) -> Self::Future {
         if let Some(web) = req.app_data::<web::Data<WebContext>>() {
             if let Some(header) = req.headers().get("divedb-token") {
-                return ready(Ok(Token {
-                    user_id: web
-                        .cipher
-                        .base64_decrypt(header)
-                        .ok()
-                        .and_then(|val| TryInto::try_into(val).ok())
-                        .map(Uuid::from_bytes),
-                }));
+                return ready(Ok(Token::decrypt(web, header)));
             }
         }
 
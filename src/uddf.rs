@@ -0,0 +1,437 @@
+use actix_multipart::Multipart;
+use actix_web::{error::ErrorBadRequest, web, Error as ActixError, HttpResponse};
+use anyhow::{anyhow, Error};
+use chrono::prelude::*;
+use futures::TryStreamExt;
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+use tracing::*;
+use uuid::Uuid;
+
+use crate::{
+    db::DbHandle,
+    graphql::WebContext,
+    log_error,
+    schema::{Difficulty, Dive, DiveMetric, DiveSite, ImportSummary},
+    subsurface::{self, Repository, DIVE_NAMESPACE, SITE_NAMESPACE},
+    token::Token,
+};
+
+// UDDF files are plain XML, but can get quite large with long profiles
+const MAX_UDDF_SIZE: usize = 52428800;
+
+// UDDF stores everything in SI units
+const KELVIN_OFFSET: f32 = 273.15;
+const PASCALS_PER_BAR: f32 = 100000.0;
+
+pub async fn import_uddf(
+    mut payload: Multipart,
+    context: web::Data<WebContext>,
+    token: Token,
+) -> Result<HttpResponse, ActixError> {
+    let user_id = match token.user_id {
+        Some(id) => id,
+        None => return Ok(HttpResponse::Unauthorized().body("Unauthorized")),
+    };
+
+    // Ids are namespaced by email so that another diver's copy of the same file doesn't clash
+    let email = context
+        .handle
+        .user_details(user_id)
+        .await
+        .map_err(log_error)?
+        .email
+        .unwrap_or_else(|| user_id.to_string());
+
+    let mut imported = ImportSummary::default();
+
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let mut buffer = Vec::new();
+
+        while let Ok(Some(chunk)) = field.try_next().await {
+            buffer.extend_from_slice(&chunk);
+
+            if buffer.len() > MAX_UDDF_SIZE {
+                return Ok(HttpResponse::PayloadTooLarge().body("UDDF file is too large"));
+            }
+        }
+
+        let contents = String::from_utf8(buffer)
+            .map_err(|_| ErrorBadRequest("UDDF file is not valid UTF-8"))?;
+
+        let email = email.clone();
+
+        let repo = tokio::task::spawn_blocking(move || parse_uddf(user_id, &email, &contents))
+            .await
+            .map_err(log_error)?
+            .map_err(|err| ErrorBadRequest(format!("Could not parse UDDF file: {err}")))?;
+
+        imported += import_repository(user_id, repo, context.handle.clone())
+            .await
+            .map_err(log_error)?;
+    }
+
+    Ok(HttpResponse::Ok().json(&imported))
+}
+
+/// Parses a UDDF document into a `Repository` ready to be imported.
+///
+/// Ids are derived from the user's email & the site name/dive date, so importing the same file twice will update rather than duplicate.
+/// These don't match the ids of dives synced from subsurface, which are found by [`skip_existing_dives`] instead.
+pub fn parse_uddf(user_id: Uuid, email: &str, contents: &str) -> Result<Repository, Error> {
+    let site_ns = Uuid::new_v5(&SITE_NAMESPACE, email.as_bytes());
+    let dive_ns = Uuid::new_v5(&DIVE_NAMESPACE, email.as_bytes());
+
+    let doc = Document::parse(contents)?;
+
+    let root = doc.root_element();
+
+    if root.tag_name().name() != "uddf" {
+        return Err(anyhow!("Root element is not `uddf`"));
+    }
+
+    let mut mixes = HashMap::new();
+
+    if let Some(definitions) = child(root, "gasdefinitions") {
+        for mix in children(definitions, "mix") {
+            if let Some(id) = mix.attribute("id") {
+                let o2 = child_value::<f32>(mix, "o2").unwrap_or(0.21) * 100.;
+                let he = child_value::<f32>(mix, "he").unwrap_or(0.) * 100.;
+
+                mixes.insert(id, (o2, he));
+            }
+        }
+    }
+
+    let deco_model = child(root, "decomodel")
+        .and_then(|val| child(val, "buehlmann"))
+        .and_then(|model| {
+            let low = child_value::<f32>(model, "gradientfactorlow")?;
+            let high = child_value::<f32>(model, "gradientfactorhigh")?;
+
+            Some(format!(
+                "GF {:.0}/{:.0}",
+                gradient_factor(low),
+                gradient_factor(high)
+            ))
+        });
+
+    let mut sites = HashMap::new();
+
+    if let Some(dive_sites) = child(root, "divesite") {
+        for site in children(dive_sites, "site") {
+            let (Some(id), Some(name)) = (site.attribute("id"), child_text(site, "name")) else {
+                continue;
+            };
+
+            let geography = child(site, "geography");
+
+            let lat = geography
+                .and_then(|val| child_value(val, "latitude"))
+                .unwrap_or(0.0);
+            let lon = geography
+                .and_then(|val| child_value(val, "longitude"))
+                .unwrap_or(0.0);
+
+            let dive_site = DiveSite {
+                id: Uuid::new_v5(&site_ns, name.as_bytes()),
+                user_id: Some(user_id),
+                name: name.to_string(),
+                description: child(site, "notes").map(notes).unwrap_or_default(),
+                access: String::default(),
+                difficulty: Difficulty::OW,
+                depth: 0.0,
+                lat,
+                lon,
+                published: false,
+                photo_id: None,
+                date: Local::now(),
+                slug: None,
+            };
+
+            sites.insert(id, dive_site);
+        }
+    }
+
+    let mut dives = Vec::new();
+    let mut dive_metrics = HashMap::new();
+
+    for dive in root
+        .descendants()
+        .filter(|node| node.tag_name().name() == "dive")
+    {
+        let before = child(dive, "informationbeforedive");
+        let after = child(dive, "informationafterdive");
+
+        let date_str = before
+            .and_then(|val| child_text(val, "datetime"))
+            .ok_or_else(|| anyhow!("Dive is missing a `datetime`"))?;
+
+        let date = parse_datetime(date_str)?;
+
+        debug!("UDDF dive: {}", date);
+
+        let dive_site_id = before
+            .into_iter()
+            .flat_map(|val| children(val, "link"))
+            .filter_map(|link| link.attribute("ref"))
+            .find_map(|id| sites.get(id))
+            .map(|site| site.id);
+
+        let metrics = child(dive, "samples")
+            .map(|samples| parse_samples(samples, &mixes))
+            .unwrap_or_default();
+
+        let depth = after
+            .and_then(|val| child_value::<f32>(val, "greatestdepth"))
+            .unwrap_or_else(|| metrics.iter().map(|val| val.depth).fold(0.0, f32::max));
+
+        let duration = after
+            .and_then(|val| child_value::<f32>(val, "diveduration"))
+            .map(|val| val as i32)
+            .or_else(|| metrics.last().map(|val| val.time))
+            .unwrap_or_default();
+
+        let description = after
+            .and_then(|val| child(val, "notes"))
+            .map(notes)
+            .unwrap_or_default();
+
+        let dive_id = Uuid::new_v5(&dive_ns, &date.timestamp().to_be_bytes());
+
+        dive_metrics.insert(dive_id, metrics);
+
+        dives.push(Dive {
+            id: dive_id,
+            user_id,
+            date,
+            duration,
+            depth,
+            dive_site_id,
+            dive_number: 0,
+            description,
+            published: false,
+            deco_model: deco_model.clone(),
+            buddy: String::new(),
+            divemaster: String::new(),
+            tags: Vec::new(),
+            visibility: 0,
+            rating: 0,
+        });
+    }
+
+    Ok(Repository {
+        sites: sites.into_values().collect(),
+        dives,
+        dive_metrics,
+        ..Default::default()
+    })
+}
+
+/// Imports a parsed UDDF file, matching its sites & dives up with the ones already logged
+pub async fn import_repository(
+    user_id: Uuid,
+    mut repo: Repository,
+    db: DbHandle,
+) -> Result<ImportSummary, Error> {
+    subsurface::resolve_existing_sites(user_id, &mut repo, db.clone()).await?;
+
+    let dates = repo.dives.iter().map(|dive| dive.date).collect::<Vec<_>>();
+
+    let existing = db.dives_at(user_id, &dates).await?;
+
+    let skipped = skip_existing_dives(&mut repo, &existing);
+
+    let mut summary = subsurface::import_repository(user_id, repo, db).await?;
+
+    summary.dives_skipped += skipped;
+
+    Ok(summary)
+}
+
+/// Leaves out dives already logged at the same time from somewhere else, such as a subsurface sync or an export from here, returning how many were left out.
+///
+/// UDDF doesn't carry everything those have, like the other dive computers, sensor pressures or deco model, so updating them would lose it.
+/// Dives from an earlier import of a UDDF file share its ids, and are updated as usual.  Sites are matched by name with [`subsurface::resolve_existing_sites`].
+pub fn skip_existing_dives(repo: &mut Repository, existing: &[Dive]) -> i32 {
+    let mut skipped = 0;
+
+    repo.dives.retain(|dive| {
+        let Some(existing) = existing
+            .iter()
+            .find(|val| val.date == dive.date && val.id != dive.id)
+        else {
+            return true;
+        };
+
+        debug!(
+            "Dive already logged:{} for {}, skipping",
+            existing.id, dive.date
+        );
+
+        repo.dive_metrics.remove(&dive.id);
+        skipped += 1;
+
+        false
+    });
+
+    skipped
+}
+
+fn parse_samples(samples: Node, mixes: &HashMap<&str, (f32, f32)>) -> Vec<DiveMetric> {
+    let mut metrics = Vec::new();
+
+    for waypoint in children(samples, "waypoint") {
+        let (Some(time), Some(depth)) = (
+            child_value::<f32>(waypoint, "divetime"),
+            child_value::<f32>(waypoint, "depth"),
+        ) else {
+            continue;
+        };
+
+        let (o2, he) = child(waypoint, "switchmix")
+            .and_then(|val| val.attribute("ref"))
+            .and_then(|id| mixes.get(id))
+            .map(|(o2, he)| (Some(*o2), Some(*he)))
+            .unwrap_or((None, None));
+
+        metrics.push(DiveMetric {
+            time: time as i32,
+            depth,
+            pressure: child_value::<f32>(waypoint, "tankpressure").map(|val| val / PASCALS_PER_BAR),
+            temperature: child_value::<f32>(waypoint, "temperature").map(|val| val - KELVIN_OFFSET),
+            o2,
+            he,
+            ..Default::default()
+        });
+    }
+
+    metrics
+}
+
+fn parse_datetime(input: &str) -> Result<DateTime<Local>, Error> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(input) {
+        return Ok(date.with_timezone(&Local));
+    }
+
+    // Most dive computers don't record a timezone, so treat it as local time like subsurface does
+    let naive = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+        .ok_or_else(|| anyhow!("Could not parse date: {input}"))?;
+
+    Local
+        .from_local_datetime(&naive)
+        .single()
+        .ok_or_else(|| anyhow!("Could not get local date from naive date"))
+}
+
+// Gradient factors are fractions in UDDF, but some tools write them as percentages
+fn gradient_factor(val: f32) -> f32 {
+    if val <= 1.0 {
+        val * 100.
+    } else {
+        val
+    }
+}
+
+fn notes(node: Node) -> String {
+    children(node, "para")
+        .filter_map(|para| para.text())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &'static str) -> Option<Node<'a, 'input>> {
+    children(node, name).next()
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |val| val.is_element() && val.tag_name().name() == name)
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &'static str) -> Option<&'a str> {
+    child(node, name).and_then(|val| val.text()).map(str::trim)
+}
+
+fn child_value<T: std::str::FromStr>(node: Node, name: &'static str) -> Option<T> {
+    child_text(node, name).and_then(|val| val.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UDDF: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<uddf xmlns="http://www.streit.cc/uddf/3.2/" version="3.2.0">
+  <gasdefinitions>
+    <mix id="ean50"><name>EAN50</name><o2>0.50</o2><he>0.0</he></mix>
+  </gasdefinitions>
+  <divesite>
+    <site id="site_1">
+      <name>Port Hughes</name>
+      <geography><latitude>-34.075304</latitude><longitude>137.544702</longitude></geography>
+    </site>
+  </divesite>
+  <profiledata>
+    <repetitiongroup id="rg1">
+      <dive id="dive_1">
+        <informationbeforedive>
+          <link ref="site_1"/>
+          <datetime>2023-01-14T09:30:00</datetime>
+        </informationbeforedive>
+        <samples>
+          <waypoint><divetime>0</divetime><depth>0.0</depth><tankpressure>20000000</tankpressure></waypoint>
+          <waypoint><divetime>60</divetime><depth>12.5</depth><temperature>291.15</temperature></waypoint>
+          <waypoint><divetime>120</divetime><depth>6.0</depth><switchmix ref="ean50"/></waypoint>
+        </samples>
+        <informationafterdive>
+          <greatestdepth>12.5</greatestdepth>
+          <diveduration>120</diveduration>
+          <notes><para>Nice dive</para></notes>
+        </informationafterdive>
+      </dive>
+    </repetitiongroup>
+  </profiledata>
+</uddf>"#;
+
+    #[test]
+    fn test_parse_uddf() {
+        let user_id = Uuid::new_v4();
+
+        let repo = parse_uddf(user_id, "test@example.com", UDDF).expect("Parses correctly");
+
+        assert_eq!(repo.sites.len(), 1);
+        assert_eq!(repo.dives.len(), 1);
+
+        let dive = &repo.dives[0];
+
+        assert_eq!(dive.depth, 12.5);
+        assert_eq!(dive.duration, 120);
+        assert_eq!(dive.description, "Nice dive");
+        assert_eq!(dive.dive_site_id, Some(repo.sites[0].id));
+
+        let metrics = &repo.dive_metrics[&dive.id];
+
+        assert_eq!(metrics.len(), 3);
+        assert_eq!(metrics[0].pressure, Some(200.0));
+        assert!((metrics[1].temperature.unwrap() - 18.0).abs() < 0.01);
+        assert_eq!(metrics[2].o2, Some(50.0));
+
+        // Importing again should produce the same ids
+        let repo_again = parse_uddf(user_id, "test@example.com", UDDF).expect("Parses correctly");
+
+        assert_eq!(repo_again.dives[0].id, dive.id);
+        assert_eq!(repo_again.sites[0].id, repo.sites[0].id);
+
+        // Ids are namespaced by email, so another diver's copy of the file doesn't clash
+        let other = parse_uddf(user_id, "other@example.com", UDDF).expect("Parses correctly");
+
+        assert_ne!(other.dives[0].id, dive.id);
+    }
+}